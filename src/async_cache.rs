@@ -0,0 +1,451 @@
+//! An async front end to [`Cache`] that works with any executor.
+use crate::sync::Recover;
+use crate::Cache;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Wraps a [`Cache`] for use from async code.
+///
+/// The cache's locks only guard short, in-memory critical sections and are
+/// never held across an `.await`, so awaiting these methods does not stall
+/// other tasks on the executor. Loaders passed to the `get_or_insert_with`
+/// methods run on the calling task, outside of any lock, and concurrent
+/// callers for the same key share a single load.
+///
+/// The wrapper needs no particular runtime and dereferences to the
+/// underlying [`Cache`] for everything else.
+pub struct AsyncCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    cache: Cache<K, V>,
+    /// Loads in progress, shared by every caller asking for the same key
+    in_flight: Arc<Mutex<HashMap<K, Arc<Flight<V>>>>>,
+    /// Keys locked through [`AsyncCache::key_lock`], with the tasks waiting for them
+    key_locks: Arc<Mutex<HashMap<K, Vec<Waker>>>>,
+}
+
+impl<K, V> Clone for AsyncCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    fn clone(&self) -> Self {
+        AsyncCache {
+            cache: self.cache.clone(),
+            in_flight: self.in_flight.clone(),
+            key_locks: self.key_locks.clone(),
+        }
+    }
+}
+
+impl<K, V> AsyncCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    pub fn new(cache: Cache<K, V>) -> Self {
+        AsyncCache {
+            cache,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+            key_locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub async fn get(&self, key: &K) -> Option<V> {
+        self.cache.get(key)
+    }
+
+    pub async fn insert(&self, key: K, value: V) {
+        self.cache.insert(key, value)
+    }
+
+    pub async fn remove(&self, key: &K) -> Option<V> {
+        self.cache.remove(key)
+    }
+
+    /// Returns the cached value for `key`, or awaits `init` and caches its output.
+    ///
+    /// Concurrent callers for the same key are coalesced: only the first runs
+    /// its `init`, and the others wait for and share its output. If that
+    /// caller is cancelled, one of the waiting callers takes over.
+    pub async fn get_or_insert_with<F, Fut>(&self, key: K, init: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let result: Result<V, std::convert::Infallible> = self
+            .try_get_or_insert_with(key, || async { Ok(init().await) })
+            .await;
+        match result {
+            Ok(value) => value,
+        }
+    }
+
+    /// Like [`AsyncCache::get_or_insert_with`], but nothing is cached if `init` fails.
+    ///
+    /// The error is returned only to the caller whose `init` failed; a
+    /// waiting caller then runs its own `init`.
+    pub async fn try_get_or_insert_with<F, Fut, E>(&self, key: K, init: F) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        loop {
+            let flight = {
+                let mut in_flight = self.in_flight.lock().recover();
+                // Checked under the lock, as a finished load is cached before its flight is removed
+                if let Some(value) = self.cache.get(&key) {
+                    return Ok(value);
+                }
+                match in_flight.get(&key) {
+                    Some(flight) => Err(flight.clone()),
+                    None => {
+                        let flight = Arc::new(Flight::new());
+                        in_flight.insert(key.clone(), flight.clone());
+                        Ok(flight)
+                    }
+                }
+            };
+
+            match flight {
+                Err(flight) => {
+                    if let Some(value) = (Wait { flight }).await {
+                        return Ok(value);
+                    }
+                    // The load failed or was cancelled, so try again
+                }
+                Ok(flight) => {
+                    let mut leader = Leader {
+                        in_flight: &self.in_flight,
+                        key: key.clone(),
+                        flight,
+                        value: None,
+                    };
+                    let value = init().await?;
+                    self.cache.insert(key, value.clone());
+                    leader.value = Some(value.clone());
+                    return Ok(value);
+                }
+            }
+        }
+    }
+
+    /// Runs `listener` for each entry evicted from the cache, handing the
+    /// future it returns to `spawn`.
+    ///
+    /// `spawn` should start the future on an executor, e.g.
+    /// `|task| { tokio::spawn(task); }`, so that slow work such as writing
+    /// the entry back to a remote store does not hold up the insert that
+    /// caused the eviction. See [`Cache::on_evict`] for which entries count.
+    pub fn on_evict<F, Fut, S>(&self, spawn: S, listener: F)
+    where
+        F: Fn(K, V) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()>,
+        S: Fn(Fut) + Send + Sync + 'static,
+    {
+        self.cache
+            .on_evict(move |key, value| spawn(listener(key, value)));
+    }
+
+    /// Locks `key` until the returned guard is dropped.
+    ///
+    /// The lock is advisory: it only excludes other callers of this method,
+    /// not plain reads and writes of the cache. Use it to make multi-step
+    /// updates of one key atomic without serializing updates of other keys.
+    pub async fn key_lock(&self, key: &K) -> KeyGuard<K> {
+        Acquire {
+            locks: self.key_locks.clone(),
+            key: key.clone(),
+        }
+        .await
+    }
+}
+
+/// Waits until a key can be locked.
+struct Acquire<K> {
+    locks: Arc<Mutex<HashMap<K, Vec<Waker>>>>,
+    key: K,
+}
+
+impl<K: Eq + Hash + Clone> Future for Acquire<K> {
+    type Output = KeyGuard<K>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut locks = self.locks.lock().recover();
+        match locks.get_mut(&self.key) {
+            Some(waiters) => {
+                if !waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                    waiters.push(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+            None => {
+                locks.insert(self.key.clone(), Vec::new());
+                Poll::Ready(KeyGuard {
+                    locks: self.locks.clone(),
+                    key: self.key.clone(),
+                })
+            }
+        }
+    }
+}
+
+/// Holds the lock on a key taken with [`AsyncCache::key_lock`], releasing it
+/// when dropped.
+pub struct KeyGuard<K: Eq + Hash> {
+    locks: Arc<Mutex<HashMap<K, Vec<Waker>>>>,
+    key: K,
+}
+
+impl<K: Eq + Hash> KeyGuard<K> {
+    /// The locked key.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: Eq + Hash> Drop for KeyGuard<K> {
+    fn drop(&mut self) {
+        let waiters = self.locks.lock().recover().remove(&self.key);
+        // Every waiter retries, so one that was cancelled cannot hold up the rest
+        for waker in waiters.into_iter().flatten() {
+            waker.wake();
+        }
+    }
+}
+
+/// A load of one key's value that other callers can wait for.
+struct Flight<V> {
+    state: Mutex<FlightState<V>>,
+}
+
+struct FlightState<V> {
+    /// `Some(None)` once the load failed or was cancelled
+    result: Option<Option<V>>,
+    wakers: Vec<Waker>,
+}
+
+impl<V: Clone> Flight<V> {
+    fn new() -> Self {
+        Flight {
+            state: Mutex::new(FlightState {
+                result: None,
+                wakers: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Waits for a flight to finish, returning its value if it succeeded.
+struct Wait<V> {
+    flight: Arc<Flight<V>>,
+}
+
+impl<V: Clone> Future for Wait<V> {
+    type Output = Option<V>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.flight.state.lock().recover();
+        if let Some(result) = &state.result {
+            return Poll::Ready(result.clone());
+        }
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Owns a flight while its load runs. Dropping it publishes `value`, or
+/// abandons the flight if the load failed or was cancelled.
+struct Leader<'a, K: Eq + Hash, V: Clone> {
+    in_flight: &'a Mutex<HashMap<K, Arc<Flight<V>>>>,
+    key: K,
+    flight: Arc<Flight<V>>,
+    value: Option<V>,
+}
+
+impl<K: Eq + Hash, V: Clone> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        self.in_flight.lock().recover().remove(&self.key);
+        let wakers = {
+            let mut state = self.flight.state.lock().recover();
+            state.result = Some(self.value.take());
+            std::mem::take(&mut state.wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl<K, V> Deref for AsyncCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    type Target = Cache<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cache
+    }
+}
+
+/// Runs a future to completion on the current thread.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{block_on, AsyncCache};
+    use crate::Cache;
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let cache = AsyncCache::new(Cache::new_lru(10));
+        block_on(async {
+            let value = cache
+                .get_or_insert_with(1, || async { "one".to_string() })
+                .await;
+            assert_eq!(value, "one");
+            let value = cache
+                .get_or_insert_with(1, || async { unreachable!() })
+                .await;
+            assert_eq!(value, "one");
+
+            let failed: Result<String, &str> = cache
+                .try_get_or_insert_with(2, || async { Err("unavailable") })
+                .await;
+            assert!(failed.is_err());
+            assert_eq!(cache.get(&2).await, None);
+            assert_eq!(cache.remove(&1).await, Some("one".to_string()));
+        });
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_loads_are_coalesced() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::{Arc, Barrier};
+        use std::time::Duration;
+
+        let cache = AsyncCache::new(Cache::new_unbounded());
+        let loads = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, loads, barrier) = (cache.clone(), loads.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    block_on(cache.get_or_insert_with(1, || async {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(50));
+                        42
+                    }))
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 42);
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_failed_load_is_retried_by_waiter() {
+        let cache: AsyncCache<i32, i32> = AsyncCache::new(Cache::new_unbounded());
+        let failed: Result<i32, &str> =
+            block_on(cache.try_get_or_insert_with(1, || async { Err("down") }));
+        assert_eq!(failed, Err("down"));
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+        assert_eq!(block_on(cache.get_or_insert_with(1, || async { 1 })), 1);
+    }
+
+    #[test]
+    fn test_on_evict() {
+        use std::sync::mpsc;
+
+        let cache = AsyncCache::new(Cache::new_lru(1));
+        let (tasks, queued) = mpsc::channel();
+        let (evicted, received) = mpsc::channel();
+        cache.on_evict(
+            move |task| tasks.send(task).unwrap(),
+            move |key, value| {
+                let evicted = evicted.clone();
+                async move { evicted.send((key, value)).unwrap() }
+            },
+        );
+        block_on(async {
+            cache.insert(1, 10).await;
+            cache.insert(2, 20).await;
+        });
+
+        // Nothing runs until the executor polls the task
+        assert!(received.try_recv().is_err());
+        block_on(queued.try_recv().unwrap());
+        assert_eq!(received.try_recv(), Ok((1, 10)));
+    }
+
+    #[test]
+    fn test_key_lock() {
+        let cache = AsyncCache::new(Cache::new_unbounded());
+        block_on(cache.insert(1, 0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    block_on(async {
+                        for _ in 0..100 {
+                            let _guard = cache.key_lock(&1).await;
+                            let value = cache.get(&1).await.unwrap();
+                            std::thread::yield_now();
+                            cache.insert(1, value + 1).await;
+                        }
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(block_on(cache.get(&1)), Some(400));
+
+        let guard = block_on(cache.key_lock(&1));
+        assert_eq!(guard.key(), &1);
+        // Other keys stay available
+        drop(block_on(cache.key_lock(&2)));
+        drop(guard);
+        assert!(cache.key_locks.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+//! An audit log of the changes made to a cache.
+use crate::json;
+use crate::report::report;
+use crate::sync::Recover;
+use crate::CacheEvent;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A change recorded by the audit log, see [`crate::Cache::enable_audit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutation<K> {
+    pub time: SystemTime,
+    /// The name of the thread that made the change, if it has one
+    pub thread: Option<String>,
+    /// What changed, and why
+    pub event: CacheEvent<K>,
+}
+
+/// Keeps the latest mutations in memory and appends every one to a file.
+pub(crate) struct AuditLog<K> {
+    recent: Mutex<VecDeque<Mutation<K>>>,
+    capacity: usize,
+    file: Option<Mutex<File>>,
+}
+
+impl<K: Clone + Serialize> AuditLog<K> {
+    /// Creates a log keeping `capacity` mutations in memory and appending
+    /// to `file_name`, if given.
+    pub(crate) fn open(capacity: usize, file_name: Option<&str>) -> Result<Self> {
+        let file = match file_name {
+            Some(file_name) => Some(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(file_name)?,
+            )),
+            None => None,
+        };
+        Ok(AuditLog {
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            file,
+        })
+    }
+
+    pub(crate) fn record(&self, event: &CacheEvent<K>) {
+        let mutation = Mutation {
+            time: SystemTime::now(),
+            thread: std::thread::current().name().map(str::to_string),
+            event: event.clone(),
+        };
+        if let Some(file) = &self.file {
+            let line = format_line(&mutation);
+            if let Err(e) = file.lock().recover().write_all(line.as_bytes()) {
+                report(anyhow!(e).context("Failed to write audit log"));
+            }
+        }
+        if self.capacity > 0 {
+            let mut recent = self.recent.lock().recover();
+            if recent.len() == self.capacity {
+                recent.pop_front();
+            }
+            recent.push_back(mutation);
+        }
+    }
+
+    /// Returns the last `n` mutations kept in memory, oldest first.
+    pub(crate) fn recent(&self, n: usize) -> Vec<Mutation<K>> {
+        let recent = self.recent.lock().recover();
+        recent
+            .iter()
+            .skip(recent.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Formats a mutation as a line of the audit file: the time in
+/// milliseconds since the Unix epoch, the thread, the kind of change, and
+/// the key as JSON.
+fn format_line<K: Serialize>(mutation: &Mutation<K>) -> String {
+    let millis = mutation
+        .time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    let thread = mutation.thread.as_deref().unwrap_or("-");
+    let (kind, key) = match &mutation.event {
+        CacheEvent::Inserted(key) => ("inserted", Some(key)),
+        CacheEvent::Updated(key) => ("updated", Some(key)),
+        CacheEvent::Removed(key) => ("removed", Some(key)),
+        CacheEvent::Evicted(key) => ("evicted", Some(key)),
+        CacheEvent::Cleared => ("cleared", None),
+    };
+    match key {
+        Some(key) => format!(
+            "{} {} {} {}\n",
+            millis,
+            thread,
+            kind,
+            json::to_compact_string(key)
+        ),
+        None => format!("{} {} {}\n", millis, thread, kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cache, CacheEvent};
+
+    #[test]
+    fn test_audit_log() {
+        let path = std::env::temp_dir().join("minne_audit.log");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let cache = Cache::new_lru(1);
+        cache.enable_audit(2, Some(path)).unwrap();
+        assert!(cache.enable_audit(2, None).is_err());
+        cache.insert((1, "a".to_string()), 1);
+        cache.insert((2, "b".to_string()), 2);
+        std::thread::Builder::new()
+            .name("cleaner".to_string())
+            .spawn({
+                let cache = cache.clone();
+                move || cache.remove(&(2, "b".to_string()))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        let recent = cache.recent_mutations(10);
+        assert_eq!(
+            recent.iter().map(|m| m.event.clone()).collect::<Vec<_>>(),
+            vec![
+                CacheEvent::Evicted((1, "a".to_string())),
+                CacheEvent::Removed((2, "b".to_string())),
+            ]
+        );
+        assert_eq!(recent[1].thread.as_deref(), Some("cleaner"));
+
+        let log = std::fs::read_to_string(path).unwrap();
+        let lines: Vec<_> = log
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].ends_with(" inserted [1,\"a\"]"));
+        assert_eq!(lines[3], "cleaner removed [2,\"b\"]");
+        std::fs::remove_file(path).unwrap();
+    }
+}
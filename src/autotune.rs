@@ -0,0 +1,181 @@
+//! Tuning the capacity of an LRU cache toward a target hit ratio, see
+//! [`crate::Cache::autotune`].
+use crate::sync::Recover;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The bounds and goal of [`crate::Cache::autotune`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Autotune {
+    pub min_capacity: usize,
+    pub max_capacity: usize,
+    /// The share of lookups that should be hits
+    pub target_hit_ratio: f64,
+    /// How many entries the capacity changes by at a time
+    pub step: usize,
+    /// The least share of lookups a step must gain, or keep, to be worth
+    /// its entries
+    pub min_gain: f64,
+}
+
+impl Autotune {
+    /// Tunes between `min_capacity` and `max_capacity` in steps of a
+    /// twentieth of the range, with steps worth a hundredth of the lookups.
+    pub fn new(min_capacity: usize, max_capacity: usize, target_hit_ratio: f64) -> Self {
+        Autotune {
+            min_capacity,
+            max_capacity,
+            target_hit_ratio,
+            step: (max_capacity.saturating_sub(min_capacity) / 20).max(1),
+            min_gain: 0.01,
+        }
+    }
+
+    /// Returns the capacity to move to from `capacity`, given what a
+    /// period's lookups say.
+    ///
+    /// The cache grows while the hit ratio is under the target and the
+    /// ghost hits say a step more would gain enough, and shrinks while the
+    /// hits in its last step are too few to matter, or would leave it at
+    /// the target without them.
+    fn next(&self, capacity: usize, lookups: u64, hits: u64, ghost: u64, tail: u64) -> usize {
+        if lookups == 0 {
+            return capacity;
+        }
+        let share = |count: u64| count as f64 / lookups as f64;
+        let (ratio, gain, loss) = (share(hits), share(ghost), share(tail));
+        if ratio < self.target_hit_ratio && gain >= self.min_gain && capacity < self.max_capacity {
+            (capacity + self.step).min(self.max_capacity)
+        } else if capacity > self.min_capacity
+            && (loss < self.min_gain || ratio - loss >= self.target_hit_ratio)
+        {
+            capacity.saturating_sub(self.step).max(self.min_capacity)
+        } else {
+            capacity
+        }
+    }
+}
+
+/// Lookup counts of an autotuned cache, with the recently evicted keys, or
+/// ghosts, whose misses would have been hits with a step more capacity.
+pub(crate) struct Ghosts {
+    hasher: RandomState,
+    /// Hashes of the keys evicted last, oldest first, with a set to find them
+    evicted: Mutex<(VecDeque<u64>, HashSet<u64>)>,
+    /// How many ghosts are kept, and how long the tail of the cache is
+    step: AtomicUsize,
+    lookups: AtomicU64,
+    hits: AtomicU64,
+    /// Misses of keys still remembered as ghosts
+    ghost_hits: AtomicU64,
+    /// Hits of entries among the `step` least recently used
+    tail_hits: AtomicU64,
+}
+
+impl Ghosts {
+    pub(crate) fn new() -> Self {
+        Ghosts {
+            hasher: RandomState::new(),
+            evicted: Mutex::new((VecDeque::new(), HashSet::new())),
+            step: AtomicUsize::new(0),
+            lookups: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            ghost_hits: AtomicU64::new(0),
+            tail_hits: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn evicted<K: Hash>(&self, key: &K) {
+        let step = self.step.load(Ordering::Relaxed);
+        let (order, set) = &mut *self.evicted.lock().recover();
+        let hash = self.hasher.hash_one(key);
+        if set.insert(hash) {
+            order.push_back(hash);
+        }
+        while order.len() > step {
+            if let Some(oldest) = order.pop_front() {
+                set.remove(&oldest);
+            }
+        }
+    }
+
+    /// Counts a hit of the entry `position` entries from the least
+    /// recently used end, if it was known.
+    pub(crate) fn hit(&self, position: Option<usize>) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        if position.is_some_and(|position| position < self.step.load(Ordering::Relaxed)) {
+            self.tail_hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn miss<K: Hash>(&self, key: &K) {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        let hash = self.hasher.hash_one(key);
+        let (order, set) = &mut *self.evicted.lock().recover();
+        if set.remove(&hash) {
+            order.retain(|ghost| *ghost != hash);
+            self.ghost_hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the capacity `config` moves to from `capacity`, starting the
+    /// counts over for the next period.
+    pub(crate) fn tune(&self, config: &Autotune, capacity: usize) -> usize {
+        self.step.store(config.step, Ordering::Relaxed);
+        config.next(
+            capacity,
+            self.lookups.swap(0, Ordering::Relaxed),
+            self.hits.swap(0, Ordering::Relaxed),
+            self.ghost_hits.swap(0, Ordering::Relaxed),
+            self.tail_hits.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Autotune, Ghosts};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_next() {
+        let config = Autotune::new(100, 300, 0.8);
+        assert_eq!(config.step, 10);
+        // Under target with ghosts to gain: grow
+        assert_eq!(config.next(200, 100, 50, 10, 5), 210);
+        assert_eq!(config.next(295, 100, 50, 10, 5), 300);
+        // Under target, nothing to gain and a busy tail: stay
+        assert_eq!(config.next(200, 100, 50, 0, 5), 200);
+        // A dead tail, or one the target does without: shrink
+        assert_eq!(config.next(200, 100, 50, 0, 0), 190);
+        assert_eq!(config.next(200, 100, 95, 0, 5), 190);
+        assert_eq!(config.next(105, 100, 95, 0, 5), 100);
+        assert_eq!(config.next(100, 100, 95, 0, 5), 100);
+        // No lookups say nothing
+        assert_eq!(config.next(200, 0, 0, 0, 0), 200);
+    }
+
+    #[test]
+    fn test_ghosts() {
+        let ghosts = Ghosts::new();
+        let config = Autotune::new(0, 40, 1.0);
+        ghosts.tune(&config, 10);
+        for key in 0..3 {
+            ghosts.evicted(&key);
+        }
+        // Only the last two evicted are remembered
+        ghosts.miss(&0);
+        ghosts.miss(&2);
+        ghosts.miss(&2);
+        ghosts.hit(Some(1));
+        ghosts.hit(Some(5));
+        assert_eq!(ghosts.ghost_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(ghosts.tail_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(ghosts.tune(&config, 10), 12);
+        assert_eq!(ghosts.tune(&config, 12), 12);
+    }
+}
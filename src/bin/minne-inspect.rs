@@ -0,0 +1,99 @@
+//! Prints what a cache file holds, for finding out why a snapshot fails to load.
+//!
+//! Usage: `minne-inspect FILE [--key-type TYPE] [--value-type TYPE] [--samples N]`
+//!
+//! Given `--key-type`, the first keys are printed, and given `--value-type`
+//! as well, the first entries. Types are one of `String`, `Vec<u8>`, `u32`,
+//! `u64`, `i32` and `i64`.
+use anyhow::{bail, Context, Result};
+use minne::inspect::{self, TypeHint};
+use std::time::UNIX_EPOCH;
+
+fn main() -> Result<()> {
+    let mut file_name = None;
+    let mut key_type = None;
+    let mut value_type = None;
+    let mut samples = 10;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key-type" => {
+                key_type = Some(
+                    args.next()
+                        .context("--key-type needs a type")?
+                        .parse::<TypeHint>()?,
+                )
+            }
+            "--value-type" => {
+                value_type = Some(
+                    args.next()
+                        .context("--value-type needs a type")?
+                        .parse::<TypeHint>()?,
+                )
+            }
+            "--samples" => {
+                let value = args.next().context("--samples needs a number")?;
+                samples = value.parse::<usize>().context("Invalid --samples")?;
+            }
+            _ if arg.starts_with("--") => bail!("Unknown argument '{}'", arg),
+            _ if file_name.is_none() => file_name = Some(arg),
+            _ => bail!("Only one file can be inspected at a time"),
+        }
+    }
+    let file_name = file_name
+        .context("Usage: minne-inspect FILE [--key-type TYPE] [--value-type TYPE] [--samples N]")?;
+
+    let inspection = inspect::inspect(&file_name)?;
+    println!("file: {} ({} bytes)", file_name, inspection.file_size);
+    match inspection.version {
+        Some(version) => println!("version: {}", version),
+        None => println!("version: 0 (no header)"),
+    }
+    if let Some((key_hash, value_hash)) = inspection.type_hashes {
+        println!("key type hash: {:016x}", key_hash);
+        println!("value type hash: {:016x}", value_hash);
+    }
+    if let Some(metadata) = &inspection.metadata {
+        println!("policy: {:?}", metadata.policy);
+        if let Some(capacity) = metadata.capacity {
+            println!("capacity: {}", capacity);
+        }
+        println!(
+            "hits: {} misses: {} evictions: {}",
+            metadata.hits, metadata.misses, metadata.evictions
+        );
+        let seconds = |time: std::time::SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs())
+        };
+        println!("created: {} (unix seconds)", seconds(metadata.created));
+        println!("written: {} (unix seconds)", seconds(metadata.written));
+    }
+    println!("entries: {}", inspection.entries);
+    println!("entry bytes: {}", inspection.entry_bytes);
+    if let Some(problem) = &inspection.problem {
+        println!("problem: {}", problem);
+    }
+
+    if let Some(key_type) = key_type {
+        if let Some(value_type) = value_type {
+            if inspection.matches(key_type, value_type) == Some(false) {
+                println!("warning: the file was written for other types than the hints");
+            }
+        }
+        let sampled = inspection.sample(key_type, value_type, samples);
+        match sampled {
+            Ok(entries) => {
+                for (key, value) in entries {
+                    match value {
+                        Some(value) => println!("  {} => {}", key, value),
+                        None => println!("  {}", key),
+                    }
+                }
+            }
+            Err(e) => println!("entries could not be decoded as the hinted types: {}", e),
+        }
+    }
+    Ok(())
+}
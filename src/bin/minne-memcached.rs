@@ -0,0 +1,65 @@
+//! Serves a cache over the memcached text protocol.
+//!
+//! Usage: `minne-memcached [--listen ADDR] [--memory BYTES] [--capacity ENTRIES]
+//! [--max-item-size BYTES] [--max-connections N] [--timeout SECONDS]`
+//!
+//! The cache holds keys and values of up to `--memory` bytes, 64 MiB by
+//! default as in memcached, or up to `--capacity` entries if given instead.
+//! Values larger than `--max-item-size`, 1 MiB by default, are refused.
+//! At most `--max-connections` clients, 1024 by default, are served at
+//! once, and a client that stalls for `--timeout` seconds, 60 by default,
+//! is disconnected; 0 waits on clients forever.
+use anyhow::{bail, Context, Result};
+use minne::memcached::ServerLimits;
+use minne::Cache;
+use std::net::TcpListener;
+use std::time::Duration;
+
+fn main() -> Result<()> {
+    let mut listen = "127.0.0.1:11211".to_string();
+    let mut memory = 64 * 1024 * 1024;
+    let mut capacity = None;
+    let mut limits = ServerLimits::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().context("--listen needs an address")?,
+            "--memory" => {
+                let value = args.next().context("--memory needs a number")?;
+                memory = value.parse::<usize>().context("Invalid --memory")?;
+            }
+            "--capacity" => {
+                let value = args.next().context("--capacity needs a number")?;
+                capacity = Some(value.parse::<usize>().context("Invalid --capacity")?);
+            }
+            "--max-item-size" => {
+                let value = args.next().context("--max-item-size needs a number")?;
+                limits.max_item_size = value.parse::<usize>().context("Invalid --max-item-size")?;
+            }
+            "--max-connections" => {
+                let value = args.next().context("--max-connections needs a number")?;
+                limits.max_connections = value
+                    .parse::<usize>()
+                    .context("Invalid --max-connections")?;
+            }
+            "--timeout" => {
+                let value = args.next().context("--timeout needs a number")?;
+                let seconds = value.parse::<u64>().context("Invalid --timeout")?;
+                limits.timeout = (seconds > 0).then(|| Duration::from_secs(seconds));
+            }
+            _ => bail!("Unknown argument '{}'", arg),
+        }
+    }
+
+    let cache = match capacity {
+        Some(capacity) => Cache::new_lru(capacity),
+        None => Cache::new_weighted(memory, |key: &Vec<u8>, value: &Vec<u8>| {
+            u32::try_from(key.len() + value.len()).unwrap_or(u32::MAX)
+        }),
+    };
+    minne::set_error_hook(|e| eprintln!("{:#}", e));
+    let listener = TcpListener::bind(&listen)?;
+    println!("Listening on {}", listen);
+    minne::memcached::serve_with(cache, listener, limits)
+}
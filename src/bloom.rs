@@ -0,0 +1,95 @@
+//! A counting bloom filter of the keys in a cache, for answering lookups of
+//! absent keys without touching the cache.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The number of counters each key is counted in.
+const HASHES: u64 = 4;
+
+/// Counters per expected key, for about 1% false positives.
+const COUNTERS_PER_KEY: usize = 10;
+
+/// Counts each key in `HASHES` counters, so a key is absent if any of its
+/// counters is zero. Keys sharing counters make other keys look present,
+/// never absent.
+///
+/// Counters stick once they saturate, as the keys counted in them are no
+/// longer known, which only adds false positives.
+pub(crate) struct Bloom {
+    counters: Vec<AtomicU8>,
+}
+
+impl Bloom {
+    /// Creates a filter sized for `expected` keys.
+    pub(crate) fn new(expected: usize) -> Self {
+        let len = (expected.max(1) * COUNTERS_PER_KEY).max(1024);
+        Bloom {
+            counters: (0..len).map(|_| AtomicU8::new(0)).collect(),
+        }
+    }
+
+    /// Counts a key entering the cache.
+    pub(crate) fn add<K: Hash>(&self, key: &K) {
+        for counter in self.counters(key) {
+            let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                count.checked_add(1)
+            });
+        }
+    }
+
+    /// Stops counting a key leaving the cache.
+    pub(crate) fn remove<K: Hash>(&self, key: &K) {
+        for counter in self.counters(key) {
+            let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count > 0 && count < u8::MAX).then(|| count - 1)
+            });
+        }
+    }
+
+    /// Returns whether `key` may be in the cache; if not, it certainly is not.
+    pub(crate) fn may_contain<K: Hash>(&self, key: &K) -> bool {
+        self.counters(key)
+            .all(|counter| counter.load(Ordering::Relaxed) > 0)
+    }
+
+    pub(crate) fn clear(&self) {
+        for counter in &self.counters {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the counters of `key`, picked by double hashing.
+    fn counters<K: Hash>(&self, key: &K) -> impl Iterator<Item = &AtomicU8> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (first, step) = (hash & u32::MAX as u64, (hash >> 32) | 1);
+        let len = self.counters.len() as u64;
+        (0..HASHES).map(move |i| &self.counters[((first + i * step) % len) as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bloom;
+
+    #[test]
+    fn test_bloom() {
+        let bloom = Bloom::new(100);
+        for key in 0..100 {
+            bloom.add(&key);
+        }
+        assert!((0..100).all(|key| bloom.may_contain(&key)));
+        let false_positives = (100..10_100).filter(|key| bloom.may_contain(key)).count();
+        assert!(false_positives < 500, "{} false positives", false_positives);
+
+        for key in 0..50 {
+            bloom.remove(&key);
+        }
+        assert!((50..100).all(|key| bloom.may_contain(&key)));
+        assert!((0..50).filter(|key| bloom.may_contain(key)).count() < 10);
+        bloom.clear();
+        assert!(!bloom.may_contain(&75));
+    }
+}
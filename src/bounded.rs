@@ -0,0 +1,158 @@
+//! A bounded cache that never evicts, for tables whose entries must not be
+//! dropped behind the caller's back.
+use crate::sync::Recover;
+use crate::Cache;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A cache of at most `capacity` entries that refuses new keys when full
+/// instead of evicting others, such as a table of in-flight requests.
+///
+/// [`BoundedCache::try_insert`] fails while the cache is full, and
+/// [`BoundedCache::insert`] waits until a removal makes room. Replacing the
+/// value of a key already cached always succeeds. Inserts hold a lock, so
+/// concurrent inserts cannot go over the capacity together.
+pub struct BoundedCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    cache: Cache<K, V>,
+    capacity: usize,
+    inserting: Mutex<()>,
+    /// Signalled whenever entries are removed
+    room: Condvar,
+}
+
+impl<K, V> BoundedCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    pub fn new(capacity: usize) -> Self {
+        BoundedCache {
+            cache: Cache::new_unbounded(),
+            capacity,
+            inserting: Mutex::new(()),
+            room: Condvar::new(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.cache.get(key)
+    }
+
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.cache.peek(key)
+    }
+
+    fn has_room(&self, key: &K) -> bool {
+        self.cache.len() < self.capacity || self.cache.peek(key).is_some()
+    }
+
+    /// Inserts the entry, or fails if the cache is full.
+    pub fn try_insert(&self, key: K, value: V) -> Result<()> {
+        let _inserting = self.inserting.lock().recover();
+        if !self.has_room(&key) {
+            return Err(anyhow!("The cache is full"));
+        }
+        self.cache.insert(key, value);
+        Ok(())
+    }
+
+    /// Inserts the entry, waiting for room if the cache is full.
+    pub fn insert(&self, key: K, value: V) {
+        let inserting = self.inserting.lock().recover();
+        let _inserting = self
+            .room
+            .wait_while(inserting, |_| !self.has_room(&key))
+            .recover();
+        self.cache.insert(key, value);
+    }
+
+    /// Inserts the entry, waiting up to `timeout` for room if the cache is
+    /// full, and fails if there is still none.
+    pub fn insert_timeout(&self, key: K, value: V, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut inserting = self.inserting.lock().recover();
+        while !self.has_room(&key) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(anyhow!("The cache is full"));
+            }
+            inserting = self.room.wait_timeout(inserting, left).recover().0;
+        }
+        self.cache.insert(key, value);
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let value = self.cache.remove(key);
+        if value.is_some() {
+            // Taking the lock orders this after the check of a waiting insert
+            drop(self.inserting.lock().recover());
+            self.room.notify_all();
+        }
+        value
+    }
+
+    pub fn clear(&self) {
+        self.cache.clear();
+        drop(self.inserting.lock().recover());
+        self.room.notify_all();
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns whether new keys would be refused.
+    pub fn is_full(&self) -> bool {
+        self.cache.len() >= self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BoundedCache;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bounded() {
+        let cache = Arc::new(BoundedCache::new(2));
+        cache.try_insert(1, 1).unwrap();
+        cache.try_insert(2, 2).unwrap();
+        assert!(cache.is_full());
+        assert!(cache.try_insert(3, 3).is_err());
+        assert!(cache
+            .insert_timeout(3, 3, Duration::from_millis(10))
+            .is_err());
+        // Replacing a value needs no room
+        cache.try_insert(1, 10).unwrap();
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.get(&2), Some(2));
+
+        let waiting = std::thread::spawn({
+            let cache = cache.clone();
+            move || cache.insert(3, 3)
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(cache.peek(&3), None);
+        cache.remove(&2);
+        waiting.join().unwrap();
+        assert_eq!(cache.get(&3), Some(3));
+        assert_eq!(cache.len(), 2);
+    }
+}
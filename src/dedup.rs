@@ -0,0 +1,170 @@
+//! Caches storing identical values once.
+use crate::{Cache, CacheStats};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Weak};
+
+/// The values in the cache by their hash, to find one equal to a new value.
+///
+/// Holding them weakly means a value is gone once no entry, or caller it
+/// was returned to, holds it, without counting the entries sharing it.
+struct Values<V> {
+    hasher: RandomState,
+    buckets: DashMap<u64, Vec<Weak<V>>>,
+}
+
+impl<V: Eq + Hash> Values<V> {
+    /// Returns the value equal to `value` if there is one, or `value` shared.
+    fn share(&self, value: V) -> Arc<V> {
+        let mut bucket = self
+            .buckets
+            .entry(self.hasher.hash_one(&value))
+            .or_default();
+        bucket.retain(|weak| weak.strong_count() > 0);
+        let mut equal = bucket.iter().filter_map(Weak::upgrade);
+        if let Some(shared) = equal.find(|shared| **shared == value) {
+            return shared;
+        }
+        let shared = Arc::new(value);
+        bucket.push(Arc::downgrade(&shared));
+        shared
+    }
+
+    /// Drops a value that left the cache, and its bucket if it is empty.
+    fn release(&self, value: Arc<V>) {
+        let hash = self.hasher.hash_one(&*value);
+        drop(value);
+        self.buckets.remove_if_mut(&hash, |_, bucket| {
+            bucket.retain(|weak| weak.strong_count() > 0);
+            bucket.is_empty()
+        });
+    }
+
+    fn distinct(&self) -> usize {
+        let live =
+            |bucket: &Vec<Weak<V>>| bucket.iter().filter(|weak| weak.strong_count() > 0).count();
+        self.buckets.iter().map(|bucket| live(bucket.value())).sum()
+    }
+}
+
+/// A cache that stores values equal to one already in it only once, for
+/// caches where many keys map to the same few large values.
+///
+/// Values are hashed on insert and, if an equal value is in the cache,
+/// the entry shares it instead, so lookups return the value behind an
+/// [`Arc`]. See [`DedupCache::dedup_ratio`] for how much is shared.
+///
+/// Values the cache behind it reads back from disk, from snapshots or a
+/// spill store, are not shared with equal ones.
+pub struct DedupCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Eq + Hash + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    cache: Cache<K, Arc<V>>,
+    values: Arc<Values<V>>,
+}
+
+impl<K, V> DedupCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Eq + Hash + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    /// Shares equal values between the entries of `cache`, which should be
+    /// new and empty, e.g. `Cache::new_lru(capacity)`.
+    pub fn new(cache: Cache<K, Arc<V>>) -> Self {
+        let values = Arc::new(Values {
+            hasher: RandomState::new(),
+            buckets: DashMap::new(),
+        });
+        let releasing = values.clone();
+        cache.on_removal(move |_, value, _| releasing.release(value));
+        DedupCache { cache, values }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.cache.insert(key, self.values.share(value));
+    }
+
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.cache.get(key)
+    }
+
+    pub fn peek(&self, key: &K) -> Option<Arc<V>> {
+        self.cache.peek(key)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<Arc<V>> {
+        self.cache.remove(key)
+    }
+
+    pub fn clear(&self) {
+        self.cache.clear();
+        self.values.buckets.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Returns how many distinct values are held, counting those still
+    /// held by callers they were returned to.
+    pub fn distinct_values(&self) -> usize {
+        self.values.distinct()
+    }
+
+    /// Returns how many entries there are per distinct value, 1.0 if none
+    /// are shared or the cache is empty.
+    pub fn dedup_ratio(&self) -> f64 {
+        match self.distinct_values() {
+            0 => 1.0,
+            distinct => (self.len() as f64 / distinct as f64).max(1.0),
+        }
+    }
+
+    /// Returns the cache behind the sharing, e.g. to subscribe to it.
+    pub fn cache(&self) -> &Cache<K, Arc<V>> {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DedupCache;
+    use crate::Cache;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_dedup() {
+        let cache = DedupCache::new(Cache::new_lru(4));
+        for key in 0..4 {
+            cache.insert(key, format!("blob {}", key % 2));
+        }
+        let (even, odd) = (cache.get(&0).unwrap(), cache.get(&1).unwrap());
+        assert!(Arc::ptr_eq(&even, &cache.get(&2).unwrap()));
+        assert!(Arc::ptr_eq(&odd, &cache.get(&3).unwrap()));
+        assert!(!Arc::ptr_eq(&even, &odd));
+        assert_eq!(cache.distinct_values(), 2);
+        assert_eq!(cache.dedup_ratio(), 2.0);
+
+        // Values leave with the last entry holding them
+        drop((even, odd));
+        cache.insert(1, "blob 0".to_string());
+        cache.insert(3, "blob 0".to_string());
+        assert_eq!(cache.distinct_values(), 1);
+        assert_eq!(cache.dedup_ratio(), 4.0);
+        cache.clear();
+        assert_eq!(cache.distinct_values(), 0);
+        assert_eq!(cache.dedup_ratio(), 1.0);
+    }
+}
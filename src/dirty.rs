@@ -0,0 +1,163 @@
+//! Tracking of the keys changed since the last snapshot, for incremental
+//! snapshots.
+//!
+//! An incremental snapshot is a full snapshot plus a delta log next to it,
+//! in the write-ahead log format, holding the changes made since.
+use crate::sync::Recover;
+use crate::wal::{self, Record};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hash;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The keys changed since the last call to [`DirtySet::take`].
+///
+/// Tracking is off until [`DirtySet::enable`] is called, so caches that never
+/// write incremental snapshots pay only for an atomic load per mutation.
+pub(crate) struct DirtySet<K> {
+    enabled: AtomicBool,
+    state: Mutex<Changes<K>>,
+}
+
+/// The changes collected since the last snapshot.
+pub(crate) struct Changes<K> {
+    /// Whether the cache was cleared; `keys` only holds later changes
+    pub(crate) cleared: bool,
+    pub(crate) keys: HashSet<K>,
+}
+
+impl<K: Eq + Hash + Clone> DirtySet<K> {
+    pub(crate) fn new() -> Self {
+        DirtySet {
+            enabled: AtomicBool::new(false),
+            state: Mutex::new(Changes {
+                cleared: false,
+                keys: HashSet::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    /// Starts tracking from an empty set of changes.
+    pub(crate) fn enable(&self) {
+        let mut state = self.state.lock().recover();
+        state.cleared = false;
+        state.keys.clear();
+        self.enabled.store(true, Ordering::Release);
+    }
+
+    /// Stops tracking, so the next snapshot has to be a full one.
+    pub(crate) fn disable(&self) {
+        self.enabled.store(false, Ordering::Release);
+        self.state.lock().recover().keys.clear();
+    }
+
+    /// Records a change to `key`. Must be called after the change is applied,
+    /// so a concurrent [`DirtySet::take`] cannot miss it.
+    pub(crate) fn mark(&self, key: &K) {
+        if self.is_enabled() {
+            self.state.lock().recover().keys.insert(key.clone());
+        }
+    }
+
+    pub(crate) fn mark_cleared(&self) {
+        if self.is_enabled() {
+            let mut state = self.state.lock().recover();
+            state.cleared = true;
+            state.keys.clear();
+        }
+    }
+
+    /// Returns the changes since the last call and starts collecting anew.
+    pub(crate) fn take(&self) -> Changes<K> {
+        let mut state = self.state.lock().recover();
+        Changes {
+            cleared: std::mem::take(&mut state.cleared),
+            keys: std::mem::take(&mut state.keys),
+        }
+    }
+}
+
+/// Returns the path of the delta log kept next to an incremental snapshot.
+pub(crate) fn delta_path(file_name: &str) -> String {
+    format!("{}.delta", file_name)
+}
+
+/// Writes an incremental snapshot to `file_name`.
+///
+/// The first call, and any call after the delta log has grown larger than the
+/// snapshot, writes a full snapshot with `write_full` and starts tracking.
+/// Otherwise the changed keys are appended to the delta log, with their
+/// current values found through `lookup`.
+pub(crate) fn write_incremental<K, V>(
+    file_name: &str,
+    dirty: &DirtySet<K>,
+    write_full: impl FnOnce() -> Result<()>,
+    lookup: impl Fn(&K) -> Option<V>,
+) -> Result<()>
+where
+    K: Eq + Hash + Clone + Serialize,
+    V: Serialize,
+{
+    let delta = delta_path(file_name);
+    let size = |path: &str| fs::metadata(path).map(|m| m.len()).ok();
+    let compact = match (size(file_name), size(&delta)) {
+        (None, _) => true,
+        (Some(base), Some(delta)) => delta > base,
+        (Some(_), None) => false,
+    };
+
+    if !dirty.is_enabled() || compact {
+        // Remove the delta first: a crash before the new snapshot is in place
+        // then leaves the older snapshot, which is consistent on its own
+        match fs::remove_file(&delta) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        // Changes made while writing are tracked, and replaying them is harmless
+        dirty.enable();
+        return write_full().inspect_err(|_| dirty.disable());
+    }
+
+    let changes = dirty.take();
+    let values: Vec<(K, Option<V>)> = changes
+        .keys
+        .into_iter()
+        .map(|key| {
+            let value = lookup(&key);
+            (key, value)
+        })
+        .collect();
+
+    let mut records = Vec::with_capacity(values.len() + 1);
+    if changes.cleared {
+        records.push(Record::Clear);
+    }
+    for (key, value) in &values {
+        records.push(match value {
+            Some(value) => Record::Insert(key, value),
+            None => Record::Remove(key),
+        });
+    }
+    if records.is_empty() {
+        return Ok(());
+    }
+    // The changes taken above would be lost, so fall back to a full snapshot next time
+    wal::append_records(&delta, &records).inspect_err(|_| dirty.disable())
+}
+
+/// Returns the records in the delta log of the incremental snapshot at `file_name`.
+pub(crate) fn read_delta<K, V>(file_name: &str) -> Result<Vec<Record<K, V>>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    wal::read_records(&delta_path(file_name))
+}
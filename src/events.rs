@@ -0,0 +1,574 @@
+//! Broadcasting of cache mutations to subscribers.
+use crate::report::report;
+use crate::sync::{isolate, Recover};
+use anyhow::{anyhow, Result};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock, RwLock, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Events a subscriber can fall behind by before the oldest are dropped.
+const CAPACITY: usize = 1024;
+
+/// What happens to events sent to a [`Receiver`] whose buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the oldest buffered event to make room
+    DropOldest,
+    /// Drop the event being sent
+    DropNewest,
+    /// Make the change to the cache wait until the receiver catches up
+    Block,
+}
+
+/// A change to the contents of a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheEvent<K> {
+    /// A key without a value was given one
+    Inserted(K),
+    /// A key's value was replaced
+    Updated(K),
+    /// A key was removed by the caller
+    Removed(K),
+    /// A key was evicted to make room for another
+    Evicted(K),
+    /// Every key was removed at once
+    Cleared,
+}
+
+impl<K> CacheEvent<K> {
+    /// Returns the key the event is about, or `None` for [`CacheEvent::Cleared`].
+    pub fn key(&self) -> Option<&K> {
+        match self {
+            CacheEvent::Inserted(key)
+            | CacheEvent::Updated(key)
+            | CacheEvent::Removed(key)
+            | CacheEvent::Evicted(key) => Some(key),
+            CacheEvent::Cleared => None,
+        }
+    }
+}
+
+/// Why an entry left the cache, as told to removal listeners.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum RemovalCause {
+    /// The entry was removed or cleared by the caller, invalidated, or
+    /// purged as idle
+    Explicit,
+    /// The entry's value was overwritten by an insert
+    Replaced,
+    /// The entry was evicted to stay within capacity
+    Evicted,
+}
+
+/// Identifies a hook or listener, to remove it again.
+pub(crate) type HookId = u64;
+
+static NEXT_HOOK: AtomicU64 = AtomicU64::new(0);
+
+fn next_hook() -> HookId {
+    NEXT_HOOK.fetch_add(1, Ordering::Relaxed)
+}
+
+type RemovalListener<K, V> = Box<dyn Fn(K, V, RemovalCause) + Send + Sync>;
+
+/// The removal listeners of a cache.
+pub(crate) struct Listeners<K, V> {
+    listeners: RwLock<Vec<(HookId, RemovalListener<K, V>)>>,
+}
+
+impl<K: Clone, V: Clone> Listeners<K, V> {
+    pub(crate) fn new() -> Self {
+        Listeners {
+            listeners: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn add(
+        &self,
+        listener: impl Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    ) -> HookId {
+        let id = next_hook();
+        self.listeners
+            .write()
+            .recover()
+            .push((id, Box::new(listener)));
+        id
+    }
+
+    pub(crate) fn remove(&self, id: HookId) {
+        self.listeners
+            .write()
+            .recover()
+            .retain(|(added, _)| *added != id);
+    }
+
+    /// Returns whether there are listeners, so removed values are worth keeping.
+    pub(crate) fn is_active(&self) -> bool {
+        !self.listeners.read().recover().is_empty()
+    }
+
+    pub(crate) fn notify(&self, key: K, value: V, cause: RemovalCause) {
+        for (_, listener) in self.listeners.read().recover().iter() {
+            isolate("Removal listener", || {
+                listener(key.clone(), value.clone(), cause)
+            });
+        }
+    }
+}
+
+type Hook<K, V> = Box<dyn Fn(&K, &V) + Send + Sync>;
+
+/// Callbacks run on an operation of a cache, such as every hit.
+pub(crate) struct Hooks<K, V> {
+    hooks: RwLock<Vec<(HookId, Hook<K, V>)>>,
+    /// Set once there are hooks, so operations without any skip the lock
+    active: AtomicBool,
+}
+
+impl<K, V> Hooks<K, V> {
+    pub(crate) fn new() -> Self {
+        Hooks {
+            hooks: RwLock::new(Vec::new()),
+            active: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn add(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) -> HookId {
+        let id = next_hook();
+        self.hooks.write().recover().push((id, Box::new(hook)));
+        self.active.store(true, Ordering::Release);
+        id
+    }
+
+    pub(crate) fn remove(&self, id: HookId) {
+        let mut hooks = self.hooks.write().recover();
+        hooks.retain(|(added, _)| *added != id);
+        self.active.store(!hooks.is_empty(), Ordering::Release);
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn call(&self, key: &K, value: &V) {
+        if self.is_active() {
+            for (_, hook) in self.hooks.read().recover().iter() {
+                isolate("Hook", || hook(key, value));
+            }
+        }
+    }
+}
+
+/// Takes batches of cache events elsewhere, e.g. to a Kafka topic or NATS subject.
+pub trait EventSink<K>: Send {
+    fn send(&mut self, batch: Vec<CacheEvent<K>>) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Hands each batch to the receiving end of a channel.
+impl<K: Send> EventSink<K> for mpsc::Sender<Vec<CacheEvent<K>>> {
+    async fn send(&mut self, batch: Vec<CacheEvent<K>>) -> Result<()> {
+        mpsc::Sender::send(self, batch).map_err(|_| anyhow!("Event channel is closed"))
+    }
+}
+
+/// Sends the events from `events` to `sink` in batches of up to `max_batch`,
+/// until the cache is dropped. See [`crate::Cache::forward_events`].
+pub(crate) async fn forward<K, S>(events: Receiver<CacheEvent<K>>, mut sink: S, max_batch: usize)
+where
+    S: EventSink<K>,
+{
+    while let Some(event) = events.recv_async().await {
+        let mut batch = vec![event];
+        while batch.len() < max_batch {
+            match events.try_recv() {
+                Some(event) => batch.push(event),
+                None => break,
+            }
+        }
+        if let Err(e) = sink.send(batch).await {
+            report(e.context("Failed to send cache events"));
+        }
+    }
+}
+
+/// Delivers each sent event to every live [`Receiver`].
+pub(crate) struct Broadcast<T> {
+    subscribers: Mutex<Vec<Weak<Channel<T>>>>,
+    /// Called with every event on the sending thread, e.g. to audit it
+    tap: OnceLock<Tap<T>>,
+    /// Whether anyone may be listening, so senders can skip building events
+    active: AtomicBool,
+}
+
+type Tap<T> = Box<dyn Fn(&T) + Send + Sync>;
+
+struct Channel<T> {
+    state: Mutex<ChannelState<T>>,
+    /// Signalled when an event is pushed or the channel is closed
+    ready: Condvar,
+    /// Signalled when an event is taken or the receiver is dropped
+    space: Condvar,
+    capacity: usize,
+    overflow: Overflow,
+    missed: AtomicU64,
+}
+
+struct ChannelState<T> {
+    queue: VecDeque<T>,
+    waker: Option<Waker>,
+    closed: bool,
+    /// Whether the receiver is gone, so a blocked sender must stop waiting
+    dropped: bool,
+}
+
+impl<T: Clone> Broadcast<T> {
+    pub(crate) fn new() -> Self {
+        Broadcast {
+            subscribers: Mutex::new(Vec::new()),
+            tap: OnceLock::new(),
+            active: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<T> {
+        self.subscribe_with(CAPACITY, Overflow::DropOldest)
+    }
+
+    pub(crate) fn subscribe_with(&self, capacity: usize, overflow: Overflow) -> Receiver<T> {
+        let channel = Arc::new(Channel {
+            state: Mutex::new(ChannelState {
+                queue: VecDeque::new(),
+                waker: None,
+                closed: false,
+                dropped: false,
+            }),
+            ready: Condvar::new(),
+            space: Condvar::new(),
+            capacity: capacity.max(1),
+            overflow,
+            missed: AtomicU64::new(0),
+        });
+        self.subscribers
+            .lock()
+            .recover()
+            .push(Arc::downgrade(&channel));
+        self.active.store(true, Ordering::Release);
+        Receiver { channel }
+    }
+
+    /// Makes every event sent from now on go to `tap` as well, before any
+    /// subscriber. There can be one tap only.
+    pub(crate) fn set_tap(&self, tap: impl Fn(&T) + Send + Sync + 'static) -> Result<()> {
+        self.tap
+            .set(Box::new(tap))
+            .map_err(|_| anyhow!("Events are already tapped"))?;
+        self.active.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns whether there may be subscribers to send to.
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Sends the event built by `event`, which is only called if there are subscribers.
+    pub(crate) fn send_with(&self, event: impl FnOnce() -> T) {
+        if !self.is_active() {
+            return;
+        }
+        let event = event();
+        if let Some(tap) = self.tap.get() {
+            tap(&event);
+        }
+        // Pushing outside the lock, as a blocking push can wait for long
+        let channels: Vec<_> = {
+            let mut subscribers = self.subscribers.lock().recover();
+            subscribers.retain(|channel| channel.strong_count() > 0);
+            if subscribers.is_empty() && self.tap.get().is_none() {
+                self.active.store(false, Ordering::Release);
+            }
+            subscribers.iter().filter_map(Weak::upgrade).collect()
+        };
+        for channel in channels {
+            channel.push(event.clone());
+        }
+    }
+}
+
+impl<T> Drop for Broadcast<T> {
+    fn drop(&mut self) {
+        for channel in self.subscribers.get_mut().recover().drain(..) {
+            if let Some(channel) = channel.upgrade() {
+                channel.close();
+            }
+        }
+    }
+}
+
+impl<T> Channel<T> {
+    fn push(&self, event: T) {
+        let waker = {
+            let mut state = self.state.lock().recover();
+            if state.queue.len() >= self.capacity {
+                match self.overflow {
+                    Overflow::DropOldest => {
+                        state.queue.pop_front();
+                        self.missed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Overflow::DropNewest => {
+                        self.missed.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    Overflow::Block => {
+                        state = self
+                            .space
+                            .wait_while(state, |s| s.queue.len() >= self.capacity && !s.dropped)
+                            .recover();
+                    }
+                }
+            }
+            if state.dropped {
+                return;
+            }
+            state.queue.push_back(event);
+            state.waker.take()
+        };
+        self.ready.notify_one();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn close(&self) {
+        let waker = {
+            let mut state = self.state.lock().recover();
+            state.closed = true;
+            state.waker.take()
+        };
+        self.ready.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Receives the events of the cache it was subscribed to.
+///
+/// Each receiver buffers a limited number of events, 1024 unless subscribed
+/// with [`crate::Cache::subscribe_with`]. A receiver that falls further
+/// behind loses the oldest ones, which [`Receiver::missed`] counts, unless
+/// its [`Overflow`] policy says otherwise.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> ChannelState<T> {
+    /// Takes the next event, making room for a blocked sender.
+    fn pop(&mut self, space: &Condvar) -> Option<T> {
+        let event = self.queue.pop_front();
+        if event.is_some() {
+            space.notify_one();
+        }
+        event
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Returns the next event if one is waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        let mut state = self.channel.state.lock().recover();
+        state.pop(&self.channel.space)
+    }
+
+    /// Blocks until the next event, or returns `None` once the cache is dropped.
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.channel.state.lock().recover();
+        loop {
+            if let Some(event) = state.pop(&self.channel.space) {
+                return Some(event);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.channel.ready.wait(state).recover();
+        }
+    }
+
+    /// Like [`Receiver::recv`], but gives up and returns `None` after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.channel.state.lock().recover();
+        loop {
+            if let Some(event) = state.pop(&self.channel.space) {
+                return Some(event);
+            }
+            let now = Instant::now();
+            if state.closed || now >= deadline {
+                return None;
+            }
+            state = self
+                .channel
+                .ready
+                .wait_timeout(state, deadline - now)
+                .recover()
+                .0;
+        }
+    }
+
+    /// Waits for the next event without blocking the thread, or returns
+    /// `None` once the cache is dropped.
+    pub fn recv_async(&self) -> impl Future<Output = Option<T>> + '_ {
+        RecvAsync { receiver: self }
+    }
+
+    /// Returns how many events were dropped because this receiver fell behind.
+    pub fn missed(&self) -> u64 {
+        self.channel.missed.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.state.lock().recover().dropped = true;
+        self.channel.space.notify_all();
+    }
+}
+
+struct RecvAsync<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Future for RecvAsync<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let channel = &self.receiver.channel;
+        let mut state = channel.state.lock().recover();
+        if let Some(event) = state.pop(&channel.space) {
+            return Poll::Ready(Some(event));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheEvent, Overflow, CAPACITY};
+    use crate::Cache;
+
+    #[test]
+    fn test_subscribe() {
+        let cache = Cache::new_lru(2);
+        let events = cache.subscribe();
+        cache.insert(1, "one".to_string());
+        cache.insert(1, "uno".to_string());
+        cache.insert(2, "two".to_string());
+        cache.insert(3, "three".to_string());
+        cache.remove(&2);
+        cache.remove(&2);
+        cache.clear();
+
+        let received: Vec<_> = std::iter::from_fn(|| events.try_recv()).collect();
+        assert_eq!(
+            received,
+            vec![
+                CacheEvent::Inserted(1),
+                CacheEvent::Updated(1),
+                CacheEvent::Inserted(2),
+                CacheEvent::Inserted(3),
+                CacheEvent::Evicted(1),
+                CacheEvent::Removed(2),
+                CacheEvent::Cleared,
+            ]
+        );
+
+        drop(cache);
+        assert_eq!(events.recv(), None);
+    }
+
+    #[test]
+    fn test_slow_subscriber_misses_oldest() {
+        let cache = Cache::new_unbounded();
+        let events = cache.subscribe();
+        for i in 0..CAPACITY + 10 {
+            cache.insert(i, i);
+        }
+        assert_eq!(events.missed(), 10);
+        assert_eq!(events.recv(), Some(CacheEvent::Inserted(10)));
+        assert_eq!(
+            crate::async_cache::block_on(events.recv_async()),
+            Some(CacheEvent::Inserted(11))
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        let cache = Cache::new_unbounded();
+        let newest_dropped = cache.subscribe_with(2, Overflow::DropNewest);
+        let blocking = cache.subscribe_with(2, Overflow::Block);
+
+        let writer = {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                for i in 0..4 {
+                    cache.insert(i, i);
+                }
+            })
+        };
+        // A blocked writer does not keep others from subscribing
+        while newest_dropped.missed() == 0 {
+            std::thread::yield_now();
+        }
+        let late = cache.subscribe();
+        let received: Vec<_> = (0..4).map(|_| blocking.recv().unwrap()).collect();
+        writer.join().unwrap();
+        assert_eq!(
+            received,
+            (0..4).map(CacheEvent::Inserted).collect::<Vec<_>>()
+        );
+        assert_eq!(blocking.missed(), 0);
+        assert_eq!(late.try_recv(), Some(CacheEvent::Inserted(3)));
+
+        // The first two were kept, and the rest dropped
+        assert_eq!(newest_dropped.missed(), 2);
+        assert_eq!(newest_dropped.try_recv(), Some(CacheEvent::Inserted(0)));
+        assert_eq!(newest_dropped.try_recv(), Some(CacheEvent::Inserted(1)));
+
+        // A dropped receiver no longer holds up changes
+        drop(blocking);
+        for i in 0..4 {
+            cache.insert(i, i);
+        }
+    }
+
+    #[test]
+    fn test_forward_events() {
+        let cache = Cache::new_unbounded();
+        let (sender, batches) = std::sync::mpsc::channel();
+        let forwarding = cache.forward_events(sender, 2);
+        for i in 0..3 {
+            cache.insert(i, i);
+        }
+        cache.remove(&0);
+        drop(cache);
+
+        crate::async_cache::block_on(forwarding);
+        let batches: Vec<_> = batches.try_iter().collect();
+        assert_eq!(
+            batches,
+            vec![
+                vec![CacheEvent::Inserted(0), CacheEvent::Inserted(1)],
+                vec![CacheEvent::Inserted(2), CacheEvent::Removed(0)],
+            ]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! A C interface to byte-keyed caches.
+//!
+//! Caches are passed around as opaque handles created by [`minne_lru_new`] or
+//! [`minne_unbounded_new`] and released with [`minne_free`]. Keys and values
+//! are byte strings, copied in and out of the cache.
+use crate::Cache;
+use std::ptr;
+use std::slice;
+
+/// An opaque handle to a cache.
+pub struct MinneCache(Cache<Vec<u8>, Vec<u8>>);
+
+/// Statistics of a cache, filled in by [`minne_stats`].
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MinneStats {
+    pub len: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+/// Creates an LRU cache holding at most `capacity` entries.
+#[no_mangle]
+pub extern "C" fn minne_lru_new(capacity: usize) -> *mut MinneCache {
+    Box::into_raw(Box::new(MinneCache(Cache::new_lru(capacity))))
+}
+
+/// Creates a cache without a size limit.
+#[no_mangle]
+pub extern "C" fn minne_unbounded_new() -> *mut MinneCache {
+    Box::into_raw(Box::new(MinneCache(Cache::new_unbounded())))
+}
+
+/// Releases a cache. Null is ignored.
+///
+/// # Safety
+/// `cache` must be null or a handle that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn minne_free(cache: *mut MinneCache) {
+    if !cache.is_null() {
+        drop(Box::from_raw(cache));
+    }
+}
+
+/// Inserts a copy of the value under a copy of the key.
+///
+/// # Safety
+/// `cache` must be a live handle, and `key` and `value` must point to
+/// `key_len` and `value_len` readable bytes (or be null with a length of 0).
+#[no_mangle]
+pub unsafe extern "C" fn minne_insert(
+    cache: *const MinneCache,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) {
+    let cache = &(*cache).0;
+    cache.insert(
+        bytes(key, key_len).to_vec(),
+        bytes(value, value_len).to_vec(),
+    );
+}
+
+/// Returns a copy of the value for the key, with its length in `value_len`,
+/// or null if there is none. The copy must be released with [`minne_bytes_free`].
+///
+/// # Safety
+/// `cache` must be a live handle, `key` must point to `key_len` readable
+/// bytes (or be null with a length of 0), and `value_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn minne_get(
+    cache: *const MinneCache,
+    key: *const u8,
+    key_len: usize,
+    value_len: *mut usize,
+) -> *mut u8 {
+    let cache = &(*cache).0;
+    match cache.get(&bytes(key, key_len).to_vec()) {
+        Some(value) => into_raw(value, value_len),
+        None => {
+            *value_len = 0;
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Removes the key, returning whether it was present.
+///
+/// # Safety
+/// `cache` must be a live handle, and `key` must point to `key_len`
+/// readable bytes (or be null with a length of 0).
+#[no_mangle]
+pub unsafe extern "C" fn minne_remove(
+    cache: *const MinneCache,
+    key: *const u8,
+    key_len: usize,
+) -> bool {
+    let cache = &(*cache).0;
+    cache.remove(&bytes(key, key_len).to_vec()).is_some()
+}
+
+/// Writes the statistics of the cache to `stats`.
+///
+/// # Safety
+/// `cache` must be a live handle and `stats` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn minne_stats(cache: *const MinneCache, stats: *mut MinneStats) {
+    let cache = &(*cache).0;
+    let current = cache.stats();
+    *stats = MinneStats {
+        len: current.len,
+        hits: current.hits,
+        misses: current.misses,
+        evictions: current.evictions,
+    };
+}
+
+/// Releases a value returned by [`minne_get`]. Null is ignored.
+///
+/// # Safety
+/// `value` must be null or a value returned by [`minne_get`] with its
+/// length, not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn minne_bytes_free(value: *mut u8, value_len: usize) {
+    if !value.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            value, value_len,
+        )));
+    }
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// Hands ownership of `value` to the caller, to be freed by `minne_bytes_free`.
+unsafe fn into_raw(value: Vec<u8>, len: *mut usize) -> *mut u8 {
+    // Boxing a slice drops any spare capacity, so the length is enough to free it
+    let value = value.into_boxed_slice();
+    *len = value.len();
+    Box::into_raw(value) as *mut u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let cache = minne_lru_new(1);
+            minne_insert(cache, b"a".as_ptr(), 1, b"one".as_ptr(), 3);
+            minne_insert(cache, b"b".as_ptr(), 1, ptr::null(), 0);
+
+            let mut len = 0;
+            assert!(minne_get(cache, b"a".as_ptr(), 1, &mut len).is_null());
+            let value = minne_get(cache, b"b".as_ptr(), 1, &mut len);
+            assert!(!value.is_null());
+            assert_eq!(len, 0);
+            minne_bytes_free(value, len);
+
+            let mut stats = MinneStats::default();
+            minne_stats(cache, &mut stats);
+            assert_eq!(
+                stats,
+                MinneStats {
+                    len: 1,
+                    hits: 1,
+                    misses: 1,
+                    evictions: 1
+                }
+            );
+            assert!(minne_remove(cache, b"b".as_ptr(), 1));
+            assert!(!minne_remove(cache, b"b".as_ptr(), 1));
+            minne_free(cache);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Approximate per-key access counts, for finding the hottest keys.
+use crate::sync::Recover;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The number of independent counter rows in the sketch.
+const DEPTH: usize = 4;
+
+/// Counts accesses in a count-min sketch and keeps the keys with the
+/// highest counts seen.
+///
+/// Counts may be overestimated when keys share counters, never
+/// underestimated. A key enters the top list only when it is accessed, so
+/// the list reflects the keys that were hot while they were being tracked.
+pub(crate) struct HotKeys<K> {
+    /// `DEPTH` rows of `width` counters
+    counters: Vec<AtomicU64>,
+    width: usize,
+    /// The keys with the highest counts, in no particular order
+    top: Mutex<Vec<(K, u64)>>,
+    tracked: usize,
+    /// The lowest count in a full top list; lower counts cannot enter it
+    floor: AtomicU64,
+}
+
+impl<K: Eq + Hash + Clone> HotKeys<K> {
+    /// Creates a sketch sized to keep the `tracked` hottest keys.
+    pub(crate) fn new(tracked: usize) -> Self {
+        let width = (tracked * 64).max(1024);
+        HotKeys {
+            counters: (0..DEPTH * width).map(|_| AtomicU64::new(0)).collect(),
+            width,
+            top: Mutex::new(Vec::with_capacity(tracked)),
+            tracked,
+            floor: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts an access to `key`.
+    pub(crate) fn record(&self, key: &K) {
+        let count = (0..DEPTH)
+            .map(|row| {
+                let counter = &self.counters[row * self.width + self.column(row, key)];
+                counter.fetch_add(1, Ordering::Relaxed) + 1
+            })
+            .min()
+            .unwrap_or(0);
+        if count <= self.floor.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut top = self.top.lock().recover();
+        if let Some(entry) = top.iter_mut().find(|(k, _)| k == key) {
+            entry.1 = entry.1.max(count);
+        } else if top.len() < self.tracked {
+            top.push((key.clone(), count));
+        } else if let Some(coldest) = top.iter_mut().min_by_key(|(_, c)| *c) {
+            if count > coldest.1 {
+                *coldest = (key.clone(), count);
+            }
+        }
+        if top.len() == self.tracked {
+            let floor = top.iter().map(|(_, c)| *c).min().unwrap_or(0);
+            self.floor.store(floor, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the estimated count of `key`, at least its true count.
+    pub(crate) fn estimate(&self, key: &K) -> u64 {
+        (0..DEPTH)
+            .map(|row| {
+                self.counters[row * self.width + self.column(row, key)].load(Ordering::Relaxed)
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Returns up to `n` of the hottest keys with their counts, hottest first.
+    pub(crate) fn hottest(&self, n: usize) -> Vec<(K, u64)> {
+        let mut top = self.top.lock().recover().clone();
+        top.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        top.truncate(n);
+        top
+    }
+
+    fn column(&self, row: usize, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() % self.width as u64) as usize
+    }
+}
+
+/// The hottest keys of all lookups and of the lookups that missed.
+pub(crate) struct KeyTraffic<K> {
+    pub(crate) lookups: HotKeys<K>,
+    pub(crate) misses: HotKeys<K>,
+}
+
+impl<K: Eq + Hash + Clone> KeyTraffic<K> {
+    pub(crate) fn new(tracked: usize) -> Self {
+        KeyTraffic {
+            lookups: HotKeys::new(tracked),
+            misses: HotKeys::new(tracked),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HotKeys;
+
+    #[test]
+    fn test_hottest() {
+        let hot = HotKeys::new(2);
+        for key in 0..100 {
+            hot.record(&key);
+        }
+        for _ in 0..10 {
+            hot.record(&7);
+        }
+        for _ in 0..5 {
+            hot.record(&42);
+        }
+
+        assert_eq!(hot.hottest(2), vec![(7, 11), (42, 6)]);
+        assert_eq!(hot.hottest(1), vec![(7, 11)]);
+        assert!(hot.estimate(&42) >= 6);
+        assert!(hot.estimate(&1000) < 6);
+    }
+}
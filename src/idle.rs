@@ -0,0 +1,57 @@
+//! When each entry was last used, for purging the ones left idle.
+use dashmap::DashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// The last insert or hit of each entry, in milliseconds since tracking began.
+pub(crate) struct Touched<K> {
+    started: Instant,
+    times: DashMap<K, u64>,
+}
+
+impl<K: Eq + Hash + Clone> Touched<K> {
+    pub(crate) fn new() -> Self {
+        Touched {
+            started: Instant::now(),
+            times: DashMap::new(),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    pub(crate) fn touch(&self, key: &K) {
+        let now = self.now();
+        match self.times.get_mut(key) {
+            Some(mut time) => *time = now,
+            None => {
+                self.times.insert(key.clone(), now);
+            }
+        }
+    }
+
+    pub(crate) fn forget(&self, key: &K) {
+        self.times.remove(key);
+    }
+
+    pub(crate) fn forget_all(&self) {
+        self.times.clear();
+    }
+
+    /// Returns whether `key` has not been used for `max_idle`.
+    pub(crate) fn is_idle(&self, key: &K, max_idle: Duration) -> bool {
+        let oldest = self.now().saturating_sub(max_idle.as_millis() as u64);
+        self.times.get(key).is_some_and(|time| *time <= oldest)
+    }
+
+    /// Returns the keys that have not been used for `max_idle`.
+    pub(crate) fn idle(&self, max_idle: Duration) -> Vec<K> {
+        let oldest = self.now().saturating_sub(max_idle.as_millis() as u64);
+        self.times
+            .iter()
+            .filter(|entry| *entry.value() <= oldest)
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+}
@@ -0,0 +1,227 @@
+//! Secondary indexes: finding entries by properties of their values.
+use dashmap::DashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// The keys of a cache by a property of their values, kept up to date as
+/// entries are inserted and removed, see [`Cache::index_by`](crate::Cache::index_by).
+///
+/// Clones share the same index. Once the last is dropped, the cache stops
+/// updating it.
+pub struct SecondaryIndex<K, I> {
+    inner: Arc<IndexInner<K, I>>,
+}
+
+type LivenessCheck<K, I> = Box<dyn Fn(&K, &I) -> bool + Send + Sync>;
+
+struct IndexInner<K, I> {
+    entries: Arc<IndexEntries<K, I>>,
+    /// Whether the cache still has an entry for the key with the property
+    is_live: LivenessCheck<K, I>,
+    /// Removes the hooks that update `entries`
+    unregister: Option<Box<dyn FnOnce() + Send + Sync>>,
+}
+
+impl<K, I> Clone for SecondaryIndex<K, I> {
+    fn clone(&self) -> Self {
+        SecondaryIndex {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, I> Drop for IndexInner<K, I> {
+    fn drop(&mut self) {
+        if let Some(unregister) = self.unregister.take() {
+            unregister();
+        }
+    }
+}
+
+/// The keys by property, as the hooks of the cache see them.
+///
+/// Hooks run after the cache changes, so a key can be left behind when a
+/// removal is seen before the insert it undoes, e.g. for an entry evicted
+/// as it is inserted, or racing another thread.
+pub(crate) struct IndexEntries<K, I> {
+    keys: DashMap<I, HashSet<K>>,
+    /// The indexed property of each key, to unlink it when it changes
+    properties: DashMap<K, I>,
+}
+
+impl<K, I> IndexEntries<K, I>
+where
+    K: Eq + Hash + Clone,
+    I: Eq + Hash + Clone,
+{
+    pub(crate) fn new() -> Self {
+        IndexEntries {
+            keys: DashMap::new(),
+            properties: DashMap::new(),
+        }
+    }
+
+    /// Indexes `key` under `property`, instead of what it was indexed under.
+    pub(crate) fn add(&self, key: &K, property: I) {
+        if let Some(previous) = self.properties.insert(key.clone(), property.clone()) {
+            if previous != property {
+                self.unlink(key, &previous);
+            }
+        }
+        self.keys.entry(property).or_default().insert(key.clone());
+    }
+
+    pub(crate) fn remove(&self, key: &K) {
+        if let Some((_, previous)) = self.properties.remove(key) {
+            self.unlink(key, &previous);
+        }
+    }
+
+    fn unlink(&self, key: &K, property: &I) {
+        self.keys.remove_if_mut(property, |_, keys| {
+            keys.remove(key);
+            keys.is_empty()
+        });
+    }
+}
+
+impl<K, I> SecondaryIndex<K, I>
+where
+    K: Eq + Hash + Clone,
+    I: Eq + Hash + Clone,
+{
+    pub(crate) fn new(
+        entries: Arc<IndexEntries<K, I>>,
+        is_live: impl Fn(&K, &I) -> bool + Send + Sync + 'static,
+        unregister: impl FnOnce() + Send + Sync + 'static,
+    ) -> Self {
+        SecondaryIndex {
+            inner: Arc::new(IndexInner {
+                entries,
+                is_live: Box::new(is_live),
+                unregister: Some(Box::new(unregister)),
+            }),
+        }
+    }
+
+    /// Returns the keys of the entries whose values have `property`.
+    ///
+    /// Each key is checked against the cache, without counting a hit, and
+    /// keys left behind by changes seen out of order are dropped.
+    pub fn get(&self, property: &I) -> Vec<K> {
+        let entries = &self.inner.entries;
+        let Some(mut keys) = entries.keys.get_mut(property) else {
+            return Vec::new();
+        };
+        keys.retain(|key| {
+            let live = (self.inner.is_live)(key, property);
+            if !live {
+                entries
+                    .properties
+                    .remove_if(key, |_, indexed| indexed == property);
+            }
+            live
+        });
+        let live: Vec<K> = keys.iter().cloned().collect();
+        drop(keys);
+        if live.is_empty() {
+            entries.keys.remove_if(property, |_, keys| keys.is_empty());
+        }
+        live
+    }
+
+    /// Returns how many distinct properties the entries have, counting
+    /// those of keys left behind until [`SecondaryIndex::get`] drops them.
+    pub fn len(&self) -> usize {
+        self.inner.entries.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.entries.keys.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Cache;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn session(user: &str, started: i32) -> (String, i32) {
+        (user.to_string(), started)
+    }
+
+    #[test]
+    fn test_find() {
+        for cache in [Cache::new_lru(3), Cache::new_unbounded()] {
+            cache.insert(1, session("alice", 10));
+            cache.insert(2, session("bob", 20));
+            cache.insert(3, session("alice", 30));
+            let mut found = cache.find(|_, (user, _)| user == "alice");
+            found.sort();
+            assert_eq!(found, [1, 3]);
+            assert!(cache.find(|key, _| *key > 3).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_index_by() {
+        let cache = Cache::new_lru(3);
+        cache.insert(1, session("alice", 10));
+        let sessions = cache.index_by(|_, (user, _): &(String, i32)| user.clone());
+        cache.insert(2, session("bob", 20));
+        cache.insert(3, session("alice", 30));
+        let mut alice = sessions.get(&"alice".to_string());
+        alice.sort();
+        assert_eq!(alice, [1, 3]);
+
+        // Updates move keys, removals and evictions drop them
+        cache.insert(3, session("bob", 30));
+        cache.remove(&2);
+        cache.insert(4, session("carol", 40));
+        cache.insert(5, session("carol", 50));
+        assert_eq!(sessions.get(&"alice".to_string()), Vec::<i32>::new());
+        assert_eq!(sessions.get(&"bob".to_string()), [3]);
+        assert_eq!(sessions.len(), 2);
+        cache.clear();
+        assert!(sessions.is_empty());
+    }
+
+    #[test]
+    fn test_index_self_eviction() {
+        // Entries evicted as they are inserted are removed before the
+        // insert hook adds them
+        let weighted =
+            Cache::new_weighted(10, |_: &i32, (_, started): &(String, i32)| *started as u32);
+        for cache in [weighted, Cache::new_lru(0)] {
+            let sessions = cache.index_by(|_, (user, _): &(String, i32)| user.clone());
+            cache.insert(1, session("alice", 20));
+            assert!(cache.is_empty());
+            assert_eq!(sessions.get(&"alice".to_string()), Vec::<i32>::new());
+            assert!(sessions.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_index_drop() {
+        let cache = Cache::new_lru(3);
+        let extracted = Arc::new(AtomicUsize::new(0));
+        let counting = extracted.clone();
+        let sessions = cache.index_by(move |_, (user, _): &(String, i32)| {
+            counting.fetch_add(1, Ordering::Relaxed);
+            user.clone()
+        });
+        cache.insert(1, session("alice", 10));
+        let clone = sessions.clone();
+        drop(sessions);
+        assert_eq!(clone.get(&"alice".to_string()), [1]);
+
+        // The cache stops updating the index once the last clone is gone
+        drop(clone);
+        let before = extracted.load(Ordering::Relaxed);
+        cache.insert(2, session("bob", 20));
+        cache.remove(&1);
+        assert_eq!(extracted.load(Ordering::Relaxed), before);
+    }
+}
@@ -0,0 +1,260 @@
+//! Examining snapshot files without knowing the types they were written for,
+//! as done by the `minne-inspect` binary.
+use crate::persistence::{self, SnapshotMetadata, FRAME_HEADER};
+use anyhow::{anyhow, Result};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// A key or value type to decode the entries of an inspected file as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeHint {
+    String,
+    Bytes,
+    U32,
+    U64,
+    I32,
+    I64,
+}
+
+impl FromStr for TypeHint {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "String" | "string" => Ok(TypeHint::String),
+            "Vec<u8>" | "bytes" => Ok(TypeHint::Bytes),
+            "u32" => Ok(TypeHint::U32),
+            "u64" => Ok(TypeHint::U64),
+            "i32" => Ok(TypeHint::I32),
+            "i64" => Ok(TypeHint::I64),
+            _ => Err(anyhow!(
+                "Unknown type '{}', expected one of String, Vec<u8>, u32, u64, i32, i64",
+                name
+            )),
+        }
+    }
+}
+
+impl TypeHint {
+    /// Returns the hash of the type's name stored in snapshot headers.
+    fn type_hash(self) -> u64 {
+        match self {
+            TypeHint::String => persistence::type_hash::<String>(),
+            TypeHint::Bytes => persistence::type_hash::<Vec<u8>>(),
+            TypeHint::U32 => persistence::type_hash::<u32>(),
+            TypeHint::U64 => persistence::type_hash::<u64>(),
+            TypeHint::I32 => persistence::type_hash::<i32>(),
+            TypeHint::I64 => persistence::type_hash::<i64>(),
+        }
+    }
+
+    /// Decodes one value of this type from the front of `input`, for display.
+    fn decode(self, input: &mut &[u8]) -> bincode::Result<String> {
+        Ok(match self {
+            TypeHint::String => format!("{:?}", bincode::deserialize_from::<_, String>(input)?),
+            TypeHint::Bytes => bincode::deserialize_from::<_, Vec<u8>>(input)?
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            TypeHint::U32 => bincode::deserialize_from::<_, u32>(input)?.to_string(),
+            TypeHint::U64 => bincode::deserialize_from::<_, u64>(input)?.to_string(),
+            TypeHint::I32 => bincode::deserialize_from::<_, i32>(input)?.to_string(),
+            TypeHint::I64 => bincode::deserialize_from::<_, i64>(input)?.to_string(),
+        })
+    }
+}
+
+/// What could be learned about a snapshot file without its types.
+#[derive(Debug)]
+pub struct Inspection {
+    /// Size of the file on disk
+    pub file_size: usize,
+    /// The format version, or `None` for v0 files, which have no header
+    pub version: Option<u16>,
+    /// Hashes of the key and value type names the file was written for
+    pub type_hashes: Option<(u64, u64)>,
+    pub metadata: Option<SnapshotMetadata>,
+    /// Entries in the frames that could be read
+    pub entries: usize,
+    /// Bytes of serialized entries in those frames
+    pub entry_bytes: usize,
+    /// Why the file could not be read to its end, if it could not
+    pub problem: Option<String>,
+    /// The decoded snapshot
+    data: Vec<u8>,
+    /// The payloads of the intact entry frames
+    chunks: Vec<Range<usize>>,
+}
+
+/// Reads the snapshot at `file_name` and walks its frames.
+///
+/// Damage is reported in [`Inspection::problem`] rather than as an error,
+/// so whatever comes before it can still be examined.
+pub fn inspect(file_name: &str) -> Result<Inspection> {
+    let data = std::fs::read(file_name)?;
+    let file_size = data.len();
+
+    let mut inspection = Inspection {
+        file_size,
+        version: None,
+        type_hashes: None,
+        metadata: None,
+        entries: 0,
+        entry_bytes: 0,
+        problem: None,
+        data,
+        chunks: Vec::new(),
+    };
+    if !inspection.data.starts_with(&persistence::MAGIC) {
+        // A v0 file is a single bare chunk, if its count is plausible
+        let entries = chunk_len(&inspection.data);
+        if inspection.data.len() < 8 || entries > inspection.data.len() {
+            inspection.problem = Some(persistence::PersistenceError::UnknownFormat.to_string());
+            return Ok(inspection);
+        }
+        inspection.entries = entries;
+        inspection.entry_bytes = inspection.data.len().saturating_sub(8);
+        inspection.chunks.push(0..inspection.data.len());
+        return Ok(inspection);
+    }
+    if let Err(e) = inspection.walk() {
+        inspection.problem = Some(e.to_string());
+    }
+    Ok(inspection)
+}
+
+impl Inspection {
+    /// Reads the header and frames, stopping at the first damaged one.
+    fn walk(&mut self) -> Result<()> {
+        let header = self
+            .data
+            .get(..persistence::HEADER_LEN)
+            .ok_or_else(|| persistence::corrupt("unexpected end of file"))?;
+        let version = u16::from_le_bytes(header[6..8].try_into()?);
+        self.version = Some(version);
+        self.type_hashes = Some((
+            u64::from_le_bytes(header[8..16].try_into()?),
+            u64::from_le_bytes(header[16..24].try_into()?),
+        ));
+        if !(persistence::MIN_VERSION..=persistence::FORMAT_VERSION).contains(&version) {
+            return Err(persistence::PersistenceError::UnsupportedVersion(version).into());
+        }
+
+        let (metadata, mut pos) = persistence::read_metadata(&self.data, persistence::HEADER_LEN)?;
+        self.metadata = metadata;
+        loop {
+            let payload = persistence::read_frame(&self.data, pos)?;
+            let start = pos + FRAME_HEADER;
+            pos = start + payload.len();
+            if payload.is_empty() {
+                return persistence::check_trailer(&self.data, pos);
+            }
+            self.entries += chunk_len(payload);
+            self.entry_bytes += payload.len().saturating_sub(8);
+            self.chunks.push(start..pos);
+        }
+    }
+
+    /// Returns whether the file was written for the hinted types, or `None`
+    /// for v0 files, which do not record their types.
+    pub fn matches(&self, key: TypeHint, value: TypeHint) -> Option<bool> {
+        self.type_hashes
+            .map(|hashes| hashes == (key.type_hash(), value.type_hash()))
+    }
+
+    /// Decodes up to `n` entries as the hinted types, for display.
+    ///
+    /// Before format version 3, the end of a value is not known without
+    /// decoding it, so without a value type only the first key of each
+    /// frame can be found.
+    pub fn sample(
+        &self,
+        key: TypeHint,
+        value: Option<TypeHint>,
+        n: usize,
+    ) -> Result<Vec<(String, Option<String>)>> {
+        let mut samples = Vec::new();
+        let value_len = self.version >= Some(persistence::VALUE_LEN_VERSION);
+        for chunk in &self.chunks {
+            let mut rest = &self.data[chunk.clone()];
+            let count: u64 = bincode::deserialize_from(&mut rest)?;
+            for _ in 0..count {
+                if samples.len() >= n {
+                    return Ok(samples);
+                }
+                let decoded_key = key.decode(&mut rest)?;
+                if value_len {
+                    let mut bytes = persistence::split_value(&mut rest)?;
+                    let decoded_value = value.map(|value| value.decode(&mut bytes)).transpose()?;
+                    samples.push((decoded_key, decoded_value));
+                    continue;
+                }
+                match value {
+                    Some(value) => samples.push((decoded_key, Some(value.decode(&mut rest)?))),
+                    None => {
+                        samples.push((decoded_key, None));
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// Returns the entry count at the start of a chunk.
+fn chunk_len(payload: &[u8]) -> usize {
+    payload.get(..8).map_or(0, |count| {
+        u64::from_le_bytes(count.try_into().unwrap()) as usize
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inspect, TypeHint};
+    use crate::Cache;
+
+    #[test]
+    fn test_inspect() {
+        let path = std::env::temp_dir().join("minne_inspect.cache");
+        let path = path.to_str().unwrap();
+        let cache = Cache::new_unbounded();
+        cache.insert("one".to_string(), 1u64);
+        cache.write(path).unwrap();
+
+        let inspection = inspect(path).unwrap();
+        assert_eq!(inspection.version, Some(3));
+        assert_eq!(inspection.entries, 1);
+        assert_eq!(inspection.metadata.as_ref().unwrap().len, 1);
+        assert_eq!(inspection.problem, None);
+        assert_eq!(
+            inspection.matches(TypeHint::String, TypeHint::U64),
+            Some(true)
+        );
+        assert_eq!(
+            inspection.matches(TypeHint::U64, TypeHint::U64),
+            Some(false)
+        );
+        assert_eq!(
+            inspection
+                .sample(TypeHint::String, Some(TypeHint::U64), 10)
+                .unwrap(),
+            vec![("\"one\"".to_string(), Some("1".to_string()))]
+        );
+        assert_eq!(
+            inspection.sample(TypeHint::String, None, 10).unwrap(),
+            vec![("\"one\"".to_string(), None)]
+        );
+
+        // A truncated file is reported, not refused
+        let data = std::fs::read(path).unwrap();
+        std::fs::write(path, &data[..data.len() - 2]).unwrap();
+        let inspection = inspect(path).unwrap();
+        assert_eq!(inspection.entries, 1);
+        assert!(inspection
+            .problem
+            .unwrap()
+            .contains("unexpected end of file"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+//! Caches keyed by long strings, stored once in an intern table.
+use crate::{Cache, CacheStats, RemovalCause};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The ids of the interned keys, and the keys of the ids.
+struct Interner {
+    ids: DashMap<Arc<str>, u64>,
+    keys: DashMap<u64, Arc<str>>,
+    next: AtomicU64,
+}
+
+impl Interner {
+    fn intern(&self, key: &str) -> u64 {
+        if let Some(id) = self.ids.get(key) {
+            return *id;
+        }
+        *self.ids.entry(Arc::from(key)).or_insert_with(|| {
+            let id = self.next.fetch_add(1, Ordering::Relaxed);
+            self.keys.insert(id, Arc::from(key));
+            id
+        })
+    }
+
+    fn forget(&self, id: u64) {
+        if let Some((_, key)) = self.keys.remove(&id) {
+            self.ids.remove_if(&key, |_, interned| *interned == id);
+        }
+    }
+}
+
+/// A cache keyed by strings, such as URLs, that stores each key once.
+///
+/// Keys are interned: the table holds each one once and gives it a numeric
+/// id, and the cache behind it, whose map and recency order would each hold
+/// a copy of the key, holds only ids. Keys leave the table along with
+/// their entries. Ids are never reused, so a lookup racing with the
+/// eviction of its key can at worst miss.
+///
+/// The cache behind the table is keyed by ids that mean nothing without
+/// it, so it should not be written to or read from snapshots.
+pub struct InternedCache<V>
+where
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    cache: Cache<u64, V>,
+    interner: Arc<Interner>,
+}
+
+impl<V> InternedCache<V>
+where
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    /// Interns the keys of `cache`, which should be new and empty, e.g.
+    /// `Cache::new_lru(capacity)`.
+    pub fn new(cache: Cache<u64, V>) -> Self {
+        let interner = Arc::new(Interner {
+            ids: DashMap::new(),
+            keys: DashMap::new(),
+            next: AtomicU64::new(0),
+        });
+        let forgetting = interner.clone();
+        cache.on_removal(move |id, _, cause| {
+            if cause != RemovalCause::Replaced {
+                forgetting.forget(id);
+            }
+        });
+        InternedCache { cache, interner }
+    }
+
+    pub fn insert(&self, key: &str, value: V) {
+        self.cache.insert(self.interner.intern(key), value);
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        match self.interner.ids.get(key).map(|id| *id) {
+            Some(id) => self.cache.get(&id),
+            None => {
+                // Never an id, looked up to count the miss
+                self.cache.get(&u64::MAX)
+            }
+        }
+    }
+
+    pub fn peek(&self, key: &str) -> Option<V> {
+        let id = *self.interner.ids.get(key)?;
+        self.cache.peek(&id)
+    }
+
+    pub fn remove(&self, key: &str) -> Option<V> {
+        let id = *self.interner.ids.get(key)?;
+        self.cache.remove(&id)
+    }
+
+    pub fn clear(&self) {
+        self.cache.clear();
+        self.interner.ids.clear();
+        self.interner.keys.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// Returns how many keys are interned.
+    pub fn interned(&self) -> usize {
+        self.interner.keys.len()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Returns the cache of ids behind the table, e.g. to subscribe to it.
+    pub fn cache(&self) -> &Cache<u64, V> {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InternedCache;
+    use crate::Cache;
+
+    #[test]
+    fn test_interned() {
+        let cache = InternedCache::new(Cache::new_lru(2));
+        let url = |i: usize| format!("https://example.com/a/rather/long/path/{}", i);
+        cache.insert(&url(1), 1);
+        cache.insert(&url(2), 2);
+        cache.insert(&url(1), 10);
+        assert_eq!(cache.interned(), 2);
+        assert_eq!(cache.get(&url(1)), Some(10));
+
+        // Evicted and removed keys leave the table
+        cache.insert(&url(3), 3);
+        assert_eq!(cache.get(&url(2)), None);
+        assert_eq!(cache.remove(&url(1)), Some(10));
+        assert_eq!(cache.interned(), 1);
+        assert_eq!(cache.peek(&url(3)), Some(3));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        cache.clear();
+        assert_eq!(cache.interned(), 0);
+    }
+}
@@ -0,0 +1,81 @@
+//! Keeping caches on several instances coherent by broadcasting removals.
+use crate::sync::{isolate, Recover};
+use std::sync::RwLock;
+
+/// Called with each key published on a bus.
+pub type InvalidationHandler<K> = Box<dyn Fn(&K) + Send + Sync>;
+
+/// Carries invalidated keys between caches.
+///
+/// A cache joined to a bus publishes every key removed through
+/// [`crate::Cache::remove`], and removes every key published by others.
+/// Removals caused by the bus are not published again. An implementation
+/// for a multi-instance deployment publishes to a shared channel, such as a
+/// message queue topic, and calls the handlers for keys received from it.
+pub trait InvalidationBus<K>: Send + Sync {
+    /// Announces that `key` was removed.
+    fn publish(&self, key: &K);
+
+    /// Registers `handler` to be called with every key published from now on.
+    fn subscribe(&self, handler: InvalidationHandler<K>);
+}
+
+/// A bus connecting the caches of a single process.
+pub struct LocalBus<K> {
+    handlers: RwLock<Vec<InvalidationHandler<K>>>,
+}
+
+impl<K> LocalBus<K> {
+    pub fn new() -> Self {
+        LocalBus {
+            handlers: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl<K> Default for LocalBus<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> InvalidationBus<K> for LocalBus<K> {
+    fn publish(&self, key: &K) {
+        for handler in self.handlers.read().recover().iter() {
+            isolate("Invalidation handler", || handler(key));
+        }
+    }
+
+    fn subscribe(&self, handler: InvalidationHandler<K>) {
+        self.handlers.write().recover().push(handler);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalBus;
+    use crate::Cache;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_local_bus() {
+        let bus = Arc::new(LocalBus::new());
+        let first = Cache::new_lru(10);
+        let second = Cache::new_unbounded();
+        first.join_invalidation(bus.clone()).unwrap();
+        second.join_invalidation(bus.clone()).unwrap();
+        assert!(first.join_invalidation(bus).is_err());
+
+        for cache in [&first, &second] {
+            cache.insert(1, "one".to_string());
+            cache.insert(2, "two".to_string());
+        }
+        assert_eq!(first.remove(&1), Some("one".to_string()));
+        assert_eq!(second.get(&1), None);
+        // Keys the removing cache never held are invalidated elsewhere too
+        second.remove(&2);
+        first.remove(&3);
+        assert_eq!(first.get(&2), None);
+        assert_eq!(first.len() + second.len(), 0);
+    }
+}
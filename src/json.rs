@@ -0,0 +1,546 @@
+//! Human-readable JSON dumps of a cache, for bug reports.
+//!
+//! Keys and values are converted through their `Serialize` impls into a
+//! small JSON tree, which is then pretty-printed. This is independent of
+//! the binary formats used for persistence.
+use crate::{Cache, CachePolicy};
+use anyhow::Result;
+use serde::ser::{self, Serialize};
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::hash::Hash;
+use std::io::Write;
+
+/// A JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    /// A number, already formatted
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Cuts strings and arrays longer than `max_len` down to it, marking
+    /// strings that were cut with a trailing ellipsis.
+    fn truncate(&mut self, max_len: usize) {
+        match self {
+            Json::String(s) => {
+                if let Some((end, _)) = s.char_indices().nth(max_len) {
+                    s.truncate(end);
+                    s.push('…');
+                }
+            }
+            Json::Array(items) => {
+                items.truncate(max_len);
+                items.iter_mut().for_each(|item| item.truncate(max_len));
+            }
+            Json::Object(fields) => fields.iter_mut().for_each(|(_, v)| v.truncate(max_len)),
+            Json::Null | Json::Bool(_) | Json::Number(_) => {}
+        }
+    }
+
+    fn write_compact(&self, out: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            Json::Array(items) => {
+                write!(out, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    item.write_compact(out)?;
+                }
+                write!(out, "]")
+            }
+            Json::Object(fields) => {
+                write!(out, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, ",")?;
+                    }
+                    write_string(out, name)?;
+                    write!(out, ":")?;
+                    value.write_compact(out)?;
+                }
+                write!(out, "}}")
+            }
+            scalar => scalar.write_pretty(out, 0),
+        }
+    }
+
+    fn write_pretty(&self, out: &mut impl Write, indent: usize) -> std::io::Result<()> {
+        let pad = "  ".repeat(indent + 1);
+        let end_pad = "  ".repeat(indent);
+        match self {
+            Json::Null => write!(out, "null"),
+            Json::Bool(b) => write!(out, "{}", b),
+            Json::Number(n) => write!(out, "{}", n),
+            Json::String(s) => write_string(out, s),
+            Json::Array(items) if items.is_empty() => write!(out, "[]"),
+            Json::Array(items) => {
+                writeln!(out, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(out, "{}", pad)?;
+                    item.write_pretty(out, indent + 1)?;
+                    writeln!(out, "{}", if i + 1 < items.len() { "," } else { "" })?;
+                }
+                write!(out, "{}]", end_pad)
+            }
+            Json::Object(fields) if fields.is_empty() => write!(out, "{{}}"),
+            Json::Object(fields) => {
+                writeln!(out, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    write!(out, "{}", pad)?;
+                    write_string(out, name)?;
+                    write!(out, ": ")?;
+                    value.write_pretty(out, indent + 1)?;
+                    writeln!(out, "{}", if i + 1 < fields.len() { "," } else { "" })?;
+                }
+                write!(out, "{}}}", end_pad)
+            }
+        }
+    }
+}
+
+/// Formats `value` as JSON on a single line, or as its error if it cannot be.
+pub(crate) fn to_compact_string<T: Serialize + ?Sized>(value: &T) -> String {
+    match to_json(value) {
+        Ok(json) => {
+            let mut out = Vec::new();
+            let _ = json.write_compact(&mut out);
+            String::from_utf8_lossy(&out).into_owned()
+        }
+        Err(e) => format!("<{}>", e),
+    }
+}
+
+fn write_string(out: &mut impl Write, s: &str) -> std::io::Result<()> {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+/// Writes the statistics of `cache` and up to `limit` of its entries to
+/// `writer`. See [`Cache::dump_json`].
+pub(crate) fn dump<K, V>(
+    cache: &Cache<K, V>,
+    writer: impl Write,
+    limit: usize,
+    max_value_len: Option<usize>,
+) -> Result<()>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    let policy = match cache {
+        Cache::LRU(_) => to_json(&CachePolicy::LRU)?,
+        Cache::Unbounded(_) => to_json(&CachePolicy::Unbounded)?,
+        Cache::None | Cache::Noop(_) => Json::Null,
+    };
+    let stats = cache.stats();
+
+    // Most recently used first, as those are usually the interesting ones
+    let mut entries: Vec<(K, V)> = cache.export().collect();
+    entries.reverse();
+    let mut dumped = Vec::with_capacity(entries.len().min(limit));
+    for (recency, (key, value)) in entries.into_iter().take(limit).enumerate() {
+        let mut value = to_json(&value)?;
+        if let Some(max_len) = max_value_len {
+            value.truncate(max_len);
+        }
+        let mut fields = vec![
+            ("key".to_string(), to_json(&key)?),
+            ("value".to_string(), value),
+        ];
+        if let Cache::LRU(_) = cache {
+            fields.push(("recency".to_string(), Json::Number(recency.to_string())));
+        }
+        dumped.push(Json::Object(fields));
+    }
+
+    let dump = Json::Object(vec![
+        ("policy".to_string(), policy),
+        ("stats".to_string(), to_json(&stats)?),
+        (
+            "omitted".to_string(),
+            Json::Number(stats.len.saturating_sub(dumped.len()).to_string()),
+        ),
+        ("entries".to_string(), Json::Array(dumped)),
+    ]);
+    let mut writer = std::io::BufWriter::new(writer);
+    dump.write_pretty(&mut writer, 0)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub(crate) struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// Converts `value` to JSON.
+pub(crate) fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<Json, Error> {
+    value.serialize(Serializer)
+}
+
+struct Serializer;
+
+/// Collects the elements of a sequence, tuple or tuple variant.
+struct SeqBuilder {
+    items: Vec<Json>,
+    /// The variant to wrap the array in, for tuple variants
+    variant: Option<&'static str>,
+}
+
+/// Collects the fields of a map, struct or struct variant.
+struct MapBuilder {
+    fields: Vec<(String, Json)>,
+    next_key: Option<String>,
+    variant: Option<&'static str>,
+}
+
+/// Wraps `value` as `{"variant": value}`, the usual JSON form of an enum.
+fn wrap(variant: Option<&'static str>, value: Json) -> Json {
+    match variant {
+        Some(variant) => Json::Object(vec![(variant.to_string(), value)]),
+        None => value,
+    }
+}
+
+/// Returns the text of a JSON map key; non-string keys are written as JSON.
+fn key_text(key: Json) -> String {
+    match key {
+        Json::String(s) | Json::Number(s) => s,
+        other => {
+            let mut out = Vec::new();
+            let _ = other.write_compact(&mut out);
+            String::from_utf8_lossy(&out).into_owned()
+        }
+    }
+}
+
+fn number(n: impl Display) -> Result<Json, Error> {
+    Ok(Json::Number(n.to_string()))
+}
+
+fn float(n: f64) -> Result<Json, Error> {
+    // JSON has no NaN or infinities
+    match n.is_finite() {
+        true => number(n),
+        false => Ok(Json::Null),
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Json;
+    type Error = Error;
+    type SerializeSeq = SeqBuilder;
+    type SerializeTuple = SeqBuilder;
+    type SerializeTupleStruct = SeqBuilder;
+    type SerializeTupleVariant = SeqBuilder;
+    type SerializeMap = MapBuilder;
+    type SerializeStruct = MapBuilder;
+    type SerializeStructVariant = MapBuilder;
+
+    fn serialize_bool(self, v: bool) -> Result<Json, Error> {
+        Ok(Json::Bool(v))
+    }
+    fn serialize_i8(self, v: i8) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_i16(self, v: i16) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_i32(self, v: i32) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_i64(self, v: i64) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_i128(self, v: i128) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_u8(self, v: u8) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_u16(self, v: u16) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_u32(self, v: u32) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_u64(self, v: u64) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_u128(self, v: u128) -> Result<Json, Error> {
+        number(v)
+    }
+    fn serialize_f32(self, v: f32) -> Result<Json, Error> {
+        float(v as f64)
+    }
+    fn serialize_f64(self, v: f64) -> Result<Json, Error> {
+        float(v)
+    }
+    fn serialize_char(self, v: char) -> Result<Json, Error> {
+        Ok(Json::String(v.to_string()))
+    }
+    fn serialize_str(self, v: &str) -> Result<Json, Error> {
+        Ok(Json::String(v.to_string()))
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Json, Error> {
+        Ok(Json::Array(
+            v.iter().map(|b| Json::Number(b.to_string())).collect(),
+        ))
+    }
+    fn serialize_none(self) -> Result<Json, Error> {
+        Ok(Json::Null)
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Json, Error> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Json, Error> {
+        Ok(Json::Null)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Json, Error> {
+        Ok(Json::Null)
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Json, Error> {
+        Ok(Json::String(variant.to_string()))
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Json, Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Json, Error> {
+        Ok(wrap(Some(variant), value.serialize(self)?))
+    }
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqBuilder, Error> {
+        Ok(SeqBuilder {
+            items: Vec::with_capacity(len.unwrap_or(0)),
+            variant: None,
+        })
+    }
+    fn serialize_tuple(self, len: usize) -> Result<SeqBuilder, Error> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqBuilder, Error> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqBuilder, Error> {
+        Ok(SeqBuilder {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+    fn serialize_map(self, len: Option<usize>) -> Result<MapBuilder, Error> {
+        Ok(MapBuilder {
+            fields: Vec::with_capacity(len.unwrap_or(0)),
+            next_key: None,
+            variant: None,
+        })
+    }
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapBuilder, Error> {
+        self.serialize_map(Some(len))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<MapBuilder, Error> {
+        Ok(MapBuilder {
+            fields: Vec::with_capacity(len),
+            next_key: None,
+            variant: Some(variant),
+        })
+    }
+}
+
+impl ser::SerializeSeq for SeqBuilder {
+    type Ok = Json;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.items.push(to_json(value)?);
+        Ok(())
+    }
+    fn end(self) -> Result<Json, Error> {
+        Ok(wrap(self.variant, Json::Array(self.items)))
+    }
+}
+
+impl ser::SerializeTuple for SeqBuilder {
+    type Ok = Json;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+    fn end(self) -> Result<Json, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqBuilder {
+    type Ok = Json;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+    fn end(self) -> Result<Json, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqBuilder {
+    type Ok = Json;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+    fn end(self) -> Result<Json, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeMap for MapBuilder {
+    type Ok = Json;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.next_key = Some(key_text(to_json(key)?));
+        Ok(())
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| Error("Map value without a key".to_string()))?;
+        self.fields.push((key, to_json(value)?));
+        Ok(())
+    }
+    fn end(self) -> Result<Json, Error> {
+        Ok(wrap(self.variant, Json::Object(self.fields)))
+    }
+}
+
+impl ser::SerializeStruct for MapBuilder {
+    type Ok = Json;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.fields.push((name.to_string(), to_json(value)?));
+        Ok(())
+    }
+    fn end(self) -> Result<Json, Error> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for MapBuilder {
+    type Ok = Json;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, name, value)
+    }
+    fn end(self) -> Result<Json, Error> {
+        ser::SerializeMap::end(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_json, Json};
+    use crate::Cache;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_to_json() {
+        let map = BTreeMap::from([(1, vec![Some("a\"b".to_string()), None])]);
+        let mut out = Vec::new();
+        to_json(&map).unwrap().write_pretty(&mut out, 0).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\n  \"1\": [\n    \"a\\\"b\",\n    null\n  ]\n}"
+        );
+        assert_eq!(to_json(&f64::NAN).unwrap(), Json::Null);
+    }
+
+    #[test]
+    fn test_dump_json() {
+        let cache = Cache::new_lru(10);
+        cache.insert(1, "one".to_string());
+        cache.insert(2, "a rather long value".to_string());
+        cache.insert(3, "three".to_string());
+        cache.get(&1);
+
+        let mut out = Vec::new();
+        cache.dump_json(&mut out, 2, Some(6)).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("{\n  \"policy\": \"LRU\",\n  \"stats\": {\n    \"len\": 3,"));
+        assert!(text.contains("\"omitted\": 1,"));
+        assert!(text.contains(
+            "    {\n      \"key\": 1,\n      \"value\": \"one\",\n      \"recency\": 0\n    },"
+        ));
+        assert!(text.contains("\"value\": \"three\","));
+        assert!(!text.contains("rather"));
+    }
+}
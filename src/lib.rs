@@ -1,8 +1,91 @@
 use anyhow::Result;
+pub use async_cache::{AsyncCache, KeyGuard};
+pub use audit::Mutation;
+pub use autotune::Autotune;
+pub use bounded::BoundedCache;
+pub use dedup::DedupCache;
+pub use events::{CacheEvent, EventSink, Overflow, Receiver, RemovalCause};
+use index::IndexEntries;
+pub use index::SecondaryIndex;
+pub use intern::InternedCache;
+pub use invalidation::{InvalidationBus, InvalidationHandler, LocalBus};
+pub use lifetime::LifetimeStats;
+pub use loader::{Loader, LoadingCache};
+pub use lru::OverflowPolicy;
+pub use mmap::MappedSnapshot;
+pub use partitioned::PartitionedCache;
+pub use persistence::{
+    CachePolicy, LoadReport, PersistenceError, ReadLimits, ReadMode, SnapshotMetadata,
+};
+pub use persistent::PersistentCache;
+pub use recorder::{NoopRecorder, StatsRecorder};
+pub use report::set_error_hook;
 use serde::{Deserialize, Serialize};
-use std::{hash::Hash, sync::atomic::AtomicUsize};
+pub use snapshot::SnapshotHandle;
+use std::{
+    hash::Hash,
+    ops::RangeBounds,
+    sync::{atomic::AtomicUsize, Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+use sync::Recover;
+pub use tiered::{Tier, TieredCache};
+pub use trace::{Trace, TraceHandle, TraceOp, TraceRecord, TraceSummary};
+pub use two_tier::TwoTierCache;
+pub use warm::WarmProgress;
+pub use weights::WeightBucket;
+pub use write_behind::{WriteBehind, Writer};
+pub use write_through::{Store, WriteThrough};
+mod async_cache;
+mod audit;
+mod autotune;
+mod bloom;
+mod bounded;
+mod checksum;
+mod dedup;
+mod dirty;
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod frequency;
+mod idle;
+mod index;
+pub mod inspect;
+mod intern;
+mod invalidation;
+mod json;
+mod lifetime;
+mod loader;
 pub mod lru;
+#[cfg(feature = "memcached")]
+pub mod memcached;
+mod mmap;
+pub mod noop;
+mod partitioned;
+mod persistence;
+mod persistent;
+pub mod pressure;
+pub mod prometheus;
+mod recorder;
+#[cfg(feature = "remote")]
+pub mod remote;
+mod report;
+mod shard;
+mod snapshot;
+mod spill;
+mod sync;
+#[cfg(test)]
+mod testing;
+mod tiered;
+mod trace;
+mod two_tier;
 pub mod unbounded;
+mod wal;
+mod warm;
+mod weights;
+mod window;
+mod write_behind;
+mod write_through;
 
 #[derive(Clone)]
 pub enum Cache<K, V>
@@ -12,7 +95,11 @@ where
 {
     LRU(lru::LRU<K, V>),
     Unbounded(unbounded::Unbounded<K, V>),
+    /// Caching disabled: nothing is stored or counted
     None,
+    /// Caching disabled, with lookups and inserts still counted, see
+    /// [`Cache::new_noop`]
+    Noop(noop::Noop),
 }
 
 impl<K, V> Cache<K, V>
@@ -24,16 +111,85 @@ where
         Cache::LRU(lru::LRU::new(capacity))
     }
 
+    /// Creates an LRU cache whose capacity is a total weight, such as a
+    /// number of bytes, rather than a number of entries.
+    ///
+    /// `weigher` gives the weight of each entry when it is inserted, and the
+    /// least recently used entries are evicted until the total is back under
+    /// `max_weight`. An entry heavier than `max_weight` is evicted at once,
+    /// and one the weigher panics on weighs nothing.
+    pub fn new_weighted(
+        max_weight: usize,
+        weigher: impl Fn(&K, &V) -> u32 + Send + Sync + 'static,
+    ) -> Self {
+        Cache::LRU(lru::LRU::with_weigher(
+            usize::MAX,
+            max_weight,
+            Some(Box::new(weigher)),
+        ))
+    }
+
+    /// Creates an LRU cache holding at most `capacity` entries with a total
+    /// weight of at most `max_weight`, evicting when either is exceeded.
+    ///
+    /// Weights work as for [`Cache::new_weighted`]; with
+    /// [`lru::estimated_size`] as the weigher, `max_weight` is in bytes.
+    pub fn new_lru_weighted(
+        capacity: usize,
+        max_weight: usize,
+        weigher: impl Fn(&K, &V) -> u32 + Send + Sync + 'static,
+    ) -> Self {
+        Cache::LRU(lru::LRU::with_weigher(
+            capacity,
+            max_weight,
+            Some(Box::new(weigher)),
+        ))
+    }
+
+    /// Creates an LRU cache that keeps the estimated memory used by its
+    /// entries under `max_bytes`.
+    ///
+    /// An entry is estimated as the size of its key and value when
+    /// serialized, plus the inline size of the key twice, for the map and
+    /// the recency order, and of the value. Use [`Cache::new_weighted`] for
+    /// a more exact weigher.
+    pub fn new_memory_bounded(max_bytes: usize) -> Self {
+        Cache::new_weighted(max_bytes, lru::estimated_size)
+    }
+
+    /// Creates an LRU cache of at most `capacity` entries that handles a new
+    /// key when full as `policy` says: evicting, refusing the insert, or
+    /// spilling the least recently used entry to disk.
+    ///
+    /// Spilling fails if its scratch file cannot be created. A refused
+    /// insert leaves the cache as it was; concurrent inserts of new keys may
+    /// still evict when they race for the last free place. Use
+    /// [`BoundedCache`] where nothing may ever be evicted.
+    pub fn new_bounded(capacity: usize, policy: OverflowPolicy) -> Result<Self> {
+        let cache = lru::LRU::new(capacity);
+        cache.set_overflow_policy(policy)?;
+        Ok(Cache::LRU(cache))
+    }
+
+    /// Creates a disabled cache that stores nothing, but counts every lookup
+    /// as a miss and every insert, so its statistics can be compared with
+    /// those of an enabled cache.
+    pub fn new_noop() -> Self {
+        Cache::Noop(noop::Noop::new())
+    }
+
     pub fn new_unbounded() -> Self {
         Cache::Unbounded(unbounded::Unbounded::new())
     }
 
+    /// Returns whether caching is disabled, with [`Cache::None`] or
+    /// [`Cache::Noop`].
     pub fn is_none(&self) -> bool {
-        matches!(self, Cache::None)
+        matches!(self, Cache::None | Cache::Noop(_))
     }
 
     pub fn is_some(&self) -> bool {
-        !matches!(self, Cache::None)
+        !self.is_none()
     }
 
     pub fn insert(&self, key: K, value: V) {
@@ -41,6 +197,24 @@ where
             Cache::LRU(cache) => cache.insert(key, value),
             Cache::Unbounded(cache) => cache.insert(key, value),
             Cache::None => {}
+            Cache::Noop(cache) => cache.insert(),
+        }
+    }
+
+    /// Inserts an entry whose weight the caller already knows, such as the
+    /// length of a response body, instead of having the weigher compute it.
+    ///
+    /// The cost counts against the weight limit and the limit set with
+    /// [`Cache::set_max_entry_weight`] like a weigher's would. Caches
+    /// without a weigher ignore it. Costs are not persisted: entries read
+    /// from snapshots or logs, or brought back from disk, are weighed by the
+    /// weigher again.
+    pub fn insert_with_cost(&self, key: K, value: V, cost: u32) {
+        match self {
+            Cache::LRU(cache) => cache.insert_with_cost(key, value, cost),
+            Cache::Unbounded(cache) => cache.insert(key, value),
+            Cache::None => {}
+            Cache::Noop(cache) => cache.insert(),
         }
     }
 
@@ -49,6 +223,20 @@ where
             Cache::LRU(cache) => cache.get(key),
             Cache::Unbounded(cache) => cache.get(key),
             Cache::None => None,
+            Cache::Noop(cache) => {
+                cache.get();
+                None
+            }
+        }
+    }
+
+    /// Returns the value for `key` without counting a hit or miss, and
+    /// without making it the most recently used entry.
+    pub fn peek(&self, key: &K) -> Option<V> {
+        match self {
+            Cache::LRU(cache) => cache.peek(key),
+            Cache::Unbounded(cache) => cache.peek(key),
+            Cache::None | Cache::Noop(_) => None,
         }
     }
 
@@ -56,7 +244,7 @@ where
         match self {
             Cache::LRU(cache) => cache.remove(key),
             Cache::Unbounded(cache) => cache.remove(key),
-            Cache::None => None,
+            Cache::None | Cache::Noop(_) => None,
         }
     }
 
@@ -64,7 +252,7 @@ where
         match self {
             Cache::LRU(cache) => cache.clear(),
             Cache::Unbounded(cache) => cache.clear(),
-            Cache::None => {}
+            Cache::None | Cache::Noop(_) => {}
         }
     }
 
@@ -72,7 +260,7 @@ where
         match self {
             Cache::LRU(cache) => cache.len(),
             Cache::Unbounded(cache) => cache.len(),
-            Cache::None => 0,
+            Cache::None | Cache::Noop(_) => 0,
         }
     }
 
@@ -80,7 +268,7 @@ where
         match self {
             Cache::LRU(cache) => cache.is_empty(),
             Cache::Unbounded(cache) => cache.is_empty(),
-            Cache::None => true,
+            Cache::None | Cache::Noop(_) => true,
         }
     }
 
@@ -88,7 +276,7 @@ where
         match self {
             Cache::LRU(cache) => cache.hits(),
             Cache::Unbounded(cache) => cache.hits(),
-            Cache::None => 0,
+            Cache::None | Cache::Noop(_) => 0,
         }
     }
 
@@ -97,40 +285,1059 @@ where
             Cache::LRU(cache) => cache.misses(),
             Cache::Unbounded(cache) => cache.misses(),
             Cache::None => 0,
+            Cache::Noop(cache) => cache.misses(),
+        }
+    }
+
+    /// Returns the number of entries evicted to stay within capacity, or
+    /// purged for being idle from unbounded caches.
+    ///
+    /// Entries removed through [`Cache::remove`] or [`Cache::clear`] are
+    /// not counted.
+    pub fn evictions(&self) -> usize {
+        match self {
+            Cache::LRU(cache) => cache.evictions(),
+            Cache::Unbounded(cache) => cache.evictions(),
+            Cache::None | Cache::Noop(_) => 0,
         }
     }
 
+    /// Returns the share of lookups since the cache was created that were
+    /// hits, or 0 if there were none.
+    pub fn hit_ratio(&self) -> f64 {
+        self.stats().hit_ratio()
+    }
+
+    /// Returns the share of lookups within the last `window` that were hits,
+    /// or 0 if there were none.
+    ///
+    /// Lookups are counted per minute, so the window is rounded up to whole
+    /// minutes, including the current one, and is at most an hour long.
+    /// Lookups counted in a snapshot read into the cache are not included.
+    pub fn recent_hit_ratio(&self, window: Duration) -> f64 {
+        match self {
+            Cache::LRU(cache) => cache.recent_hit_ratio(window),
+            Cache::Unbounded(cache) => cache.recent_hit_ratio(window),
+            Cache::None => 0.0,
+            Cache::Noop(cache) => cache.recent_hit_ratio(window),
+        }
+    }
+
+    /// Returns all statistics of the cache at once.
+    ///
+    /// Inserts, updates and removals count the calls made since the cache
+    /// was created; unlike hits, misses and evictions they are not stored
+    /// in snapshots.
+    pub fn stats(&self) -> CacheStats {
+        match self {
+            Cache::LRU(cache) => cache.stats(),
+            Cache::Unbounded(cache) => cache.stats(),
+            Cache::None => CacheStats::default(),
+            Cache::Noop(cache) => cache.stats(),
+        }
+    }
+
+    /// Returns the statistics of each shard of the map holding the entries.
+    ///
+    /// Keys are spread over the shards by hash, so a shard holding far more
+    /// entries or lookups than the others points at a skewed key
+    /// distribution or hasher. Spilled entries are not counted, and counting
+    /// the others takes a pass over the map.
+    pub fn shard_stats(&self) -> Vec<ShardStats> {
+        match self {
+            Cache::LRU(cache) => cache.shard_stats(),
+            Cache::Unbounded(cache) => cache.shard_stats(),
+            Cache::None => Vec::new(),
+            Cache::Noop(cache) => cache.shard_stats(),
+        }
+    }
+
+    /// Sets all counters back to zero, including the ones behind
+    /// [`Cache::recent_hit_ratio`].
+    pub fn reset_stats(&self) {
+        match self {
+            Cache::LRU(cache) => cache.reset_stats(),
+            Cache::Unbounded(cache) => cache.reset_stats(),
+            Cache::None => {}
+            Cache::Noop(cache) => cache.reset_stats(),
+        }
+    }
+
+    /// Returns when the cache was created, or when the oldest snapshot
+    /// read into it was created.
+    pub fn created(&self) -> Option<SystemTime> {
+        match self {
+            Cache::LRU(cache) => Some(cache.created()),
+            Cache::Unbounded(cache) => Some(cache.created()),
+            Cache::None => None,
+            Cache::Noop(cache) => Some(cache.created()),
+        }
+    }
+
+    /// Rewrites the snapshot at `file_name` in the current format.
+    ///
+    /// Older snapshots, including the bare bincode files written before
+    /// snapshots had a header, stay readable with [`Cache::read`]; migrating
+    /// them adds the checksums and type checks of the current format.
+    pub fn migrate(file_name: &str) -> Result<()> {
+        persistence::migrate::<K, V>(file_name)
+    }
+
+    /// Reads only the metadata of the snapshot at `file_name`.
+    ///
+    /// Returns `None` for snapshots written before metadata was stored.
+    pub fn read_metadata(file_name: &str) -> Result<Option<SnapshotMetadata>> {
+        persistence::read_metadata_of::<K, V>(file_name)
+    }
+
+    /// Returns an iterator over clones of all entries, in the order they are
+    /// persisted (least to most recently used for LRU caches).
+    ///
+    /// Together with [`Cache::import`] this allows moving cache contents to
+    /// and from any storage, not just files.
+    pub fn export(&self) -> Box<dyn Iterator<Item = (K, V)> + '_> {
+        match self {
+            Cache::LRU(cache) => Box::new(cache.export()),
+            Cache::Unbounded(cache) => Box::new(cache.export()),
+            Cache::None | Cache::Noop(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    /// Writes the statistics and up to `limit` entries of the cache to
+    /// `writer` as pretty-printed JSON, for bug reports.
+    ///
+    /// Entries are listed from the most recently used, with their rank as
+    /// `recency` for LRU caches, and spilled entries are left out. With
+    /// `max_value_len`, longer strings and arrays within values are cut
+    /// short. Keys and values appear as their `Serialize` impls describe
+    /// them; the output is not meant to be read back.
+    pub fn dump_json(
+        &self,
+        writer: impl std::io::Write,
+        limit: usize,
+        max_value_len: Option<usize>,
+    ) -> Result<()> {
+        json::dump(self, writer, limit, max_value_len)
+    }
+
+    /// Returns the keys of the entries in memory for which `predicate` is
+    /// true, in no particular order, e.g. all sessions of a user.
+    ///
+    /// This looks at every entry, holding a lock on part of the cache while
+    /// calling `predicate`, which must not use the cache. Spilled entries
+    /// are not looked at. For frequent lookups, see [`Cache::index_by`].
+    pub fn find(&self, predicate: impl Fn(&K, &V) -> bool) -> Vec<K> {
+        match self {
+            Cache::LRU(cache) => cache.find(predicate),
+            Cache::Unbounded(cache) => cache.find(predicate),
+            Cache::None | Cache::Noop(_) => Vec::new(),
+        }
+    }
+
+    /// Returns an index of the keys by what `extractor` takes from their
+    /// entries, kept up to date as entries are inserted, updated, removed
+    /// and evicted until the index is dropped.
+    ///
+    /// The entries in the cache are indexed at once, which should be done
+    /// while it is not being changed. Like [`Cache::on_insert`] hooks, the
+    /// index does not see entries added by reading snapshots or replaying
+    /// logs after it is created. Lookups check the keys they find against
+    /// the cache, so they never return keys that are gone.
+    pub fn index_by<I>(
+        &self,
+        extractor: impl Fn(&K, &V) -> I + Send + Sync + 'static,
+    ) -> SecondaryIndex<K, I>
+    where
+        I: Eq + Hash + Clone + Send + Sync + 'static,
+    {
+        let entries = Arc::new(IndexEntries::new());
+        let extractor = Arc::new(extractor);
+        let (adding, extracting) = (entries.clone(), extractor.clone());
+        let on_insert = move |key: &K, value: &V| adding.add(key, extracting(key, value));
+        let removing = entries.clone();
+        let on_removal = move |key: K, _: V, cause| {
+            if cause != RemovalCause::Replaced {
+                removing.remove(&key);
+            }
+        };
+        let unregister: Box<dyn FnOnce() + Send + Sync> = match self {
+            Cache::LRU(cache) => {
+                let hooks = (cache.on_insert(on_insert), cache.on_removal(on_removal));
+                let cache = cache.clone();
+                Box::new(move || cache.remove_hooks(hooks.0, hooks.1))
+            }
+            Cache::Unbounded(cache) => {
+                let hooks = (cache.on_insert(on_insert), cache.on_removal(on_removal));
+                let cache = cache.clone();
+                Box::new(move || cache.remove_hooks(hooks.0, hooks.1))
+            }
+            Cache::None | Cache::Noop(_) => Box::new(|| {}),
+        };
+        for (key, value) in self.export() {
+            entries.add(&key, extractor(&key, &value));
+        }
+        let cache = self.clone();
+        let is_live = move |key: &K, property: &I| {
+            cache
+                .peek(key)
+                .is_some_and(|value| extractor(key, &value) == *property)
+        };
+        SecondaryIndex::new(entries, is_live, unregister)
+    }
+
+    /// Inserts all entries from the iterator, in order.
+    pub fn import(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        match self {
+            Cache::LRU(cache) => cache.import(entries),
+            Cache::Unbounded(cache) => cache.import(entries),
+            Cache::None | Cache::Noop(_) => {}
+        }
+    }
+
+    /// Loads the values of `keys` with `load` on up to `concurrency`
+    /// threads and caches them, e.g. before serving traffic so the first
+    /// requests do not all miss.
+    ///
+    /// Keys already cached are not loaded again. Failed or panicking loads
+    /// are reported to the [`set_error_hook`] hook and skipped. `progress`
+    /// is called after each key, in order of completion, and the final
+    /// report is returned.
+    pub fn warm(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        concurrency: usize,
+        load: impl Fn(&K) -> Result<V> + Sync,
+        progress: impl Fn(WarmProgress) + Sync,
+    ) -> WarmProgress {
+        let keys = keys.into_iter().collect();
+        warm::warm(self, keys, concurrency, load, progress)
+    }
+
     pub fn write(&self, file_name: &str) -> Result<()> {
         match self {
             Cache::LRU(cache) => cache.write(file_name),
             Cache::Unbounded(cache) => cache.write(file_name),
-            Cache::None => Ok(()),
+            Cache::None | Cache::Noop(_) => Ok(()),
         }
     }
 
+    /// Reads the entries from `file_name`, overwriting existing entries with
+    /// the same keys. Use [`Cache::read_with`] to choose another [`ReadMode`].
     pub fn read(&self, file_name: &str) -> Result<()> {
+        self.read_with(file_name, ReadMode::Merge)
+    }
+
+    /// Reads the entries from `file_name`, combining them with the existing
+    /// entries according to `mode`.
+    ///
+    /// The file is fully decoded before the cache is touched, so a failed
+    /// read leaves the cache unchanged.
+    ///
+    /// LRU caches load the entries in the recency order they were written
+    /// in, as more recent than the entries already cached. If the snapshot
+    /// holds more entries than the capacity, only the most recently used
+    /// ones are loaded.
+    pub fn read_with(&self, file_name: &str, mode: ReadMode) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.read(file_name, mode, &ReadLimits::default()),
+            Cache::Unbounded(cache) => cache.read(file_name, mode, &ReadLimits::default()),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Reads the entries from `file_name` like [`Cache::read`], but fails
+    /// with [`PersistenceError::LimitExceeded`] before loading anything if
+    /// the file goes over `limits`, for files from untrusted sources.
+    ///
+    /// The sizes are checked before the memory for them is allocated: the
+    /// file size before it is read, and entry counts and sizes while they
+    /// are decoded. Files
+    /// in the oldest format, without frames, have no entry sizes checked.
+    pub fn read_limited(&self, file_name: &str, limits: &ReadLimits) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.read(file_name, ReadMode::Merge, limits),
+            Cache::Unbounded(cache) => cache.read(file_name, ReadMode::Merge, limits),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Reads as much of a damaged snapshot as possible.
+    ///
+    /// Entries are checksummed in chunks, so a damaged chunk is skipped as a
+    /// whole while the intact chunks around it are still loaded. The report
+    /// says how many entries were recovered and how many were lost.
+    pub fn read_lossy(&self, file_name: &str) -> Result<LoadReport> {
+        match self {
+            Cache::LRU(cache) => cache.read_lossy(file_name),
+            Cache::Unbounded(cache) => cache.read_lossy(file_name),
+            Cache::None | Cache::Noop(_) => Ok(LoadReport::default()),
+        }
+    }
+
+    /// Reads only the entries whose keys satisfy `predicate`.
+    ///
+    /// Entries that are filtered out are dropped as soon as they are decoded,
+    /// so a worker can load its partition of a shared snapshot without
+    /// holding the whole snapshot in the cache.
+    pub fn read_filtered(
+        &self,
+        file_name: &str,
+        mut predicate: impl FnMut(&K) -> bool,
+    ) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.read_filtered(file_name, &mut predicate, usize::MAX),
+            Cache::Unbounded(cache) => cache.read_filtered(file_name, &mut predicate, usize::MAX),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Reads the entries whose keys fall within `range`, stopping after
+    /// `limit` entries if one is given.
+    pub fn read_range(
+        &self,
+        file_name: &str,
+        range: impl RangeBounds<K>,
+        limit: Option<usize>,
+    ) -> Result<()>
+    where
+        K: Ord,
+    {
+        let mut keep = |key: &K| range.contains(key);
+        let limit = limit.unwrap_or(usize::MAX);
+        match self {
+            Cache::LRU(cache) => cache.read_filtered(file_name, &mut keep, limit),
+            Cache::Unbounded(cache) => cache.read_filtered(file_name, &mut keep, limit),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Writes an incremental snapshot to `file_name`.
+    ///
+    /// The first call writes a full snapshot and starts tracking changed
+    /// keys. Later calls only append the entries changed since the previous
+    /// call to a delta log at `<file_name>.delta`, which is folded into a new
+    /// full snapshot once it grows larger than the snapshot itself. Read the
+    /// result with [`Cache::read_incremental`].
+    pub fn write_incremental(&self, file_name: &str) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.write_incremental(file_name),
+            Cache::Unbounded(cache) => cache.write_incremental(file_name),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Reads a snapshot written by [`Cache::write_incremental`], applying
+    /// its delta log on top.
+    pub fn read_incremental(&self, file_name: &str) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.read_incremental(file_name),
+            Cache::Unbounded(cache) => cache.read_incremental(file_name),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Writes the cache as `shards` files serialized in parallel.
+    ///
+    /// The shards are written to `<file_name>.<index>` and `file_name` holds
+    /// a small manifest; pass the same `file_name` to [`Cache::read_sharded`].
+    pub fn write_sharded(&self, file_name: &str, shards: usize) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.write_sharded(file_name, shards),
+            Cache::Unbounded(cache) => cache.write_sharded(file_name, shards),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Reads a cache written by [`Cache::write_sharded`], loading the shards in parallel.
+    pub fn read_sharded(&self, file_name: &str) -> Result<()> {
+        self.read_sharded_limited(file_name, &ReadLimits::default())
+    }
+
+    /// Reads a cache written by [`Cache::write_sharded`] like
+    /// [`Cache::read_sharded`], but fails with
+    /// [`PersistenceError::LimitExceeded`] before loading anything if the
+    /// shards taken together go over `limits`.
+    pub fn read_sharded_limited(&self, file_name: &str, limits: &ReadLimits) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.read_sharded(file_name, limits),
+            Cache::Unbounded(cache) => cache.read_sharded(file_name, limits),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Enables the append-only write-ahead log at `file_name`.
+    ///
+    /// Any records already in the log are replayed into the cache first, so
+    /// recovering after a crash is `read()` of the last snapshot followed by
+    /// `enable_wal()` of the log. Every later insert, remove and clear is
+    /// appended to the log before it is applied.
+    pub fn enable_wal(&self, file_name: &str) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.enable_wal(file_name),
+            Cache::Unbounded(cache) => cache.enable_wal(file_name),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Makes an LRU cache write evicted entries to a scratch file at
+    /// `file_name` instead of dropping them.
+    ///
+    /// A later `get` of a spilled key reads it back and promotes it to the
+    /// most recently used entry, so eviction becomes demotion to disk.
+    /// Spilled entries are not part of snapshots, and the scratch file is
+    /// deleted when the cache is dropped. Unbounded caches never evict, so
+    /// enabling spillover on them is an error.
+    pub fn enable_spillover(&self, file_name: &str) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.enable_spillover(file_name),
+            Cache::Unbounded(_) => Err(anyhow::anyhow!(
+                "Only LRU caches evict entries that can be spilled"
+            )),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Makes an LRU cache keep a bloom filter of its keys, sized for about
+    /// `expected` of them, so that `get` of a key the filter rules out
+    /// misses without looking in the map or locking the recency order.
+    ///
+    /// Around 1% of absent keys still take the slow path at the expected
+    /// size, more once the cache holds more keys. With spillover, enable
+    /// the filter before anything is spilled, as spilled keys are counted.
+    pub fn enable_bloom_filter(&self, expected: usize) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.enable_bloom_filter(expected),
+            Cache::Unbounded(_) => Err(anyhow::anyhow!(
+                "Only LRU caches keep a bloom filter of their keys"
+            )),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Calls `listener` with each entry evicted from the cache from now on.
+    ///
+    /// Listeners run on the thread whose insert caused the eviction, so they
+    /// should hand slow work off elsewhere. Entries spilled to disk are not
+    /// evicted in this sense, and neither are removed or cleared ones.
+    /// Unbounded caches never evict. A listener that panics is reported to
+    /// the [`set_error_hook`] hook and skipped, so the other listeners and
+    /// the cache carry on.
+    pub fn on_evict(&self, listener: impl Fn(K, V) + Send + Sync + 'static) {
+        if let Cache::LRU(cache) = self {
+            cache.on_removal(move |key, value, cause| {
+                if cause == RemovalCause::Evicted {
+                    listener(key, value);
+                }
+            });
+        }
+    }
+
+    /// Calls `hook` after each [`Cache::insert`] from now on.
+    ///
+    /// Hooks run on the inserting thread, so they should be quick. Entries
+    /// added by reading snapshots or replaying logs do not run them. A cache
+    /// without hooks pays nothing for them.
+    pub fn on_insert(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) {
+        match self {
+            Cache::LRU(cache) => {
+                cache.on_insert(hook);
+            }
+            Cache::Unbounded(cache) => {
+                cache.on_insert(hook);
+            }
+            Cache::None | Cache::Noop(_) => {}
+        }
+    }
+
+    /// Makes a weighted cache reject entries weighing more than
+    /// `max_weight`, instead of evicting others to make room for them.
+    ///
+    /// A rejected [`Cache::insert`] removes the value it would have
+    /// replaced, as that is out of date, and calls the hooks given to
+    /// [`Cache::on_reject`]. Caches without a weigher return an error.
+    pub fn set_max_entry_weight(&self, max_weight: usize) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.set_max_entry_weight(max_weight),
+            Cache::Unbounded(_) => Err(anyhow::anyhow!(
+                "Only weighted caches can limit the weight of entries"
+            )),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Calls `hook` with each entry rejected for its weight, see
+    /// [`Cache::set_max_entry_weight`], or because the cache is full, see
+    /// [`OverflowPolicy::Reject`], from now on.
+    pub fn on_reject(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) {
         match self {
-            Cache::LRU(cache) => cache.read(file_name),
-            Cache::Unbounded(cache) => cache.read(file_name),
+            Cache::LRU(cache) => cache.on_reject(hook),
+            Cache::Unbounded(_) | Cache::None | Cache::Noop(_) => {}
+        }
+    }
+
+    /// Calls `hook` with each entry found by [`Cache::get`] from now on.
+    ///
+    /// Hooks run on the looking-up thread, so they should be quick. Use
+    /// [`Cache::peek`] for lookups that should not run them.
+    pub fn on_hit(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) {
+        match self {
+            Cache::LRU(cache) => cache.on_hit(hook),
+            Cache::Unbounded(cache) => cache.on_hit(hook),
+            Cache::None | Cache::Noop(_) => {}
+        }
+    }
+
+    /// Calls `listener` once with each entry that leaves the cache from now
+    /// on, and why.
+    ///
+    /// Removed, cleared, invalidated, overwritten and evicted entries are all
+    /// reported, whatever caused the change, including replaying a log.
+    /// Entries spilled to disk are reported when they leave the spill store.
+    /// Listeners run on the thread making the change, so they should be
+    /// quick. While a listener is registered, values overwritten or cleared
+    /// are read back from the spill store to be passed to it.
+    pub fn on_removal(&self, listener: impl Fn(K, V, RemovalCause) + Send + Sync + 'static) {
+        match self {
+            Cache::LRU(cache) => {
+                cache.on_removal(listener);
+            }
+            Cache::Unbounded(cache) => {
+                cache.on_removal(listener);
+            }
+            Cache::None | Cache::Noop(_) => {}
+        }
+    }
+
+    /// Returns a receiver of the changes made to the cache from now on.
+    ///
+    /// Every subscriber gets every event, in the order the changes were
+    /// made, including changes replayed from snapshots and logs.
+    pub fn subscribe(&self) -> Receiver<CacheEvent<K>> {
+        match self {
+            Cache::LRU(cache) => cache.subscribe(),
+            Cache::Unbounded(cache) => cache.subscribe(),
+            // Closed at once, as there will never be any events
+            Cache::None | Cache::Noop(_) => events::Broadcast::new().subscribe(),
+        }
+    }
+
+    /// Like [`Cache::subscribe`], but buffers up to `capacity` events and
+    /// handles a full buffer as `overflow` says.
+    ///
+    /// With [`Overflow::Block`], every change to the cache waits while the
+    /// receiver is full, so the receiver must not be read on a thread that
+    /// also changes the cache.
+    pub fn subscribe_with(&self, capacity: usize, overflow: Overflow) -> Receiver<CacheEvent<K>> {
+        match self {
+            Cache::LRU(cache) => cache.subscribe_with(capacity, overflow),
+            Cache::Unbounded(cache) => cache.subscribe_with(capacity, overflow),
+            Cache::None | Cache::Noop(_) => {
+                events::Broadcast::new().subscribe_with(capacity, overflow)
+            }
+        }
+    }
+
+    /// Sends the statistics of the cache to `recorder` instead of counting
+    /// them, see [`StatsRecorder`]. A cache can have one recorder only.
+    ///
+    /// The built-in atomic counters are the default recorder, and are
+    /// replaced by this one: [`Cache::stats`] and everything built on it,
+    /// such as autotuning, the Prometheus metrics and
+    /// [`Cache::log_stats_every`], stop where they were. A [`NoopRecorder`]
+    /// leaves the cache without the overhead of counting.
+    pub fn set_stats_recorder(&self, recorder: Arc<dyn StatsRecorder>) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.set_stats_recorder(recorder),
+            Cache::Unbounded(cache) => cache.set_stats_recorder(recorder),
             Cache::None => Ok(()),
+            Cache::Noop(cache) => cache.set_stats_recorder(recorder),
+        }
+    }
+
+    /// Joins the cache to an invalidation bus shared with other caches.
+    ///
+    /// Every key removed with [`Cache::remove`] is then published on the bus,
+    /// and keys published by the other caches are removed from this one. A
+    /// cache can join one bus only.
+    pub fn join_invalidation(&self, bus: Arc<dyn InvalidationBus<K>>) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.join_invalidation(bus),
+            Cache::Unbounded(cache) => cache.join_invalidation(bus),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Starts recording every lookup, insert, removal and clear to
+    /// `file_name` as the time, the operation and a hash of the key, until
+    /// the returned handle is stopped or dropped.
+    ///
+    /// Records are handed to a writer thread without blocking; if it falls
+    /// behind, records are dropped and counted rather than slowing the
+    /// cache down. Read the file with [`Trace::read`] and replay it against
+    /// caches of other capacities with [`Trace::replay`]. A cache can be
+    /// traced once.
+    pub fn record_trace(&self, file_name: &str) -> Result<TraceHandle> {
+        match self {
+            Cache::LRU(cache) => cache.record_trace(file_name),
+            Cache::Unbounded(cache) => cache.record_trace(file_name),
+            Cache::None | Cache::Noop(_) => Err(anyhow::anyhow!(
+                "Only LRU and unbounded caches can be traced"
+            )),
+        }
+    }
+
+    /// Starts recording every change made to the cache, with its time and
+    /// the name of the thread that made it.
+    ///
+    /// The last `capacity` changes are kept for [`Cache::recent_mutations`],
+    /// and with `file_name`, every change is appended to that file as a line
+    /// of text. Evictions and changes replayed from snapshots and logs are
+    /// recorded too. An audit log can be enabled once per cache.
+    pub fn enable_audit(&self, capacity: usize, file_name: Option<&str>) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.enable_audit(capacity, file_name),
+            Cache::Unbounded(cache) => cache.enable_audit(capacity, file_name),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Returns the last `n` changes recorded by the audit log, oldest first,
+    /// or nothing if no audit log is enabled.
+    pub fn recent_mutations(&self, n: usize) -> Vec<Mutation<K>> {
+        match self {
+            Cache::LRU(cache) => cache.recent_mutations(n),
+            Cache::Unbounded(cache) => cache.recent_mutations(n),
+            Cache::None | Cache::Noop(_) => Vec::new(),
+        }
+    }
+
+    /// Starts counting lookups and misses per key, keeping the `tracked`
+    /// hottest keys of each.
+    ///
+    /// Counts are approximate: they come from count-min sketches, so a key
+    /// may be credited with some lookups of others. Tracking can be enabled
+    /// once per cache and costs a few atomic increments per lookup.
+    pub fn track_hot_keys(&self, tracked: usize) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.track_hot_keys(tracked),
+            Cache::Unbounded(cache) => cache.track_hot_keys(tracked),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Returns up to `n` of the most looked up keys with their approximate
+    /// lookup counts, hottest first, or nothing if hot keys are not tracked.
+    ///
+    /// Misses count as lookups too, so keys that keep missing show up here.
+    pub fn hottest(&self, n: usize) -> Vec<(K, u64)> {
+        match self {
+            Cache::LRU(cache) => cache.hottest(n),
+            Cache::Unbounded(cache) => cache.hottest(n),
+            Cache::None | Cache::Noop(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the approximate number of lookups of `key`, hits and misses,
+    /// since hot keys started being tracked, or 0 if they are not.
+    ///
+    /// The estimate comes from the same count-min sketch as
+    /// [`Cache::hottest`], so it may be too high but never too low, and
+    /// works for any key, not only the hottest. Applications can base
+    /// their own prefetching or pinning on it.
+    pub fn estimated_frequency(&self, key: &K) -> u64 {
+        match self {
+            Cache::LRU(cache) => cache.estimated_frequency(key),
+            Cache::Unbounded(cache) => cache.estimated_frequency(key),
+            Cache::None | Cache::Noop(_) => 0,
+        }
+    }
+
+    /// Returns up to `n` of the most missed keys with their approximate miss
+    /// counts, most missed first, or nothing if hot keys are not tracked.
+    ///
+    /// Keys missed often are candidates for pre-warming, or are being
+    /// removed or evicted too eagerly.
+    pub fn top_misses(&self, n: usize) -> Vec<(K, u64)> {
+        match self {
+            Cache::LRU(cache) => cache.top_misses(n),
+            Cache::Unbounded(cache) => cache.top_misses(n),
+            Cache::None | Cache::Noop(_) => Vec::new(),
+        }
+    }
+
+    /// Sets the most entries an LRU cache holds, and returns how many of
+    /// the least recently used entries were evicted at once to get down to
+    /// a smaller capacity.
+    ///
+    /// Unbounded caches have no capacity to set, and evict nothing.
+    pub fn set_capacity(&self, capacity: usize) -> usize {
+        match self {
+            Cache::LRU(cache) => cache.set_capacity(capacity),
+            Cache::Unbounded(_) | Cache::None | Cache::Noop(_) => 0,
+        }
+    }
+
+    /// Lets an LRU cache use only `share` of its capacity, and of its total
+    /// weight limit, until scaled back to 1, evicting the least recently
+    /// used entries right away. Returns how many were evicted.
+    ///
+    /// Unlike [`Cache::set_capacity`] the configured capacity is kept, so
+    /// the cache can grow back once memory is available again.
+    pub fn scale_capacity(&self, share: f64) -> usize {
+        match self {
+            Cache::LRU(cache) => cache.scale_capacity(share),
+            Cache::Unbounded(_) | Cache::None | Cache::Noop(_) => 0,
+        }
+    }
+
+    /// Drops the entries of an unbounded cache that were neither inserted
+    /// nor hit for `max_idle`, checking every `interval` on a background
+    /// thread.
+    ///
+    /// Purged entries count as removals, not evictions, as for
+    /// [`Cache::remove`]. Use of entries is recorded from the first call on,
+    /// with entries already cached counting as used then. Flushing the
+    /// returned handle purges immediately. LRU caches are bounded already,
+    /// so this is an error for them.
+    pub fn purge_idle_every(
+        &self,
+        max_idle: Duration,
+        interval: Duration,
+    ) -> Result<SnapshotHandle> {
+        match self {
+            Cache::Unbounded(cache) => {
+                cache.track_idle();
+                let cache = cache.clone();
+                Ok(SnapshotHandle::spawn_idle_purge(interval, move || {
+                    cache.purge_idle(max_idle);
+                }))
+            }
+            _ => Err(anyhow::anyhow!("Only unbounded caches purge idle entries")),
+        }
+    }
+
+    /// Scales an LRU cache to the share of its capacity returned by `share`
+    /// every `interval` on a background thread, see [`Cache::scale_capacity`].
+    ///
+    /// `share` reads the memory pressure, e.g. [`pressure::cgroup`] for the
+    /// memory limit of a container, so the cache yields memory while it is
+    /// scarce and grows back afterwards. Flushing the returned handle reads
+    /// the pressure immediately. Unbounded caches cannot shrink, so
+    /// this is an error for them.
+    pub fn shrink_under_pressure(
+        &self,
+        interval: Duration,
+        share: impl FnMut() -> f64 + Send + 'static,
+    ) -> Result<SnapshotHandle> {
+        match self {
+            Cache::Unbounded(_) => Err(anyhow::anyhow!(
+                "Only LRU caches can shrink under memory pressure"
+            )),
+            _ => Ok(SnapshotHandle::spawn_pressure(
+                self.clone(),
+                interval,
+                share,
+            )),
+        }
+    }
+
+    /// Adjusts the capacity of an LRU cache every `interval` on a background
+    /// thread, within the bounds of `config`, toward its target hit ratio.
+    ///
+    /// The cache remembers the keys it evicted last: while the hit ratio is
+    /// under the target and enough misses are of those keys, it grows by a
+    /// step. While few hits are of the entries a step would drop, it shrinks.
+    /// Flushing the returned handle tunes immediately. Unbounded caches and
+    /// caches limited only by weight have no capacity to tune, so this is an
+    /// error for them.
+    pub fn autotune(&self, config: Autotune, interval: Duration) -> Result<SnapshotHandle> {
+        match self {
+            Cache::LRU(cache) if cache.has_entry_limit() => {
+                let cache = cache.clone();
+                Ok(SnapshotHandle::spawn_autotune(interval, move || {
+                    cache.autotune(&config);
+                }))
+            }
+            _ => Err(anyhow::anyhow!(
+                "Only LRU caches with a capacity in entries can be autotuned"
+            )),
+        }
+    }
+
+    /// Makes an LRU cache that goes over its capacity evict down to `share`
+    /// of it in one batch, rather than one entry at a time at exactly its
+    /// capacity.
+    ///
+    /// With a weigher the total weight is evicted down to the same share of
+    /// its limit. `share` must be above 0 and at most 1, which is the default.
+    pub fn set_low_watermark(&self, share: f64) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.set_low_watermark(share),
+            Cache::Unbounded(_) => Err(anyhow::anyhow!(
+                "Only LRU caches evict entries down to a watermark"
+            )),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Returns the total weight of the entries in memory, for caches created
+    /// with [`Cache::new_weighted`].
+    pub fn weight(&self) -> Option<usize> {
+        match self {
+            Cache::LRU(cache) => cache.weight(),
+            Cache::Unbounded(_) | Cache::None | Cache::Noop(_) => None,
+        }
+    }
+
+    /// Returns how many entries in memory fall in each power of two range
+    /// of weights, lightest first, for caches created with
+    /// [`Cache::new_weighted`].
+    ///
+    /// Only ranges holding entries are listed, so a few heavy entries
+    /// crowding out many light ones stand out. Other caches have no weights
+    /// and return an empty list.
+    pub fn weight_histogram(&self) -> Vec<WeightBucket> {
+        match self {
+            Cache::LRU(cache) => cache.weight_histogram(),
+            Cache::Unbounded(_) | Cache::None | Cache::Noop(_) => Vec::new(),
+        }
+    }
+
+    /// Starts measuring how long entries stay in an LRU cache before they are
+    /// evicted, see [`Cache::lifetime_stats`].
+    ///
+    /// Entries already cached are measured from now. Unbounded caches never
+    /// evict, so tracking lifetimes on them is an error.
+    pub fn track_lifetimes(&self) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.track_lifetimes(),
+            Cache::Unbounded(_) => Err(anyhow::anyhow!(
+                "Only LRU caches evict entries whose lifetimes can be tracked"
+            )),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Returns the distribution of the lifetimes of the entries evicted since
+    /// lifetimes started being tracked, from insertion to eviction.
+    ///
+    /// Updates do not restart an entry's lifetime, and removed entries are not
+    /// counted. Short lifetimes suggest the cache is too small.
+    pub fn lifetime_stats(&self) -> LifetimeStats {
+        match self {
+            Cache::LRU(cache) => cache.lifetime_stats(),
+            Cache::Unbounded(_) | Cache::None | Cache::Noop(_) => LifetimeStats::default(),
+        }
+    }
+
+    /// Returns a future that sends the changes made to the cache from now on
+    /// to `sink`, in batches of up to `max_batch` events.
+    ///
+    /// Spawn the future on an executor; it completes once the cache is
+    /// dropped. Failed batches are reported to the [`set_error_hook`] hook
+    /// and dropped.
+    pub fn forward_events<S>(
+        &self,
+        sink: S,
+        max_batch: usize,
+    ) -> impl std::future::Future<Output = ()> + Send + 'static
+    where
+        S: EventSink<K> + 'static,
+    {
+        events::forward(self.subscribe(), sink, max_batch.max(1))
+    }
+
+    /// Returns the number of entries spilled to disk.
+    pub fn spilled_len(&self) -> usize {
+        match self {
+            Cache::LRU(cache) => cache.spilled_len(),
+            Cache::Unbounded(_) => 0,
+            Cache::None | Cache::Noop(_) => 0,
+        }
+    }
+
+    /// Snapshots the cache to `file_name` every `interval` on a background thread.
+    ///
+    /// Intervals in which the cache did not change are skipped, and failed
+    /// snapshots are reported to the [`set_error_hook`] hook. The returned
+    /// handle can force an immediate snapshot and stops the thread when dropped.
+    pub fn persist_every(&self, file_name: &str, interval: Duration) -> SnapshotHandle {
+        SnapshotHandle::spawn(self.clone(), file_name.to_string(), interval)
+    }
+
+    /// Writes a line of statistics to stderr every `interval` on a
+    /// background thread.
+    ///
+    /// Each line holds the size of the cache and the hit ratio, hits,
+    /// misses, evictions, inserts, updates and removals since the previous
+    /// line, as `key=value` pairs. Flushing the returned handle writes a line
+    /// immediately.
+    pub fn log_stats_every(&self, interval: Duration) -> SnapshotHandle {
+        SnapshotHandle::spawn_stats_log(self.clone(), interval)
+    }
+
+    /// Writes a snapshot of the cache to `snapshot_file` and drops the
+    /// write-ahead log records it covers.
+    ///
+    /// Recovery stays `read()` of the snapshot followed by `enable_wal()` of
+    /// the log. Mutations made during compaction are kept in the log.
+    pub fn compact_wal(&self, snapshot_file: &str) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.compact_wal(snapshot_file),
+            Cache::Unbounded(cache) => cache.compact_wal(snapshot_file),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+
+    /// Checks the write-ahead log every `interval` on a background thread
+    /// and compacts it into `snapshot_file` once it exceeds `max_bytes`.
+    ///
+    /// Flushing the returned handle compacts immediately.
+    pub fn compact_wal_every(
+        &self,
+        snapshot_file: &str,
+        max_bytes: u64,
+        interval: Duration,
+    ) -> SnapshotHandle {
+        SnapshotHandle::spawn_compaction(
+            self.clone(),
+            snapshot_file.to_string(),
+            max_bytes,
+            interval,
+        )
+    }
+
+    /// Returns the size of the write-ahead log in bytes, or 0 without one.
+    pub(crate) fn wal_len(&self) -> Result<u64> {
+        match self {
+            Cache::LRU(cache) => cache.wal_len(),
+            Cache::Unbounded(cache) => cache.wal_len(),
+            Cache::None | Cache::Noop(_) => Ok(0),
+        }
+    }
+
+    /// Returns a counter that changes whenever the contents change.
+    pub(crate) fn generation(&self) -> u64 {
+        match self {
+            Cache::LRU(cache) => cache.generation(),
+            Cache::Unbounded(cache) => cache.generation(),
+            Cache::None | Cache::Noop(_) => 0,
+        }
+    }
+
+    /// Flushes the write-ahead log to disk, if one is enabled.
+    pub fn sync_wal(&self) -> Result<()> {
+        match self {
+            Cache::LRU(cache) => cache.sync_wal(),
+            Cache::Unbounded(cache) => cache.sync_wal(),
+            Cache::None | Cache::Noop(_) => Ok(()),
+        }
+    }
+}
+
+/// A point-in-time copy of the statistics of a cache, see [`Cache::stats`].
+///
+/// Subtracting one copy from a later one, field by field, gives the activity
+/// in between, unless the statistics were reset meanwhile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Entries held in memory
+    pub len: usize,
+    /// The most entries of an LRU cache, or its most total weight if only
+    /// the weight is limited
+    pub capacity: Option<usize>,
+    pub hits: usize,
+    pub misses: usize,
+    /// Entries evicted to stay within capacity
+    pub evictions: usize,
+    /// Inserts of keys that were not in the cache
+    pub inserts: usize,
+    /// Inserts that overwrote a value
+    pub updates: usize,
+    /// Removals of keys that were in the cache
+    pub removals: usize,
+}
+
+/// The statistics of one shard of a cache, see [`Cache::shard_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ShardStats {
+    /// Entries held in the shard
+    pub len: usize,
+    /// Lookups of keys in the shard that found a value
+    pub hits: usize,
+    /// Lookups of keys in the shard that found none
+    pub misses: usize,
+}
+
+impl CacheStats {
+    /// Returns the activity between `earlier` and these statistics, with
+    /// the size and capacity as of now.
+    ///
+    /// Counters that went down, because the statistics were reset in
+    /// between, count from zero.
+    pub fn since(&self, earlier: &CacheStats) -> CacheStats {
+        let delta = |now: usize, then: usize| match now >= then {
+            true => now - then,
+            false => now,
+        };
+        CacheStats {
+            len: self.len,
+            capacity: self.capacity,
+            hits: delta(self.hits, earlier.hits),
+            misses: delta(self.misses, earlier.misses),
+            evictions: delta(self.evictions, earlier.evictions),
+            inserts: delta(self.inserts, earlier.inserts),
+            updates: delta(self.updates, earlier.updates),
+            removals: delta(self.removals, earlier.removals),
         }
     }
+
+    /// Returns the share of lookups that were hits, or 0 if there were none.
+    pub fn hit_ratio(&self) -> f64 {
+        window::ratio(self.hits, self.misses)
+    }
 }
 
-/// A struct that holds statistics about cache hits and misses.
+/// A struct that holds statistics about cache hits, misses and evictions.
 struct Statistics {
     hits: AtomicUsize,
     misses: AtomicUsize,
+    evictions: AtomicUsize,
+    inserts: AtomicUsize,
+    updates: AtomicUsize,
+    removals: AtomicUsize,
+    recent: window::RecentLookups,
+    /// Hits and misses per shard of the map
+    shards: Box<[(AtomicUsize, AtomicUsize)]>,
+    created: Mutex<SystemTime>,
+    /// Where the statistics go instead of the counters, once one is set
+    recorder: OnceLock<Arc<dyn StatsRecorder>>,
 }
 
 impl Statistics {
-    fn new() -> Self {
+    fn new(shards: usize) -> Self {
         Statistics {
             hits: AtomicUsize::new(0),
             misses: AtomicUsize::new(0),
+            evictions: AtomicUsize::new(0),
+            inserts: AtomicUsize::new(0),
+            updates: AtomicUsize::new(0),
+            removals: AtomicUsize::new(0),
+            recent: window::RecentLookups::new(),
+            shards: (0..shards)
+                .map(|_| (AtomicUsize::new(0), AtomicUsize::new(0)))
+                .collect(),
+            created: Mutex::new(SystemTime::now()),
+            recorder: OnceLock::new(),
         }
     }
 
+    fn set_recorder(&self, recorder: Arc<dyn StatsRecorder>) -> Result<()> {
+        self.recorder
+            .set(recorder)
+            .map_err(|_| anyhow::anyhow!("A statistics recorder is already set"))
+    }
+
     fn hits(&self) -> usize {
         self.hits.load(std::sync::atomic::Ordering::SeqCst)
     }
@@ -139,12 +1346,147 @@ impl Statistics {
         self.misses.load(std::sync::atomic::Ordering::SeqCst)
     }
 
-    fn add_hit(&self) {
+    fn evictions(&self) -> usize {
+        self.evictions.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn created(&self) -> SystemTime {
+        *self.created.lock().recover()
+    }
+
+    /// Counts a hit of a key in `shard`.
+    fn add_hit(&self, shard: usize) {
+        if let Some(recorder) = self.recorder.get() {
+            return recorder.record_hit();
+        }
         self.hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.shards[shard]
+            .0
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.recent.add(true);
     }
 
-    fn add_miss(&self) {
+    /// Counts a miss of a key in `shard`.
+    fn add_miss(&self, shard: usize) {
+        if let Some(recorder) = self.recorder.get() {
+            return recorder.record_miss();
+        }
         self.misses
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.shards[shard]
+            .1
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.recent.add(false);
+    }
+
+    /// Returns the statistics of each shard, given the number of entries in each.
+    fn shard_stats(&self, lens: impl Iterator<Item = usize>) -> Vec<ShardStats> {
+        self.shards
+            .iter()
+            .zip(lens)
+            .map(|((hits, misses), len)| ShardStats {
+                len,
+                hits: hits.load(std::sync::atomic::Ordering::Relaxed),
+                misses: misses.load(std::sync::atomic::Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    fn recent_hit_ratio(&self, window: Duration) -> f64 {
+        let (hits, misses) = self.recent.within(window);
+        window::ratio(hits, misses)
+    }
+
+    fn add_eviction(&self) {
+        if let Some(recorder) = self.recorder.get() {
+            return recorder.record_eviction();
+        }
+        self.evictions
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Counts an insert, as an update if it overwrote a value.
+    fn add_insert(&self, replaced: bool) {
+        if let Some(recorder) = self.recorder.get() {
+            return recorder.record_insert(replaced);
+        }
+        let counter = match replaced {
+            true => &self.updates,
+            false => &self.inserts,
+        };
+        counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    fn add_removal(&self) {
+        if let Some(recorder) = self.recorder.get() {
+            return recorder.record_removal();
+        }
+        self.removals
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Returns the counters, with `len` as the number of entries.
+    fn stats(&self, len: usize, capacity: Option<usize>) -> CacheStats {
+        let load = |counter: &AtomicUsize| counter.load(std::sync::atomic::Ordering::SeqCst);
+        CacheStats {
+            len,
+            capacity,
+            hits: self.hits(),
+            misses: self.misses(),
+            evictions: self.evictions(),
+            inserts: load(&self.inserts),
+            updates: load(&self.updates),
+            removals: load(&self.removals),
+        }
+    }
+
+    /// Sets the counters back to zero, keeping the creation time.
+    fn reset(&self) {
+        for counter in [
+            &self.hits,
+            &self.misses,
+            &self.evictions,
+            &self.inserts,
+            &self.updates,
+            &self.removals,
+        ] {
+            counter.store(0, std::sync::atomic::Ordering::SeqCst);
+        }
+        for (hits, misses) in self.shards.iter() {
+            hits.store(0, std::sync::atomic::Ordering::Relaxed);
+            misses.store(0, std::sync::atomic::Ordering::Relaxed);
+        }
+        self.recent.reset();
+    }
+
+    /// Adds the counters from a snapshot and keeps the earlier creation time.
+    fn restore(&self, metadata: &SnapshotMetadata) {
+        self.hits
+            .fetch_add(metadata.hits, std::sync::atomic::Ordering::SeqCst);
+        self.misses
+            .fetch_add(metadata.misses, std::sync::atomic::Ordering::SeqCst);
+        self.evictions
+            .fetch_add(metadata.evictions, std::sync::atomic::Ordering::SeqCst);
+        let mut created = self.created.lock().recover();
+        *created = (*created).min(metadata.created);
+    }
+
+    /// Returns the metadata to store with a snapshot of a cache holding `len` entries.
+    fn metadata(
+        &self,
+        policy: CachePolicy,
+        capacity: Option<usize>,
+        len: usize,
+    ) -> SnapshotMetadata {
+        SnapshotMetadata {
+            policy,
+            capacity,
+            len,
+            hits: self.hits(),
+            misses: self.misses(),
+            evictions: self.evictions(),
+            created: self.created(),
+            written: SystemTime::now(),
+        }
     }
 }
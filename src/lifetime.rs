@@ -0,0 +1,134 @@
+//! How long entries stay in a cache before they are evicted.
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Lifetimes are counted in buckets of powers of two microseconds.
+const BUCKETS: usize = 64;
+
+/// The distribution of the lifetimes of evicted entries, see
+/// [`crate::Cache::lifetime_stats`].
+///
+/// Percentiles are approximate: each is the upper bound of the power of two
+/// microseconds it falls below, capped at the longest lifetime seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct LifetimeStats {
+    /// The number of evicted entries measured
+    pub count: u64,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// Remembers when each entry was inserted and measures its lifetime when
+/// it is evicted.
+pub(crate) struct Lifetimes<K> {
+    born: DashMap<K, Instant>,
+    histogram: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl<K: Eq + Hash + Clone> Lifetimes<K> {
+    pub(crate) fn new() -> Self {
+        Lifetimes {
+            born: DashMap::new(),
+            histogram: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Notes that `key` was inserted, unless it is already in the cache.
+    pub(crate) fn born(&self, key: &K) {
+        if !self.born.contains_key(key) {
+            self.born.entry(key.clone()).or_insert_with(Instant::now);
+        }
+    }
+
+    /// Forgets `key`, which left the cache other than by eviction.
+    pub(crate) fn forget(&self, key: &K) {
+        self.born.remove(key);
+    }
+
+    pub(crate) fn forget_all(&self) {
+        self.born.clear();
+    }
+
+    /// Measures the lifetime of `key`, which was evicted.
+    pub(crate) fn evicted(&self, key: &K) {
+        if let Some((_, born)) = self.born.remove(key) {
+            self.record(born.elapsed());
+        }
+    }
+
+    fn record(&self, lifetime: Duration) {
+        let micros = lifetime.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.histogram[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> LifetimeStats {
+        let counts: Vec<u64> = self
+            .histogram
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        if count == 0 {
+            return LifetimeStats::default();
+        }
+        let max = self.max_micros.load(Ordering::Relaxed);
+        let percentile = |p: f64| {
+            let rank = ((count as f64) * p).ceil().max(1.0) as u64;
+            let mut seen = 0;
+            for (bucket, n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    // Bucket b holds lifetimes below 2^b microseconds
+                    let upper = 1u64.checked_shl(bucket as u32).unwrap_or(u64::MAX);
+                    return Duration::from_micros(upper.min(max));
+                }
+            }
+            Duration::from_micros(max)
+        };
+        LifetimeStats {
+            count,
+            mean: Duration::from_micros(self.total_micros.load(Ordering::Relaxed) / count),
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max: Duration::from_micros(max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lifetimes;
+    use std::time::Duration;
+
+    #[test]
+    fn test_lifetime_stats() {
+        let lifetimes = Lifetimes::<i32>::new();
+        for millis in [1, 2, 3, 4, 100] {
+            lifetimes.record(Duration::from_millis(millis));
+        }
+        let stats = lifetimes.stats();
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.mean, Duration::from_millis(22));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        // 3ms falls in the bucket below 2^12 microseconds
+        assert_eq!(stats.p50, Duration::from_micros(4096));
+        assert_eq!(stats.p99, Duration::from_millis(100));
+    }
+}
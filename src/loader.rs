@@ -0,0 +1,142 @@
+//! Read-through caching: misses are loaded from a source of truth.
+use crate::sync::Isolated;
+use crate::warm::warm_concurrently;
+use crate::{AsyncCache, WarmProgress};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::hash::Hash;
+use std::ops::Deref;
+
+/// Loads the value for a key that is missing from the cache, e.g. from a database.
+pub trait Loader<K, V>: Send + Sync {
+    fn load(&self, key: &K) -> impl Future<Output = Result<V>> + Send;
+}
+
+/// An [`AsyncCache`] that loads misses through a [`Loader`].
+///
+/// Concurrent misses for the same key share one load. Failed loads are not
+/// cached, so the next lookup tries again.
+pub struct LoadingCache<K, V, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    cache: AsyncCache<K, V>,
+    loader: L,
+}
+
+impl<K, V, L> LoadingCache<K, V, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    L: Loader<K, V>,
+{
+    pub fn new(cache: AsyncCache<K, V>, loader: L) -> Self {
+        LoadingCache { cache, loader }
+    }
+
+    /// Returns the cached value for `key`, loading and caching it on a miss.
+    pub async fn get(&self, key: &K) -> Result<V> {
+        self.cache
+            .try_get_or_insert_with(key.clone(), || {
+                Isolated::new("Loader", self.loader.load(key))
+            })
+            .await
+    }
+
+    /// Loads `keys` that are not cached, at most `concurrency` at once, e.g.
+    /// before serving traffic so the first requests do not all miss.
+    ///
+    /// Failed loads are reported to the [`crate::set_error_hook`] hook and
+    /// skipped. `progress` is called after each key, and the final report
+    /// is returned.
+    pub async fn warm(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+        concurrency: usize,
+        progress: impl Fn(WarmProgress),
+    ) -> WarmProgress {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let loads = keys
+            .into_iter()
+            .map(|key| async move { self.get(&key).await });
+        warm_concurrently(loads, concurrency, progress).await
+    }
+
+    pub fn loader(&self) -> &L {
+        &self.loader
+    }
+}
+
+impl<K, V, L> Deref for LoadingCache<K, V, L>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    type Target = AsyncCache<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Loader, LoadingCache};
+    use crate::async_cache::block_on;
+    use crate::{AsyncCache, Cache};
+    use anyhow::{bail, Result};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// Loads the square of a key, failing for negative keys and panicking
+    /// for zero.
+    #[derive(Default)]
+    struct Squares(AtomicUsize);
+
+    impl Loader<i32, i32> for Squares {
+        async fn load(&self, key: &i32) -> Result<i32> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            if *key < 0 {
+                bail!("No square for {}", key);
+            }
+            assert!(*key != 0, "Zero is not squared");
+            Ok(key * key)
+        }
+    }
+
+    #[test]
+    fn test_loading_cache() {
+        let cache = LoadingCache::new(AsyncCache::new(Cache::new_lru(10)), Squares::default());
+        block_on(async {
+            assert_eq!(cache.get(&3).await.unwrap(), 9);
+            assert_eq!(cache.get(&3).await.unwrap(), 9);
+            assert!(cache.get(&-1).await.is_err());
+            assert!(cache.get(&-1).await.is_err());
+            // A panicking load fails like any other
+            let error = cache.get(&0).await.unwrap_err();
+            assert!(error.to_string().contains("Loader panicked"));
+            assert_eq!(cache.get(&2).await.unwrap(), 4);
+        });
+        assert_eq!(cache.loader().0.load(Ordering::SeqCst), 5);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_warm() {
+        let cache = LoadingCache::new(AsyncCache::new(Cache::new_lru(10)), Squares::default());
+        let reports = Mutex::new(Vec::new());
+        let report = block_on(cache.warm(-2..8, 3, |progress| {
+            reports.lock().unwrap().push(progress.done())
+        }));
+        assert_eq!((report.total, report.loaded, report.failed), (10, 7, 3));
+        assert_eq!(*reports.lock().unwrap(), (1..=10).collect::<Vec<_>>());
+        assert_eq!(cache.len(), 7);
+
+        // Cached keys are not loaded again
+        let loads = cache.loader().0.load(Ordering::SeqCst);
+        block_on(cache.warm(1..4, 3, |_| {}));
+        assert_eq!(cache.loader().0.load(Ordering::SeqCst), loads);
+    }
+}
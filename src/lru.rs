@@ -1,23 +1,48 @@
+use crate::report::report;
+use anyhow::{anyhow, Result};
 use dashmap::DashMap;
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::VecDeque;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLockReadGuard};
+use std::time::{Duration, SystemTime};
 
-use crate::Statistics;
+use crate::audit::{AuditLog, Mutation};
+use crate::autotune::{Autotune, Ghosts};
+use crate::bloom::Bloom;
+use crate::dirty::{self, DirtySet};
+use crate::events::{
+    Broadcast, CacheEvent, HookId, Hooks, Listeners, Overflow, Receiver, RemovalCause,
+};
+use crate::frequency::KeyTraffic;
+use crate::invalidation::InvalidationBus;
+use crate::lifetime::{LifetimeStats, Lifetimes};
+use crate::persistence::{
+    self, CachePolicy, CacheState, LoadReport, PersistenceError, ReadLimits, ReadMode,
+    SnapshotMetadata,
+};
+use crate::shard;
+use crate::spill::Spill;
+use crate::sync::{isolate, Recover};
+use crate::trace::{TraceHandle, TraceOp, Tracer};
+use crate::wal::{Record, Wal};
+use crate::weights::{WeightBucket, WeightHistogram};
+use crate::{CacheStats, ShardStats, Statistics, StatsRecorder};
 
 /// An LRU cache that stores key-value pairs in a `DashMap`.
 pub struct LRU<K, V>
 where
-    K: Eq + Hash + Clone + Send + Sync + 'static,
-    V: Clone + Send + Sync + 'static,
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
 {
     inner: Arc<LRUInner<K, V>>,
 }
 
 impl<K, V> Clone for LRU<K, V>
 where
-    K: Eq + Hash + Clone + Send + Sync + 'static,
-    V: Clone + Send + Sync + 'static,
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
 {
     fn clone(&self) -> Self {
         LRU {
@@ -26,96 +51,618 @@ where
     }
 }
 
+/// What a bounded cache does with a new key when it is full, see
+/// [`Cache::new_bounded`](crate::Cache::new_bounded).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Evict the least recently used entry
+    Evict,
+    /// Refuse the insert, calling the hooks of
+    /// [`Cache::on_reject`](crate::Cache::on_reject)
+    Reject,
+    /// Evict the least recently used entry to a scratch file at this path,
+    /// as with [`Cache::enable_spillover`](crate::Cache::enable_spillover)
+    SpillToDisk(String),
+}
+
+/// Returns the weight of an entry, counted against the capacity of a
+/// weighted cache.
+pub(crate) type Weigher<K, V> = Box<dyn Fn(&K, &V) -> u32 + Send + Sync>;
+
+/// Weighs an entry, counting it as weightless if the weigher panics, as
+/// it is called with the order locked.
+fn weigh_with<K, V>(weigher: &Weigher<K, V>, key: &K, value: &V) -> u32 {
+    isolate("Weigher", || weigher(key, value)).unwrap_or(0)
+}
+
+/// Returns the key of an entry taken out of both the map and the order,
+/// cloning it only if a reference is still held elsewhere.
+fn unshare<K: Clone>(key: Arc<K>) -> K {
+    Arc::try_unwrap(key).unwrap_or_else(|key| K::clone(&key))
+}
+
+/// Estimates the bytes an entry takes in memory: its serialized size for
+/// the data it owns, plus the inline size of the value and of the key,
+/// which the map and the order share behind a reference count.
+///
+/// This is the weigher of [`Cache::new_memory_bounded`](crate::Cache::new_memory_bounded),
+/// and can be given to [`Cache::new_lru_weighted`](crate::Cache::new_lru_weighted)
+/// to limit both entries and bytes.
+pub fn estimated_size<K: Serialize, V: Serialize>(key: &K, value: &V) -> u32 {
+    let owned = bincode::serialized_size(&(key, value)).unwrap_or(0) as usize;
+    let shared = std::mem::size_of::<K>() + 4 * std::mem::size_of::<usize>();
+    let inline = shared + std::mem::size_of::<V>();
+    (owned + inline).try_into().unwrap_or(u32::MAX)
+}
+
 struct LRUInner<K, V>
 where
-    K: Eq + Hash + Clone + Send + Sync + 'static,
-    V: Clone + Send + Sync + 'static,
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize,
+    V: Clone + Send + Sync + 'static + Serialize,
 {
-    map: DashMap<K, V>,
-    order: Mutex<VecDeque<K>>,
-    capacity: usize,
+    /// The map and the order share each key, so it is stored once
+    map: DashMap<Arc<K>, V>,
+    order: Mutex<VecDeque<Arc<K>>>,
+    /// The most entries
+    capacity: AtomicUsize,
+    weigher: Option<Weigher<K, V>>,
+    /// The most total weight, with a weigher
+    max_weight: usize,
+    /// The most weight of one entry; heavier ones are rejected
+    max_entry_weight: AtomicUsize,
+    /// The share of its limits a full cache is evicted down to, as `f64` bits
+    low_watermark: AtomicU64,
+    /// The share of its limits the cache may use for now, as `f64` bits
+    scale: AtomicU64,
+    /// Whether new keys are refused when full, rather than evicting
+    reject_when_full: AtomicBool,
+    /// The total weight of the entries in memory, kept with a weigher
+    weight: AtomicUsize,
+    /// The entries in memory by weight, kept with a weigher
+    weights: WeightHistogram,
+    /// The weights given by callers in place of the weigher's
+    costs: DashMap<K, u32>,
+    /// Set once a weight is given, so caches without any skip `costs`
+    has_costs: AtomicBool,
     statistics: Statistics,
+    wal: OnceLock<Wal<K, V>>,
+    generation: AtomicU64,
+    dirty: DirtySet<K>,
+    /// Where evicted entries go, if spilling is enabled
+    spill: OnceLock<Spill<K, V>>,
+    listeners: Listeners<K, V>,
+    insert_hooks: Hooks<K, V>,
+    hit_hooks: Hooks<K, V>,
+    reject_hooks: Hooks<K, V>,
+    events: Broadcast<CacheEvent<K>>,
+    bus: OnceLock<Arc<dyn InvalidationBus<K>>>,
+    /// Access counts, once tracking of hot keys is enabled
+    hot: OnceLock<KeyTraffic<K>>,
+    audit: OnceLock<Arc<AuditLog<K>>>,
+    /// Insertion times, once tracking of lifetimes is enabled
+    lifetimes: OnceLock<Lifetimes<K>>,
+    /// Lookup counts and recently evicted keys, once autotuned
+    ghosts: OnceLock<Ghosts>,
+    /// The keys in memory or spilled, once filtered
+    bloom: OnceLock<Bloom>,
+    /// Where accesses are recorded, once traced
+    trace: OnceLock<Arc<Tracer>>,
 }
 
 impl<K, V> LRU<K, V>
 where
-    K: Eq + Hash + Clone + Send + Sync + 'static,
-    V: Clone + Send + Sync + 'static,
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
 {
     /// Creates a new LRU with the specified capacity.
     pub(crate) fn new(capacity: usize) -> Self {
+        Self::with_weigher(capacity, usize::MAX, None)
+    }
+
+    /// Creates a new LRU holding at most `capacity` entries and, given a
+    /// `weigher`, at most `max_weight` in its units.
+    pub(crate) fn with_weigher(
+        capacity: usize,
+        max_weight: usize,
+        weigher: Option<Weigher<K, V>>,
+    ) -> Self {
         LRU {
             inner: Arc::new(LRUInner {
-                map: DashMap::new(),
+                statistics: Statistics::new(shard::amount()),
+                map: DashMap::with_shard_amount(shard::amount()),
                 order: Mutex::new(VecDeque::new()),
-                capacity,
-                statistics: Statistics::new(),
+                capacity: AtomicUsize::new(capacity),
+                weigher,
+                max_weight,
+                max_entry_weight: AtomicUsize::new(usize::MAX),
+                low_watermark: AtomicU64::new(1f64.to_bits()),
+                scale: AtomicU64::new(1f64.to_bits()),
+                reject_when_full: AtomicBool::new(false),
+                weight: AtomicUsize::new(0),
+                weights: WeightHistogram::new(),
+                costs: DashMap::new(),
+                has_costs: AtomicBool::new(false),
+                wal: OnceLock::new(),
+                generation: AtomicU64::new(0),
+                dirty: DirtySet::new(),
+                spill: OnceLock::new(),
+                listeners: Listeners::new(),
+                insert_hooks: Hooks::new(),
+                hit_hooks: Hooks::new(),
+                reject_hooks: Hooks::new(),
+                events: Broadcast::new(),
+                bus: OnceLock::new(),
+                hot: OnceLock::new(),
+                audit: OnceLock::new(),
+                lifetimes: OnceLock::new(),
+                ghosts: OnceLock::new(),
+                bloom: OnceLock::new(),
+                trace: OnceLock::new(),
             }),
         }
     }
 
-    fn evict_if_needed(&self) {
-        let oldest_key = {
-            let mut order = self.inner.order.lock().unwrap();
-            if order.len() > self.inner.capacity {
-                order.pop_front()
-            } else {
-                None
+    /// Evicts until the cache is within its limits, or down to the low
+    /// watermark once it went over them, returning how many entries were
+    /// evicted.
+    fn evict_if_needed(&self) -> usize {
+        let mut order = self.inner.order.lock().recover();
+        let evicted = self.take_over_capacity(&mut order);
+        drop(order);
+        let count = evicted.len();
+        self.finish_evictions(evicted);
+        count
+    }
+
+    /// Takes entries out of the map and the order until the cache is within
+    /// its limits. Holding the order lock throughout means no other insert
+    /// can slip in between, so the cache is never left over its capacity
+    /// and only the least recently used entries go.
+    ///
+    /// Returns the evicted entries, with whether each was spilled, for
+    /// [`LRU::finish_evictions`] to report once the lock is released.
+    fn take_over_capacity(&self, order: &mut VecDeque<Arc<K>>) -> Vec<(K, Option<V>, bool)> {
+        let mut evicted = Vec::new();
+        let mut share = 1.0;
+        while let Some(key) = self.pop_over_capacity(order, share) {
+            let mut spilled = false;
+            if let Some(spill) = self.inner.spill.get() {
+                // Spill before removing, so the entry is always in one of the two
+                let value = self.inner.map.get(&key).map(|value| value.clone());
+                if let Some(value) = value {
+                    match spill.put(&key, &value) {
+                        Ok(()) => spilled = true,
+                        Err(e) => {
+                            report(e.context("Failed to spill evicted entry"));
+                        }
+                    }
+                }
+            }
+            let value = self.inner.map.remove(&key).map(|(_, value)| value);
+            if let Some(value) = &value {
+                self.unweigh(&key, value);
+                if let Some(bloom) = self.inner.bloom.get().filter(|_| !spilled) {
+                    bloom.remove(&key);
+                }
             }
+            evicted.push((unshare(key), value, spilled));
+            share = f64::from_bits(self.inner.low_watermark.load(Ordering::Relaxed));
+        }
+        evicted
+    }
+
+    /// Takes the least recently used key off the order while the cache is
+    /// over `share` of either of its scaled limits.
+    fn pop_over_capacity(&self, order: &mut VecDeque<Arc<K>>, share: f64) -> Option<Arc<K>> {
+        let share = share * f64::from_bits(self.inner.scale.load(Ordering::Relaxed));
+        let limit = |max: usize| match share < 1.0 {
+            true => (max as f64 * share) as usize,
+            false => max,
         };
+        let over = order.len() > limit(self.inner.capacity.load(Ordering::Relaxed))
+            || (self.inner.weigher.is_some()
+                && self.inner.weight.load(Ordering::SeqCst) > limit(self.inner.max_weight));
+        over.then(|| order.pop_front()).flatten()
+    }
+
+    /// Counts and reports entries taken by [`LRU::take_over_capacity`].
+    fn finish_evictions(&self, evicted: Vec<(K, Option<V>, bool)>) {
+        for (key, value, spilled) in evicted {
+            self.inner.statistics.add_eviction();
+            if let Some(lifetimes) = self.inner.lifetimes.get() {
+                lifetimes.evicted(&key);
+            }
+            if let Some(ghosts) = self.inner.ghosts.get() {
+                ghosts.evicted(&key);
+            }
+            self.inner.dirty.mark(&key);
+
+            // Spilled entries are still in the cache, so listeners only see
+            // the ones that are gone
+            if let Some(value) = value.filter(|_| !spilled) {
+                self.inner
+                    .events
+                    .send_with(|| CacheEvent::Evicted(key.clone()));
+                self.inner
+                    .listeners
+                    .notify(key, value, RemovalCause::Evicted);
+            }
+        }
+    }
+
+    /// Returns whether a full cache refuses the new `key`.
+    fn is_full_for(&self, key: &K) -> bool {
+        self.inner.reject_when_full.load(Ordering::Relaxed)
+            && self.inner.map.len() >= self.inner.capacity.load(Ordering::Relaxed)
+            && !self.inner.map.contains_key(key)
+    }
+
+    /// Returns whether the entry weighs more than one entry may.
+    fn is_oversized(&self, key: &K, value: &V, cost: Option<u32>) -> bool {
+        let max = self.inner.max_entry_weight.load(Ordering::Relaxed);
+        match &self.inner.weigher {
+            Some(weigher) if max < usize::MAX => {
+                cost.unwrap_or_else(|| weigh_with(weigher, key, value)) as usize > max
+            }
+            _ => false,
+        }
+    }
 
-        if let Some(key) = oldest_key {
-            self.inner.map.remove(&key);
+    /// Counts the weight of an entry added to memory, `cost` if given.
+    fn weigh(&self, key: &K, value: &V, cost: Option<u32>) {
+        if let Some(weigher) = &self.inner.weigher {
+            let weight = cost.unwrap_or_else(|| weigh_with(weigher, key, value));
+            self.inner
+                .weight
+                .fetch_add(weight as usize, Ordering::SeqCst);
+            self.inner.weights.add(weight);
         }
     }
 
-    fn update_order(&self, key: K) {
-        let mut order = self.inner.order.lock().unwrap();
-        if let Some(pos) = order.iter().position(|k| *k == key) {
-            order.remove(pos);
+    /// Stops counting the weight of an entry taken out of memory.
+    fn unweigh(&self, key: &K, value: &V) {
+        if let Some(weigher) = &self.inner.weigher {
+            let cost = match self.inner.has_costs.load(Ordering::Relaxed) {
+                true => self.inner.costs.remove(key).map(|(_, cost)| cost),
+                false => None,
+            };
+            let weight = cost.unwrap_or_else(|| weigh_with(weigher, key, value));
+            self.inner
+                .weight
+                .fetch_sub(weight as usize, Ordering::SeqCst);
+            self.inner.weights.remove(weight);
         }
+    }
+
+    /// Makes `key` the most recently used, returning where it was counted
+    /// from the least recently used end.
+    ///
+    /// A key already in the order keeps its allocation, which the map
+    /// holds too, as the map keeps the key it has when its value is replaced.
+    fn update_order(order: &mut VecDeque<Arc<K>>, key: Arc<K>) -> Option<usize> {
+        let pos = order.iter().position(|k| *k == key);
+        let key = pos.and_then(|pos| order.remove(pos)).unwrap_or(key);
         order.push_back(key);
+        pos
     }
 }
 
 impl<K, V> LRU<K, V>
 where
-    K: Eq + Hash + Clone + Send + Sync + 'static,
-    V: Clone + Send + Sync + 'static,
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
 {
     pub(crate) fn insert(&self, key: K, value: V) {
-        self.inner.map.insert(key.clone(), value);
-        self.update_order(key);
-        self.evict_if_needed();
+        self.insert_with(key, value, None);
+    }
+
+    pub(crate) fn insert_with_cost(&self, key: K, value: V, cost: u32) {
+        self.insert_with(key, value, Some(cost));
+    }
+
+    /// Inserts an entry weighing `cost`, or what the weigher says without one.
+    fn insert_with(&self, key: K, value: V, cost: Option<u32>) {
+        self.trace(TraceOp::Insert, Some(&key));
+        if self.is_oversized(&key, &value, cost) {
+            // The value it was to replace is out of date all the same
+            self.remove(&key);
+            self.inner.reject_hooks.call(&key, &value);
+            return;
+        }
+        if self.is_full_for(&key) {
+            self.inner.reject_hooks.call(&key, &value);
+            return;
+        }
+        let applying = self.begin();
+        self.log(Record::Insert(&key, &value));
+        let hooked = self
+            .inner
+            .insert_hooks
+            .is_active()
+            .then(|| (key.clone(), value.clone()));
+        let replaced = self.insert_weighed(key, value, cost);
+        self.inner.statistics.add_insert(replaced);
+        drop(applying);
+        if let Some((key, value)) = hooked {
+            self.inner.insert_hooks.call(&key, &value);
+        }
+    }
+
+    /// Inserts without logging, for entries that are already durable.
+    /// Returns whether a value was replaced.
+    fn insert_entry(&self, key: K, value: V) -> bool {
+        self.insert_weighed(key, value, None)
+    }
+
+    /// Like [`LRU::insert_entry`], but weighing the entry `cost` if given.
+    fn insert_weighed(&self, key: K, value: V, cost: Option<u32>) -> bool {
+        let listening = self.inner.listeners.is_active();
+        // A spilled value is only read back if a listener is to be given it
+        let (spilled, unspilled) = match self.inner.spill.get() {
+            Some(_) if listening => {
+                let value = self.unspill(&key);
+                (value.is_some(), value)
+            }
+            Some(spill) => (spill.discard(&key), None),
+            None => (false, None),
+        };
+        // The map and the order change together, so they always agree
+        let shared = Arc::new(key.clone());
+        let mut order = self.inner.order.lock().recover();
+        self.weigh(&key, &value, cost);
+        let previous = self.inner.map.insert(shared.clone(), value);
+        if let Some(previous) = &previous {
+            self.unweigh(&key, previous);
+        } else if let Some(bloom) = self.inner.bloom.get().filter(|_| !spilled) {
+            bloom.add(&key);
+        }
+        if let (Some(cost), Some(_)) = (cost, &self.inner.weigher) {
+            self.inner.has_costs.store(true, Ordering::Relaxed);
+            self.inner.costs.insert(key.clone(), cost);
+        }
+        Self::update_order(&mut order, shared);
+        let evicted = self.take_over_capacity(&mut order);
+        drop(order);
+        let replaced = previous.is_some() || spilled;
+        if let Some(lifetimes) = self.inner.lifetimes.get() {
+            lifetimes.born(&key);
+        }
+        if let Some(previous) = previous.or(unspilled) {
+            self.inner
+                .listeners
+                .notify(key.clone(), previous, RemovalCause::Replaced);
+        }
+        self.inner.dirty.mark(&key);
+        self.inner.events.send_with(|| match replaced {
+            true => CacheEvent::Updated(key.clone()),
+            false => CacheEvent::Inserted(key.clone()),
+        });
+        self.finish_evictions(evicted);
+        self.inner.generation.fetch_add(1, Ordering::Relaxed);
+        replaced
+    }
+
+    /// Moves an entry taken from the spill store back into memory.
+    fn promote(&self, key: K, value: V) {
+        let shared = Arc::new(key.clone());
+        let mut order = self.inner.order.lock().recover();
+        self.weigh(&key, &value, None);
+        if let Some(previous) = self.inner.map.insert(shared.clone(), value) {
+            self.unweigh(&key, &previous);
+        }
+        Self::update_order(&mut order, shared);
+        let evicted = self.take_over_capacity(&mut order);
+        drop(order);
+        if let Some(lifetimes) = self.inner.lifetimes.get() {
+            lifetimes.born(&key);
+        }
+        self.inner.dirty.mark(&key);
+        self.finish_evictions(evicted);
+        self.inner.generation.fetch_add(1, Ordering::Relaxed);
     }
 
     pub(crate) fn get(&self, key: &K) -> Option<V> {
-        if let Some(value) = self.inner.map.get(key) {
-            self.update_order(key.clone());
-            self.inner.statistics.add_hit();
-            Some(value.clone())
+        self.trace(TraceOp::Get, Some(key));
+        if let Some(hot) = self.inner.hot.get() {
+            hot.lookups.record(key);
+        }
+        // Keys the filter rules out miss without locking the order
+        let present = self
+            .inner
+            .bloom
+            .get()
+            .is_none_or(|bloom| bloom.may_contain(key));
+        let shard = shard::of(&self.inner.map, key);
+        let (value, pos) = match present {
+            true => self.touch(key),
+            false => (None, None),
+        };
+        if let Some(value) = value {
+            if let Some(ghosts) = self.inner.ghosts.get() {
+                ghosts.hit(pos);
+            }
+            self.inner.statistics.add_hit(shard);
+            self.inner.hit_hooks.call(key, &value);
+            Some(value)
+        } else if let Some(value) = present.then(|| self.unspill(key)).flatten() {
+            // Promote the entry back into memory as the most recently used
+            self.promote(key.clone(), value.clone());
+            if let Some(ghosts) = self.inner.ghosts.get() {
+                ghosts.hit(None);
+            }
+            self.inner.statistics.add_hit(shard);
+            self.inner.hit_hooks.call(key, &value);
+            Some(value)
         } else {
-            self.inner.statistics.add_miss();
+            if let Some(hot) = self.inner.hot.get() {
+                hot.misses.record(key);
+            }
+            if let Some(ghosts) = self.inner.ghosts.get() {
+                ghosts.miss(key);
+            }
+            self.inner.statistics.add_miss(shard);
             None
         }
     }
 
+    /// Makes the entry for `key` the most recently used, returning its value
+    /// and where it was counted from the least recently used end.
+    fn touch(&self, key: &K) -> (Option<V>, Option<usize>) {
+        // Looking up under the order lock keeps an entry evicted meanwhile
+        // from being put back in the order
+        let mut order = self.inner.order.lock().recover();
+        let entry = self.inner.map.get(key);
+        let (shared, value) = entry
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .unzip();
+        let pos = shared.and_then(|shared| Self::update_order(&mut order, shared));
+        (value, pos)
+    }
+
+    /// Returns the value for `key` without counting a lookup or changing its recency.
+    pub(crate) fn peek(&self, key: &K) -> Option<V> {
+        if let Some(value) = self.inner.map.get(key) {
+            return Some(value.clone());
+        }
+        match self.inner.spill.get()?.get(key) {
+            Ok(value) => value,
+            Err(e) => {
+                report(e.context("Failed to read spilled entry"));
+                None
+            }
+        }
+    }
+
+    /// Takes the entry for `key` out of the spill store, if there is one.
+    fn unspill(&self, key: &K) -> Option<V> {
+        let spill = self.inner.spill.get()?;
+        match spill.take(key) {
+            Ok(value) => value,
+            Err(e) => {
+                report(e.context("Failed to read spilled entry"));
+                None
+            }
+        }
+    }
+
     pub(crate) fn remove(&self, key: &K) -> Option<V> {
-        if let Some(value) = self.inner.map.remove(key) {
-            let mut order = self.inner.order.lock().unwrap();
+        self.trace(TraceOp::Remove, Some(key));
+        let value = self.remove_unpublished(key);
+        // Other instances may hold the key even if this one did not
+        if let Some(bus) = self.inner.bus.get() {
+            bus.publish(key);
+        }
+        value
+    }
+
+    /// Removes without telling other caches on the invalidation bus.
+    fn remove_unpublished(&self, key: &K) -> Option<V> {
+        let _applying = self.begin();
+        let value = self.remove_entry(key);
+        if value.is_some() {
+            self.log(Record::Remove(key));
+            self.inner.statistics.add_removal();
+        }
+        value
+    }
+
+    fn remove_entry(&self, key: &K) -> Option<V> {
+        let mut order = self.inner.order.lock().recover();
+        let removed = self.inner.map.remove(key);
+        if let Some((key, value)) = &removed {
+            self.unweigh(key, value);
+            if let Some(bloom) = self.inner.bloom.get() {
+                bloom.remove(key);
+            }
             if let Some(pos) = order.iter().position(|k| k == key) {
                 order.remove(pos);
             }
+        }
+        drop(order);
+        if let Some(value) = removed {
+            if let Some(lifetimes) = self.inner.lifetimes.get() {
+                lifetimes.forget(key);
+            }
+            self.inner.dirty.mark(key);
+            self.inner
+                .events
+                .send_with(|| CacheEvent::Removed(key.clone()));
+            if self.inner.listeners.is_active() {
+                self.inner
+                    .listeners
+                    .notify(key.clone(), value.1.clone(), RemovalCause::Explicit);
+            }
+            self.inner.generation.fetch_add(1, Ordering::Relaxed);
             Some(value.1)
         } else {
-            None
+            let value = self.unspill(key);
+            if let Some(value) = &value {
+                if let Some(bloom) = self.inner.bloom.get() {
+                    bloom.remove(key);
+                }
+                self.inner
+                    .events
+                    .send_with(|| CacheEvent::Removed(key.clone()));
+                if self.inner.listeners.is_active() {
+                    self.inner
+                        .listeners
+                        .notify(key.clone(), value.clone(), RemovalCause::Explicit);
+                }
+            }
+            value
         }
     }
 
     pub(crate) fn clear(&self) {
+        self.trace(TraceOp::Clear, None);
+        let _applying = self.begin();
+        self.log(Record::Clear);
+        self.clear_entries();
+    }
+
+    fn clear_entries(&self) {
+        // Listeners are given the cleared entries, spilled ones included
+        let mut cleared = Vec::new();
+        let listening = self.inner.listeners.is_active();
+        if let Some(spill) = self.inner.spill.get() {
+            let result = match listening {
+                true => spill.take_all().map(|entries| cleared = entries),
+                false => spill.clear(),
+            };
+            if let Err(e) = result {
+                report(e.context("Failed to clear spilled entries"));
+            }
+        }
+        let mut order = self.inner.order.lock().recover();
+        if listening {
+            let removed = order.iter().filter_map(|key| self.inner.map.remove(key));
+            cleared.extend(removed.map(|(key, value)| (K::clone(&key), value)));
+        }
         self.inner.map.clear();
-        let mut order = self.inner.order.lock().unwrap();
         order.clear();
+        if let Some(bloom) = self.inner.bloom.get() {
+            bloom.clear();
+        }
+        self.inner.weight.store(0, Ordering::SeqCst);
+        self.inner.weights.clear();
+        self.inner.costs.clear();
+        drop(order);
+        if let Some(lifetimes) = self.inner.lifetimes.get() {
+            lifetimes.forget_all();
+        }
+        self.inner.dirty.mark_cleared();
+        self.inner.events.send_with(|| CacheEvent::Cleared);
+        for (key, value) in cleared {
+            self.inner
+                .listeners
+                .notify(key, value, RemovalCause::Explicit);
+        }
+        self.inner.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns a counter that changes whenever the contents change.
+    pub(crate) fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::Relaxed)
     }
 
     pub(crate) fn len(&self) -> usize {
@@ -134,20 +681,583 @@ where
         self.inner.statistics.misses()
     }
 
-    pub(crate) fn write(&self, file_name: &str) -> Result<(), anyhow::Error> {
-        let _ = file_name;
-        todo!()
+    pub(crate) fn evictions(&self) -> usize {
+        self.inner.statistics.evictions()
     }
 
-    pub(crate) fn read(&self, file_name: &str) -> Result<(), anyhow::Error> {
-        let _ = file_name;
-        todo!()
+    pub(crate) fn recent_hit_ratio(&self, window: Duration) -> f64 {
+        self.inner.statistics.recent_hit_ratio(window)
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.inner.statistics.stats(self.len(), self.capacity())
+    }
+
+    pub(crate) fn shard_stats(&self) -> Vec<ShardStats> {
+        let lens = shard::lens(&self.inner.map);
+        self.inner.statistics.shard_stats(lens.into_iter())
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.inner.statistics.reset();
+    }
+
+    pub(crate) fn created(&self) -> SystemTime {
+        self.inner.statistics.created()
+    }
+
+    fn metadata(&self) -> SnapshotMetadata {
+        self.inner
+            .statistics
+            .metadata(CachePolicy::LRU, self.capacity(), self.len())
+    }
+}
+
+impl<K, V> LRU<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    /// Returns the entries from least to most recently used, so that
+    /// importing them into another LRU restores the recency order.
+    pub(crate) fn export(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        // Share only the keys, so the order lock is not held while iterating
+        let keys: Vec<Arc<K>> = self.inner.order.lock().recover().iter().cloned().collect();
+        keys.into_iter().filter_map(move |key| {
+            let value = self.inner.map.get(&key)?.value().clone();
+            Some((unshare(key), value))
+        })
+    }
+
+    pub(crate) fn find(&self, predicate: impl Fn(&K, &V) -> bool) -> Vec<K> {
+        let entries = self.inner.map.iter();
+        let found = entries.filter(|entry| predicate(entry.key(), entry.value()));
+        found.map(|entry| K::clone(entry.key())).collect()
+    }
+
+    pub(crate) fn import(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+
+    pub(crate) fn write(&self, file_name: &str) -> Result<()> {
+        persistence::write(file_name, Some(&self.metadata()), |writer| {
+            for (key, value) in self.export() {
+                writer.push(&key, &value)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Writes contiguous runs of the recency order to each shard.
+    pub(crate) fn write_sharded(&self, file_name: &str, shards: usize) -> Result<()> {
+        let keys: Vec<Arc<K>> = self.inner.order.lock().recover().iter().cloned().collect();
+        let per_shard = keys.len().div_ceil(shards.max(1)).max(1);
+
+        let metadata = self.metadata();
+        persistence::write_sharded(file_name, shards, Some(&metadata), |index, writer| {
+            for key in keys.iter().skip(index * per_shard).take(per_shard) {
+                if let Some(value) = self.inner.map.get(key) {
+                    writer.push(&**key, value.value())?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Decodes the shards in parallel, then inserts them in order to restore recency.
+    pub(crate) fn read_sharded(&self, file_name: &str, limits: &ReadLimits) -> Result<()> {
+        let shards = persistence::read_sharded::<K, V>(file_name, limits)?;
+        // Every shard carries the same metadata
+        if let Some(metadata) = shards.first().and_then(|shard| shard.metadata.as_ref()) {
+            self.inner.statistics.restore(metadata);
+        }
+        let entries = shards.into_iter().flat_map(|shard| shard.entries).collect();
+        self.load(entries, ReadMode::Merge)
+    }
+
+    /// Inserts entries read from a snapshot, combining them with the
+    /// existing entries according to `mode`.
+    fn load(&self, mut entries: Vec<(K, V)>, mode: ReadMode) -> Result<()> {
+        match mode {
+            ReadMode::Merge => {}
+            ReadMode::Replace => self.clear_entries(),
+            ReadMode::KeepExisting => entries.retain(|(key, _)| !self.inner.map.contains_key(key)),
+            ReadMode::ErrorOnConflict => {
+                let conflicts = entries
+                    .iter()
+                    .filter(|(key, _)| self.inner.map.contains_key(key))
+                    .count();
+                if conflicts > 0 {
+                    return Err(PersistenceError::Conflict(conflicts).into());
+                }
+            }
+        }
+
+        // The entries run from least to most recently used, and all but the
+        // last ones that fit would only be evicted by the ones after
+        let skip = entries.len() - self.fitting(&entries);
+        for (key, value) in entries.into_iter().skip(skip) {
+            self.insert_entry(key, value);
+        }
+        Ok(())
+    }
+
+    /// Returns how many of the last of `entries` fit within both the most
+    /// entries and the most weight of the cache.
+    fn fitting(&self, entries: &[(K, V)]) -> usize {
+        let capacity = self.inner.capacity.load(Ordering::Relaxed);
+        let Some(weigher) = &self.inner.weigher else {
+            return entries.len().min(capacity);
+        };
+        let mut weight = 0usize;
+        entries
+            .iter()
+            .rev()
+            .take(capacity)
+            .take_while(|(key, value)| {
+                weight = weight.saturating_add(weigh_with(weigher, key, value) as usize);
+                weight <= self.inner.max_weight
+            })
+            .count()
+    }
+
+    pub(crate) fn read(&self, file_name: &str, mode: ReadMode, limits: &ReadLimits) -> Result<()> {
+        let snapshot = persistence::read_limited::<K, V>(file_name, limits)?;
+
+        // Insert the entries, then carry over the statistics
+        self.load(snapshot.entries, mode)?;
+        if let Some(metadata) = &snapshot.metadata {
+            self.inner.statistics.restore(metadata);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_lossy(&self, file_name: &str) -> Result<LoadReport> {
+        let (snapshot, report) = persistence::read_lossy::<K, V>(file_name)?;
+        if let Some(metadata) = &snapshot.metadata {
+            self.inner.statistics.restore(metadata);
+        }
+
+        self.load(snapshot.entries, ReadMode::Merge)?;
+        Ok(report)
+    }
+
+    pub(crate) fn read_filtered(
+        &self,
+        file_name: &str,
+        keep: &mut dyn FnMut(&K) -> bool,
+        limit: usize,
+    ) -> Result<()> {
+        // The statistics describe the whole snapshot, so a partial load leaves them alone
+        let snapshot = persistence::read_filtered::<K, V>(file_name, keep, limit)?;
+
+        self.load(snapshot.entries, ReadMode::Merge)
+    }
+
+    pub(crate) fn write_incremental(&self, file_name: &str) -> Result<()> {
+        dirty::write_incremental(
+            file_name,
+            &self.inner.dirty,
+            || self.write(file_name),
+            |key| self.inner.map.get(key).map(|value| value.clone()),
+        )
+    }
+
+    pub(crate) fn read_incremental(&self, file_name: &str) -> Result<()> {
+        // Decode both parts before touching the cache
+        let records = dirty::read_delta(file_name)?;
+        self.read(file_name, ReadMode::Merge, &ReadLimits::default())?;
+        for record in records {
+            self.apply(record);
+        }
+        Ok(())
+    }
+
+    /// Replays the log at `file_name` into the cache and appends all further
+    /// mutations to it.
+    pub(crate) fn enable_wal(&self, file_name: &str) -> Result<()> {
+        let (wal, records) = Wal::open(file_name)?;
+        for record in records {
+            self.apply(record);
+        }
+        self.inner
+            .wal
+            .set(wal)
+            .map_err(|_| anyhow!("A write-ahead log is already enabled"))
+    }
+
+    /// Writes evicted entries to a scratch file at `file_name` instead of
+    /// dropping them.
+    pub(crate) fn enable_spillover(&self, file_name: &str) -> Result<()> {
+        self.inner
+            .spill
+            .set(Spill::open(file_name)?)
+            .map_err(|_| anyhow!("Spillover is already enabled"))
+    }
+
+    pub(crate) fn on_insert(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) -> HookId {
+        self.inner.insert_hooks.add(hook)
+    }
+
+    /// Applies `policy` to a new cache.
+    pub(crate) fn set_overflow_policy(&self, policy: OverflowPolicy) -> Result<()> {
+        match policy {
+            OverflowPolicy::Evict => {}
+            OverflowPolicy::Reject => self.inner.reject_when_full.store(true, Ordering::Relaxed),
+            OverflowPolicy::SpillToDisk(file_name) => self.enable_spillover(&file_name)?,
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_max_entry_weight(&self, max_weight: usize) -> Result<()> {
+        if self.inner.weigher.is_none() {
+            return Err(anyhow!(
+                "Only weighted caches can limit the weight of entries"
+            ));
+        }
+        self.inner
+            .max_entry_weight
+            .store(max_weight, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub(crate) fn on_reject(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) {
+        self.inner.reject_hooks.add(hook);
+    }
+
+    pub(crate) fn on_hit(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) {
+        self.inner.hit_hooks.add(hook);
+    }
+
+    pub(crate) fn on_removal(
+        &self,
+        listener: impl Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    ) -> HookId {
+        self.inner.listeners.add(listener)
+    }
+
+    /// Removes hooks added by [`Self::on_insert`] and [`Self::on_removal`].
+    pub(crate) fn remove_hooks(&self, insert_hook: HookId, listener: HookId) {
+        self.inner.insert_hooks.remove(insert_hook);
+        self.inner.listeners.remove(listener);
+    }
+
+    pub(crate) fn set_stats_recorder(&self, recorder: Arc<dyn StatsRecorder>) -> Result<()> {
+        self.inner.statistics.set_recorder(recorder)
+    }
+
+    /// Publishes removals to `bus` and applies the removals published on it.
+    pub(crate) fn join_invalidation(&self, bus: Arc<dyn InvalidationBus<K>>) -> Result<()> {
+        self.inner
+            .bus
+            .set(bus.clone())
+            .map_err(|_| anyhow!("An invalidation bus is already joined"))?;
+        // A weak reference, as the bus is owned by the cache
+        let cache = Arc::downgrade(&self.inner);
+        bus.subscribe(Box::new(move |key| {
+            if let Some(inner) = cache.upgrade() {
+                LRU { inner }.remove_unpublished(key);
+            }
+        }));
+        Ok(())
+    }
+
+    pub(crate) fn record_trace(&self, file_name: &str) -> Result<TraceHandle> {
+        if self.inner.trace.get().is_some() {
+            return Err(anyhow!("Accesses are already traced"));
+        }
+        let (tracer, handle) = TraceHandle::start(file_name)?;
+        self.inner
+            .trace
+            .set(tracer)
+            .map_err(|_| anyhow!("Accesses are already traced"))?;
+        Ok(handle)
+    }
+
+    fn trace(&self, op: TraceOp, key: Option<&K>) {
+        if let Some(tracer) = self.inner.trace.get() {
+            tracer.record(op, key);
+        }
+    }
+
+    pub(crate) fn enable_audit(&self, capacity: usize, file_name: Option<&str>) -> Result<()> {
+        if self.inner.audit.get().is_some() {
+            return Err(anyhow!("An audit log is already enabled"));
+        }
+        let audit = Arc::new(AuditLog::open(capacity, file_name)?);
+        self.inner
+            .audit
+            .set(audit.clone())
+            .map_err(|_| anyhow!("An audit log is already enabled"))?;
+        self.inner.events.set_tap(move |event| audit.record(event))
+    }
+
+    pub(crate) fn recent_mutations(&self, n: usize) -> Vec<Mutation<K>> {
+        match self.inner.audit.get() {
+            Some(audit) => audit.recent(n),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) fn track_hot_keys(&self, tracked: usize) -> Result<()> {
+        self.inner
+            .hot
+            .set(KeyTraffic::new(tracked))
+            .map_err(|_| anyhow!("Hot keys are already tracked"))
+    }
+
+    pub(crate) fn hottest(&self, n: usize) -> Vec<(K, u64)> {
+        match self.inner.hot.get() {
+            Some(hot) => hot.lookups.hottest(n),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) fn estimated_frequency(&self, key: &K) -> u64 {
+        self.inner
+            .hot
+            .get()
+            .map_or(0, |hot| hot.lookups.estimate(key))
+    }
+
+    pub(crate) fn top_misses(&self, n: usize) -> Vec<(K, u64)> {
+        match self.inner.hot.get() {
+            Some(hot) => hot.misses.hottest(n),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the capacity to report: the most entries, or the most weight
+    /// if only the weight is limited.
+    fn capacity(&self) -> Option<usize> {
+        let capacity = self.inner.capacity.load(Ordering::Relaxed);
+        match self.inner.weigher {
+            Some(_) if capacity == usize::MAX => Some(self.inner.max_weight),
+            _ => Some(capacity),
+        }
+    }
+
+    /// Sets the most entries the cache holds, evicting the least recently
+    /// used ones right away if it now holds too many. Returns how many
+    /// entries were evicted.
+    pub(crate) fn set_capacity(&self, capacity: usize) -> usize {
+        self.inner.capacity.store(capacity, Ordering::Relaxed);
+        self.evict_if_needed()
+    }
+
+    /// Returns whether the cache is limited by a number of entries, rather
+    /// than by weight alone.
+    pub(crate) fn has_entry_limit(&self) -> bool {
+        self.inner.capacity.load(Ordering::Relaxed) != usize::MAX
+    }
+
+    /// Moves the capacity toward `config`'s target hit ratio by what the
+    /// lookups since the last call say, returning how many entries were
+    /// evicted. Lookups are counted from the first call on.
+    pub(crate) fn autotune(&self, config: &Autotune) -> usize {
+        let ghosts = self.inner.ghosts.get_or_init(Ghosts::new);
+        let capacity = self.inner.capacity.load(Ordering::Relaxed);
+        let tuned = ghosts.tune(config, capacity);
+        match tuned == capacity {
+            true => 0,
+            false => self.set_capacity(tuned),
+        }
+    }
+
+    /// Lets the cache use only `share` of its limits until scaled back to 1,
+    /// returning how many entries were evicted to get there.
+    pub(crate) fn scale_capacity(&self, share: f64) -> usize {
+        let share = share.clamp(0.0, 1.0);
+        self.inner.scale.store(share.to_bits(), Ordering::Relaxed);
+        self.evict_if_needed()
+    }
+
+    pub(crate) fn set_low_watermark(&self, share: f64) -> Result<()> {
+        if !(share > 0.0 && share <= 1.0) {
+            return Err(anyhow!("The low watermark must be above 0 and at most 1"));
+        }
+        self.inner
+            .low_watermark
+            .store(share.to_bits(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the total weight of the entries in memory, if the cache has a weigher.
+    pub(crate) fn weight(&self) -> Option<usize> {
+        self.inner
+            .weigher
+            .as_ref()
+            .map(|_| self.inner.weight.load(Ordering::SeqCst))
+    }
+
+    /// Returns the entries in memory by weight, if the cache has a weigher.
+    pub(crate) fn weight_histogram(&self) -> Vec<WeightBucket> {
+        match self.inner.weigher {
+            Some(_) => self.inner.weights.buckets(),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) fn enable_bloom_filter(&self, expected: usize) -> Result<()> {
+        if self.inner.spill.get().is_some_and(|spill| spill.len() > 0) {
+            return Err(anyhow!(
+                "The bloom filter must be enabled before entries are spilled"
+            ));
+        }
+        // Counting the keys already cached under the order lock keeps
+        // inserts and removals from slipping in between
+        let order = self.inner.order.lock().recover();
+        let bloom = Bloom::new(expected);
+        for key in order.iter() {
+            bloom.add(&**key);
+        }
+        self.inner
+            .bloom
+            .set(bloom)
+            .map_err(|_| anyhow!("The bloom filter is already enabled"))
+    }
+
+    pub(crate) fn track_lifetimes(&self) -> Result<()> {
+        self.inner
+            .lifetimes
+            .set(Lifetimes::new())
+            .map_err(|_| anyhow!("Lifetimes are already tracked"))?;
+        // Entries already cached are measured from now
+        if let Some(lifetimes) = self.inner.lifetimes.get() {
+            for entry in self.inner.map.iter() {
+                lifetimes.born(entry.key());
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn lifetime_stats(&self) -> LifetimeStats {
+        self.inner
+            .lifetimes
+            .get()
+            .map(Lifetimes::stats)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<CacheEvent<K>> {
+        self.inner.events.subscribe()
+    }
+
+    pub(crate) fn subscribe_with(
+        &self,
+        capacity: usize,
+        overflow: Overflow,
+    ) -> Receiver<CacheEvent<K>> {
+        self.inner.events.subscribe_with(capacity, overflow)
+    }
+
+    pub(crate) fn spilled_len(&self) -> usize {
+        self.inner.spill.get().map_or(0, Spill::len)
+    }
+
+    pub(crate) fn sync_wal(&self) -> Result<()> {
+        match self.inner.wal.get() {
+            Some(wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Folds the log into a snapshot at `snapshot_file` and drops the
+    /// records it covers.
+    pub(crate) fn compact_wal(&self, snapshot_file: &str) -> Result<()> {
+        let wal = self
+            .inner
+            .wal
+            .get()
+            .ok_or_else(|| anyhow!("No write-ahead log is enabled"))?;
+        let checkpoint = wal.checkpoint()?;
+        self.write(snapshot_file)?;
+        wal.truncate_before(checkpoint)
+    }
+
+    pub(crate) fn wal_len(&self) -> Result<u64> {
+        match self.inner.wal.get() {
+            Some(wal) => wal.len(),
+            None => Ok(0),
+        }
+    }
+
+    /// Holds off log compaction while a mutation is logged and applied.
+    fn begin(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        self.inner.wal.get().map(Wal::begin)
+    }
+
+    /// Applies a logged mutation without logging it again.
+    fn apply(&self, record: Record<K, V>) {
+        match record {
+            Record::Insert(key, value) => {
+                self.insert_entry(key, value);
+            }
+            Record::Remove(key) => {
+                self.remove_entry(&key);
+            }
+            Record::Clear => self.clear_entries(),
+        }
+    }
+
+    fn log(&self, record: Record<&K, &V>) {
+        if let Some(wal) = self.inner.wal.get() {
+            if let Err(e) = wal.append(&record) {
+                report(e.context("Failed to append to write-ahead log"));
+            }
+        }
+    }
+}
+
+/// Serializes the capacity and the entries, from least to most recently used.
+///
+/// Weighted caches cannot be serialized, as their weigher could not be
+/// restored along with them.
+impl<K, V> Serialize for LRU<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.inner.weigher.is_some() {
+            return Err(ser::Error::custom("weighted caches cannot be serialized"));
+        }
+        CacheState {
+            policy: CachePolicy::LRU,
+            capacity: Some(self.inner.capacity.load(Ordering::Relaxed)),
+            entries: self.export().collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for LRU<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let state = CacheState::<K, V>::deserialize(deserializer)?;
+        let capacity = match (state.policy, state.capacity) {
+            (CachePolicy::LRU, Some(capacity)) => capacity,
+            _ => return Err(de::Error::custom("not an LRU cache")),
+        };
+        let cache = LRU::new(capacity);
+        cache
+            .load(state.entries, ReadMode::Merge)
+            .map_err(de::Error::custom)?;
+        Ok(cache)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Cache;
+    use super::LRU;
+    use crate::testing::TempPath;
+    use crate::{Cache, CachePolicy, CacheStats};
+    use std::time::Duration;
 
     #[test]
     fn test_insert_and_get() {
@@ -187,6 +1297,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_clear() {
         let cache = Cache::new_lru(3);
         cache.insert(1, "one".to_string());
@@ -217,6 +1328,427 @@ mod tests {
         assert_eq!(cache.misses(), 1);
     }
 
+    #[test]
+    fn test_evictions() {
+        let cache = Cache::new_lru(2);
+        cache.insert(1, "one".to_string());
+        cache.insert(2, "two".to_string());
+        cache.insert(3, "three".to_string());
+        assert_eq!(cache.evictions(), 1);
+
+        // Explicit removals are not evictions
+        cache.remove(&2);
+        cache.clear();
+        assert_eq!(cache.evictions(), 1);
+    }
+
+    #[test]
+    fn test_weigher() {
+        let cache = Cache::new_weighted(10, |_: &i32, value: &String| value.len() as u32);
+        cache.insert(1, "aaaa".to_string());
+        cache.insert(2, "bbbb".to_string());
+        assert_eq!(cache.weight(), Some(8));
+
+        // One entry is too few to make room for six units
+        cache.insert(3, "cccccc".to_string());
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.weight(), Some(10));
+
+        cache.insert(3, "c".to_string());
+        assert_eq!(cache.weight(), Some(5));
+        cache.insert(4, "dddddddddddd".to_string());
+        assert!(cache.is_empty());
+        assert_eq!(cache.weight(), Some(0));
+
+        cache.insert(5, "eee".to_string());
+        cache.remove(&5);
+        assert_eq!(cache.weight(), Some(0));
+        assert_eq!(Cache::<i32, String>::new_lru(1).weight(), None);
+    }
+
+    #[test]
+    fn test_memory_bounded() {
+        // Both strings serialize as a length and their bytes, and the key
+        // is shared behind a pointer from the map and one from the order
+        let shared = std::mem::size_of::<String>() + 4 * std::mem::size_of::<usize>();
+        let entry = (8 + 1) + (8 + 992) + shared + std::mem::size_of::<String>();
+        let cache = Cache::new_memory_bounded(3 * entry);
+        for key in 0..4 {
+            cache.insert(key.to_string(), "x".repeat(992));
+        }
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.weight(), Some(3 * entry));
+        assert_eq!(cache.get(&"0".to_string()), None);
+    }
+
+    #[test]
+    fn test_dual_limits() {
+        let cache = Cache::new_lru_weighted(2, 10, |_: &i32, value: &i32| *value as u32);
+        // Many light entries hit the entry limit
+        for key in 0..3 {
+            cache.insert(key, 1);
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.weight(), Some(2));
+
+        // A heavy entry hits the weight limit
+        cache.insert(3, 10);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&3), Some(10));
+        assert_eq!(cache.stats().capacity, Some(2));
+    }
+
+    #[test]
+    fn test_oversized() {
+        use std::sync::{Arc, Mutex};
+
+        let cache = Cache::new_weighted(10, |_: &i32, value: &i32| *value as u32);
+        assert!(Cache::<i32, i32>::new_lru(1)
+            .set_max_entry_weight(5)
+            .is_err());
+        cache.set_max_entry_weight(5).unwrap();
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        let log = rejected.clone();
+        cache.on_reject(move |key, value| log.lock().unwrap().push((*key, *value)));
+
+        cache.insert(1, 4);
+        cache.insert(2, 4);
+        cache.insert(1, 6);
+        // Nothing was evicted to make room, but the stale value is gone
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(4));
+        assert_eq!(cache.weight(), Some(4));
+        assert_eq!(*rejected.lock().unwrap(), vec![(1, 6)]);
+    }
+
+    #[test]
+    fn test_shared_keys() {
+        let cache = LRU::new(2);
+        cache.insert("a".to_string(), 1);
+        cache.insert("b".to_string(), 2);
+        cache.insert("a".to_string(), 3);
+        cache.get(&"b".to_string());
+        // Each key is held once, by both the map and the order
+        let order = cache.inner.order.lock().unwrap();
+        assert!(order
+            .iter()
+            .all(|key| std::sync::Arc::strong_count(key) == 2));
+        let entry = cache.inner.map.get(&order[0]).unwrap();
+        assert!(std::sync::Arc::ptr_eq(entry.key(), &order[0]));
+        drop(entry);
+        drop(order);
+
+        cache.insert("c".to_string(), 4);
+        let keys: Vec<String> = cache.export().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["b", "c"]);
+    }
+
+    #[test]
+    fn test_concurrent_capacity() {
+        let cache = LRU::new(64);
+        std::thread::scope(|scope| {
+            for thread in 0..32u64 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    let mut key = thread;
+                    for i in 0..2000u64 {
+                        key = (key * 1103515245 + 12345) % 512;
+                        match i % 8 {
+                            0 => {
+                                cache.remove(&key);
+                            }
+                            1..=3 => {
+                                cache.get(&key);
+                            }
+                            _ => cache.insert(key, i),
+                        }
+                        // Inserts evict under the order lock, which is also
+                        // needed for an exact `len`, as the map sums its
+                        // shards one at a time
+                        let order = cache.inner.order.lock().unwrap();
+                        assert!(order.len() <= 64);
+                        assert_eq!(cache.inner.map.len(), order.len());
+                    }
+                });
+            }
+        });
+        assert!(cache.len() <= 64);
+
+        // The map and the order hold the same keys, within capacity
+        let order = cache.inner.order.lock().unwrap();
+        assert_eq!(order.len(), cache.inner.map.len());
+        assert!(order.len() <= 64);
+        assert!(order.iter().all(|key| cache.inner.map.contains_key(key)));
+        // Every new key is accounted for as still cached, evicted or removed
+        let stats = cache.stats();
+        assert_eq!(stats.inserts, stats.len + stats.evictions + stats.removals);
+    }
+
+    #[test]
+    fn test_panicking_callbacks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let cache = Cache::new_weighted(10, |_: &i32, value: &i32| match value {
+            0 => panic!("the weigher fails"),
+            value => *value as u32,
+        });
+        let notified = Arc::new(AtomicUsize::new(0));
+        cache.on_evict(|_, _| panic!("the listener fails"));
+        cache.on_evict({
+            let notified = notified.clone();
+            move |_, _| {
+                notified.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        // An entry the weigher panics on weighs nothing
+        cache.insert(1, 5);
+        cache.insert(2, 0);
+        assert_eq!(cache.weight(), Some(5));
+        assert_eq!(cache.get(&2), Some(0));
+        // A panicking listener does not keep the others from being told
+        cache.insert(3, 6);
+        assert_eq!(notified.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.get(&3), Some(6));
+        assert_eq!(cache.remove(&2), Some(0));
+    }
+
+    #[test]
+    fn test_insert_with_cost() {
+        let cache = Cache::new_weighted(10, |_: &i32, _: &String| 1);
+        cache.insert_with_cost(1, "one".to_string(), 4);
+        cache.insert(2, "two".to_string());
+        assert_eq!(cache.weight(), Some(5));
+
+        // Replacing and removing take off the recorded cost, not the weigher's
+        cache.insert_with_cost(1, "uno".to_string(), 6);
+        assert_eq!(cache.weight(), Some(7));
+        cache.insert(1, "one".to_string());
+        assert_eq!(cache.weight(), Some(2));
+        cache.insert_with_cost(3, "three".to_string(), 8);
+        cache.remove(&3);
+        assert_eq!(cache.weight(), Some(2));
+
+        // Evicting does too
+        cache.insert_with_cost(3, "three".to_string(), 9);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.weight(), Some(10));
+        cache.set_max_entry_weight(5).unwrap();
+        cache.insert_with_cost(4, "four".to_string(), 6);
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(cache.weight(), Some(10));
+    }
+
+    #[test]
+    fn test_set_capacity() {
+        let cache = Cache::new_lru(4);
+        for key in 0..4 {
+            cache.insert(key, key);
+        }
+        cache.get(&0);
+
+        assert_eq!(cache.set_capacity(2), 2);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&0), Some(0));
+        assert_eq!(cache.stats().capacity, Some(2));
+
+        assert_eq!(cache.set_capacity(3), 0);
+        cache.insert(4, 4);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(Cache::<i32, i32>::new_unbounded().set_capacity(1), 0);
+    }
+
+    #[test]
+    fn test_low_watermark() {
+        let cache = Cache::new_lru(10);
+        assert!(cache.set_low_watermark(0.0).is_err());
+        assert!(cache.set_low_watermark(1.5).is_err());
+        cache.set_low_watermark(0.5).unwrap();
+        for key in 0..10 {
+            cache.insert(key, key);
+        }
+        assert_eq!(cache.len(), 10);
+
+        // Going over the capacity evicts down to half of it in one batch
+        cache.insert(10, 10);
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.evictions(), 6);
+        assert_eq!(cache.peek(&6), Some(6));
+        cache.insert(11, 11);
+        assert_eq!(cache.len(), 6);
+    }
+
+    #[test]
+    fn test_autotune() {
+        let cache = Cache::new_lru(10);
+        let config = crate::Autotune {
+            step: 5,
+            ..crate::Autotune::new(5, 40, 0.9)
+        };
+        let handle = cache.autotune(config, Duration::from_secs(60)).unwrap();
+        handle.flush().unwrap();
+        let round = |keys: i32| {
+            for _ in 0..3 {
+                for key in 0..keys {
+                    if cache.get(&key).is_none() {
+                        cache.insert(key, key);
+                    }
+                }
+            }
+            handle.flush().unwrap();
+            cache.stats().capacity.unwrap()
+        };
+
+        // Cycling through 13 keys misses the ones just evicted, so it grows
+        assert_eq!(round(13), 15);
+        // Every hit is then in the tail, which is not dead weight
+        assert_eq!(round(13), 15);
+        // A few keys leave the tail unused, so it shrinks
+        assert_eq!(round(3), 15);
+        assert_eq!(round(3), 10);
+        assert_eq!(round(3), 5);
+        assert_eq!(round(3), 5);
+        assert!(Cache::<i32, i32>::new_unbounded()
+            .autotune(crate::Autotune::new(1, 2, 0.5), Duration::from_secs(60))
+            .is_err());
+    }
+
+    #[test]
+    fn test_scale_capacity() {
+        use std::sync::{Arc, Mutex};
+
+        let cache = Cache::new_lru(4);
+        for key in 0..4 {
+            cache.insert(key, key);
+        }
+        assert_eq!(cache.scale_capacity(0.5), 2);
+        cache.insert(4, 4);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats().capacity, Some(4));
+
+        assert_eq!(cache.scale_capacity(1.0), 0);
+        cache.insert(5, 5);
+        cache.insert(6, 6);
+        assert_eq!(cache.len(), 4);
+
+        // A background reader scales the cache to what it reports
+        let share = Arc::new(Mutex::new(0.25));
+        let reported = share.clone();
+        let handle = cache
+            .shrink_under_pressure(Duration::from_secs(3600), move || *reported.lock().unwrap())
+            .unwrap();
+        handle.flush().unwrap();
+        assert_eq!(cache.len(), 1);
+        *share.lock().unwrap() = 1.0;
+        handle.flush().unwrap();
+        cache.insert(7, 7);
+        assert_eq!(cache.len(), 2);
+        assert!(Cache::<i32, i32>::new_unbounded()
+            .shrink_under_pressure(Duration::from_secs(1), || 1.0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        use crate::OverflowPolicy;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let cache = Cache::new_bounded(2, OverflowPolicy::Reject).unwrap();
+        let rejected = Arc::new(AtomicUsize::new(0));
+        let counted = rejected.clone();
+        cache.on_reject(move |_, _| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.insert(3, 3);
+        cache.insert(1, 10);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.evictions(), 0);
+        assert_eq!(rejected.load(Ordering::SeqCst), 1);
+
+        let cache = Cache::new_bounded(1, OverflowPolicy::Evict).unwrap();
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert_eq!(cache.evictions(), 1);
+
+        let path = TempPath::new("overflow_spill.cache");
+        let policy = OverflowPolicy::SpillToDisk(path.as_str().to_string());
+        let cache = Cache::new_bounded(1, policy).unwrap();
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        assert_eq!(cache.get(&1), Some(1));
+    }
+
+    #[test]
+    fn test_lifetimes() {
+        let cache = Cache::new_lru(2);
+        cache.insert(1, "one".to_string());
+        cache.track_lifetimes().unwrap();
+        assert!(cache.track_lifetimes().is_err());
+        std::thread::sleep(Duration::from_millis(20));
+        cache.insert(2, "two".to_string());
+        cache.remove(&2);
+        cache.insert(3, "three".to_string());
+        cache.insert(4, "four".to_string());
+
+        // Only the eviction of 1 is measured, not the removal of 2
+        let stats = cache.lifetime_stats();
+        assert_eq!(stats.count, 1);
+        assert!(stats.max >= Duration::from_millis(20));
+        assert_eq!(stats.mean, stats.max);
+        assert_eq!(stats.p50, stats.max);
+
+        assert!(Cache::<i32, i32>::new_unbounded()
+            .track_lifetimes()
+            .is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        let cache = Cache::new_lru(2);
+        cache.insert(1, "one".to_string());
+        cache.insert(1, "uno".to_string());
+        cache.insert(2, "two".to_string());
+        cache.insert(3, "three".to_string());
+        cache.get(&3);
+        cache.remove(&3);
+        cache.remove(&4);
+        cache.get(&1);
+        assert_eq!(cache.hit_ratio(), 0.5);
+        assert_eq!(cache.recent_hit_ratio(Duration::from_secs(60)), 0.5);
+
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                len: 1,
+                capacity: Some(2),
+                hits: 1,
+                misses: 1,
+                evictions: 1,
+                inserts: 3,
+                updates: 1,
+                removals: 1,
+            }
+        );
+
+        cache.reset_stats();
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                len: 1,
+                capacity: Some(2),
+                ..CacheStats::default()
+            }
+        );
+        assert_eq!(cache.recent_hit_ratio(Duration::from_secs(60)), 0.0);
+    }
+
     #[test]
     fn test_multithreaded() {
         let cache = Cache::new_lru(5);
@@ -238,4 +1770,288 @@ mod tests {
         // Verify the cache size
         assert_eq!(cache.len(), 5, "Cache size is {}", cache.len());
     }
+
+    #[test]
+    fn test_write_and_read() {
+        let path = TempPath::new("lru.cache");
+        let path = path.as_str();
+
+        let cache = Cache::new_lru(3);
+        cache.insert(1, "one".to_string());
+        cache.insert(2, "two".to_string());
+        cache.insert(3, "three".to_string());
+        cache.get(&1);
+        cache.write(path).unwrap();
+
+        let cache2 = Cache::new_lru(3);
+        cache2.read(path).unwrap();
+        assert_eq!(cache2.len(), 3);
+
+        // Key 2 was the least recently used when written, so it is evicted first
+        cache2.insert(4, "four".to_string());
+        assert_eq!(cache2.get(&2), None);
+        assert_eq!(cache2.get(&1), Some("one".to_string()));
+    }
+
+    #[test]
+    fn test_write_and_read_sharded() {
+        let path = TempPath::new("lru_sharded.cache");
+        let path = path.as_str();
+        let _shards: Vec<_> = (0..4)
+            .map(|i| TempPath::new(&format!("lru_sharded.cache.{}", i)))
+            .collect();
+
+        let cache = Cache::new_lru(1000);
+        for i in 0..1000 {
+            cache.insert(i, i * 2);
+        }
+        cache.write_sharded(path, 4).unwrap();
+
+        let loaded = Cache::new_lru(1000);
+        loaded.read_sharded(path).unwrap();
+        assert_eq!(loaded.len(), 1000);
+        assert_eq!(loaded.get(&500), Some(1000));
+        // The shards hold contiguous runs of the recency order
+        loaded.insert(1000, 1000);
+        assert_eq!(loaded.get(&0), None);
+        assert_eq!(loaded.get(&1), Some(2));
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let cache = Cache::new_lru(3);
+        cache.insert(1, "one".to_string());
+        cache.insert(2, "two".to_string());
+        cache.insert(3, "three".to_string());
+        cache.get(&1);
+
+        let exported: Vec<(i32, String)> = cache.export().collect();
+        assert_eq!(
+            exported.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![2, 3, 1]
+        );
+
+        let imported = Cache::new_lru(2);
+        imported.import(exported);
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported.get(&2), None);
+        assert_eq!(imported.get(&1), Some("one".to_string()));
+    }
+
+    #[test]
+    fn test_statistics_survive_snapshot() {
+        let path = TempPath::new("lru_metadata.cache");
+        let path = path.as_str();
+
+        let cache = Cache::new_lru(2);
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        cache.insert(3, 30);
+        cache.get(&3);
+        cache.get(&1);
+        cache.write(path).unwrap();
+
+        let metadata = Cache::<i32, i32>::read_metadata(path).unwrap().unwrap();
+        assert_eq!(metadata.policy, CachePolicy::LRU);
+        assert_eq!(metadata.capacity, Some(2));
+        assert_eq!(metadata.len, 2);
+        assert_eq!(
+            (metadata.hits, metadata.misses, metadata.evictions),
+            (1, 1, 1)
+        );
+        assert_eq!(Some(metadata.created), cache.created());
+
+        let loaded: Cache<i32, i32> = Cache::new_lru(2);
+        loaded.get(&4);
+        loaded.read(path).unwrap();
+        assert_eq!(loaded.hits(), 1);
+        assert_eq!(loaded.misses(), 2);
+        assert_eq!(loaded.evictions(), 1);
+        assert_eq!(loaded.created(), cache.created());
+    }
+
+    #[test]
+    fn test_read_beyond_capacity() {
+        let path = TempPath::new("lru_beyond_capacity.cache");
+        let path = path.as_str();
+
+        let cache = Cache::new_lru(10);
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        // Make key 0 the most recently used
+        cache.get(&0);
+        cache.write(path).unwrap();
+
+        let loaded: Cache<i32, i32> = Cache::new_lru(3);
+        loaded.insert(100, 100);
+        loaded.read(path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.evictions(), 1);
+        assert_eq!(loaded.get(&100), None);
+        let keys: Vec<i32> = loaded.export().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![8, 9, 0]);
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let path = TempPath::new("lru_bloom_spill.bin");
+        let path = path.as_str();
+
+        let cache = LRU::new(2);
+        cache.insert(1, "one".to_string());
+        cache.enable_spillover(path).unwrap();
+        cache.enable_bloom_filter(100).unwrap();
+        assert!(cache.enable_bloom_filter(100).is_err());
+        let bloom = cache.inner.bloom.get().unwrap();
+        assert!(bloom.may_contain(&1));
+
+        // Spilled keys are still counted, evicted and removed ones are not
+        cache.insert(2, "two".to_string());
+        cache.insert(3, "three".to_string());
+        assert!(bloom.may_contain(&1));
+        assert_eq!(cache.get(&1), Some("one".to_string()));
+        cache.remove(&2);
+        cache.remove(&3);
+        assert!(!bloom.may_contain(&2) && !bloom.may_contain(&3));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        cache.clear();
+        assert!(!bloom.may_contain(&1));
+
+        let path = TempPath::new("lru_bloom_late.bin");
+        let path = path.as_str();
+        let spilled = LRU::new(1);
+        spilled.enable_spillover(path).unwrap();
+        spilled.insert(1, "one".to_string());
+        spilled.insert(2, "two".to_string());
+        assert!(spilled.enable_bloom_filter(100).is_err());
+    }
+
+    #[test]
+    fn test_spillover() {
+        let path = TempPath::new("lru_spill.bin");
+        let path = path.as_str();
+
+        let cache = Cache::new_lru(2);
+        cache.enable_spillover(path).unwrap();
+        cache.insert(1, "one".to_string());
+        cache.insert(2, "two".to_string());
+        cache.insert(3, "three".to_string());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.spilled_len(), 1);
+
+        // Reading 1 back promotes it and demotes 2, the least recently used
+        assert_eq!(cache.get(&1), Some("one".to_string()));
+        assert_eq!(cache.spilled_len(), 1);
+        assert_eq!(cache.remove(&2), Some("two".to_string()));
+        assert_eq!(cache.spilled_len(), 0);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.hits(), 1);
+
+        cache.insert(4, "four".to_string());
+        cache.clear();
+        assert_eq!(cache.spilled_len(), 0);
+        assert_eq!(cache.get(&3), None);
+
+        drop(cache);
+        assert!(!std::path::Path::new(path).exists());
+    }
+
+    #[test]
+    fn test_on_evict() {
+        use std::sync::{Arc, Mutex};
+
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let cache = Cache::new_lru(2);
+        let sink = evicted.clone();
+        cache.on_evict(move |key, value| sink.lock().unwrap().push((key, value)));
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        cache.remove(&3);
+        assert_eq!(*evicted.lock().unwrap(), vec![(0, 0), (1, 10)]);
+    }
+
+    #[test]
+    fn test_on_removal() {
+        use crate::RemovalCause::{Evicted, Explicit, Replaced};
+        use std::sync::{Arc, Mutex};
+
+        let path = TempPath::new("on_removal_spill.bin");
+        let removed = Arc::new(Mutex::new(Vec::new()));
+        let cache = Cache::new_lru(2);
+        cache.enable_spillover(path.as_str()).unwrap();
+        let sink = removed.clone();
+        cache.on_removal(move |key, value, cause| sink.lock().unwrap().push((key, value, cause)));
+
+        cache.insert(1, 10);
+        cache.insert(1, 11);
+        cache.insert(2, 20);
+        // Spills 1, which is then overwritten
+        cache.insert(3, 30);
+        cache.insert(1, 12);
+        cache.remove(&3);
+        cache.clear();
+
+        let mut removed = removed.lock().unwrap().clone();
+        removed[3..].sort_by_key(|(key, _, _)| *key);
+        assert_eq!(
+            removed,
+            vec![
+                (1, 10, Replaced),
+                (1, 11, Replaced),
+                (3, 30, Explicit),
+                (1, 12, Explicit),
+                (2, 20, Explicit),
+            ]
+        );
+        drop(cache);
+
+        // Without spillover, evicted entries are gone
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let cache = Cache::new_lru(1);
+        let sink = evicted.clone();
+        cache.on_removal(move |key, value, cause| sink.lock().unwrap().push((key, value, cause)));
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        assert_eq!(*evicted.lock().unwrap(), vec![(1, 10, Evicted)]);
+    }
+
+    #[test]
+    fn test_serde() {
+        let cache = LRU::new(2);
+        cache.insert(1, "one".to_string());
+        cache.insert(2, "two".to_string());
+        cache.get(&1);
+
+        let encoded = bincode::serialize(&cache).unwrap();
+        let decoded: LRU<i32, String> = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(
+            decoded.export().collect::<Vec<_>>(),
+            cache.export().collect::<Vec<_>>()
+        );
+        decoded.insert(3, "three".to_string());
+        assert_eq!(decoded.get(&2), None);
+
+        let unbounded = crate::unbounded::Unbounded::<i32, String>::new();
+        let encoded = bincode::serialize(&unbounded).unwrap();
+        assert!(bincode::deserialize::<LRU<i32, String>>(&encoded).is_err());
+
+        // The weigher would be lost, so weighted caches are refused
+        let weighted = LRU::with_weigher(usize::MAX, 10, Some(Box::new(|_: &i32, _: &i32| 1)));
+        weighted.insert(1, 1);
+        assert!(bincode::serialize(&weighted).is_err());
+    }
+
+    #[test]
+    fn test_load_weighted() {
+        let cache = LRU::with_weigher(3, 9, Some(Box::new(|_: &i32, value: &u32| *value)));
+        let entries = vec![(1, 1), (2, 2), (3, 4), (4, 5), (5, 1)];
+        assert_eq!(cache.fitting(&entries), 2);
+        cache.load(entries, crate::ReadMode::Merge).unwrap();
+        assert_eq!(cache.export().collect::<Vec<_>>(), [(4, 5), (5, 1)]);
+        assert_eq!(cache.stats().evictions, 0);
+    }
 }
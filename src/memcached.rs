@@ -0,0 +1,357 @@
+//! A server for the memcached text protocol, backed by a byte-keyed cache.
+//!
+//! Supports `get`, `gets`, `set`, `add`, `replace`, `delete`, `flush_all`,
+//! `stats`, `version` and `quit`. Flags are accepted but not stored, so
+//! values always come back with flags 0, and expiration times are ignored.
+use crate::report::report;
+use crate::Cache;
+use anyhow::{anyhow, Result};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Longest key the protocol allows.
+const MAX_KEY_LEN: usize = 250;
+
+/// Longest command line read; longer ones end the connection.
+const MAX_LINE_LEN: u64 = 8 * 1024;
+
+/// The largest value stored by default, as in memcached.
+pub const DEFAULT_MAX_ITEM_SIZE: usize = 1024 * 1024;
+
+/// What a server started with [`serve_with`] accepts from its clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerLimits {
+    /// The largest value stored, in bytes
+    pub max_item_size: usize,
+    /// The most connections served at once; more are closed on accept
+    pub max_connections: usize,
+    /// How long a read or write may wait on a client before its connection
+    /// is closed, or `None` to wait as long as it takes
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        ServerLimits {
+            max_item_size: DEFAULT_MAX_ITEM_SIZE,
+            max_connections: 1024,
+            timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Counts a connection being served until dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Accepts connections on `listener` and serves each on its own thread,
+/// within the default [`ServerLimits`].
+pub fn serve(cache: Cache<Vec<u8>, Vec<u8>>, listener: TcpListener) -> Result<()> {
+    serve_with(cache, listener, ServerLimits::default())
+}
+
+/// Like [`serve`], but within `limits`.
+pub fn serve_with(
+    cache: Cache<Vec<u8>, Vec<u8>>,
+    listener: TcpListener,
+    limits: ServerLimits,
+) -> Result<()> {
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                report(anyhow!(e).context("Failed to accept connection"));
+                continue;
+            }
+        };
+        if connections.fetch_add(1, Ordering::SeqCst) >= limits.max_connections {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            // As memcached does, tell the client why before closing
+            let _ = stream.write_all(b"SERVER_ERROR too many open connections\r\n");
+            report(anyhow!("Refused connection: too many connections"));
+            continue;
+        }
+        let slot = ConnectionSlot(connections.clone());
+        let cache = cache.clone();
+        std::thread::spawn(move || {
+            let _slot = slot;
+            if let Err(e) = serve_stream(&cache, stream, &limits) {
+                report(e.context("Connection failed"));
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve_stream(
+    cache: &Cache<Vec<u8>, Vec<u8>>,
+    stream: TcpStream,
+    limits: &ServerLimits,
+) -> Result<()> {
+    stream.set_read_timeout(limits.timeout)?;
+    stream.set_write_timeout(limits.timeout)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let writer = BufWriter::new(stream);
+    serve_connection(cache, reader, writer, limits.max_item_size)
+}
+
+/// Answers the commands read from `reader` until it ends or the client
+/// quits, storing values of up to `max_item_size` bytes.
+pub fn serve_connection(
+    cache: &Cache<Vec<u8>, Vec<u8>>,
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    max_item_size: usize,
+) -> Result<()> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader
+            .by_ref()
+            .take(MAX_LINE_LEN)
+            .read_until(b'\n', &mut line)?
+            == 0
+        {
+            return Ok(());
+        }
+        if !line.ends_with(b"\n") && line.len() as u64 == MAX_LINE_LEN {
+            writer.write_all(b"CLIENT_ERROR line too long\r\n")?;
+            writer.flush()?;
+            return Ok(());
+        }
+        let words: Vec<&[u8]> = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|w| !w.is_empty())
+            .collect();
+        let Some((&command, args)) = words.split_first() else {
+            writer.write_all(b"ERROR\r\n")?;
+            writer.flush()?;
+            continue;
+        };
+
+        match command {
+            b"get" | b"gets" => {
+                for &key in args {
+                    if let Some(value) = cache.get(&key.to_vec()) {
+                        write!(writer, "VALUE ")?;
+                        writer.write_all(key)?;
+                        if command == b"gets" {
+                            write!(writer, " 0 {} 0\r\n", value.len())?;
+                        } else {
+                            write!(writer, " 0 {}\r\n", value.len())?;
+                        }
+                        writer.write_all(&value)?;
+                        writer.write_all(b"\r\n")?;
+                    }
+                }
+                writer.write_all(b"END\r\n")?;
+            }
+            b"set" | b"add" | b"replace" => {
+                let Some(reply) = store(cache, command, args, &mut reader, max_item_size)? else {
+                    writer.write_all(b"CLIENT_ERROR bad data chunk\r\n")?;
+                    writer.flush()?;
+                    return Ok(());
+                };
+                if args.get(4) != Some(&&b"noreply"[..]) {
+                    writer.write_all(reply)?;
+                }
+            }
+            b"delete" => {
+                let reply: &[u8] = match args.first() {
+                    Some(key) => match cache.remove(&key.to_vec()) {
+                        Some(_) => b"DELETED\r\n",
+                        None => b"NOT_FOUND\r\n",
+                    },
+                    None => b"ERROR\r\n",
+                };
+                if args.last() != Some(&&b"noreply"[..]) {
+                    writer.write_all(reply)?;
+                }
+            }
+            b"flush_all" => {
+                cache.clear();
+                if args.last() != Some(&&b"noreply"[..]) {
+                    writer.write_all(b"OK\r\n")?;
+                }
+            }
+            b"stats" => {
+                let stats = cache.stats();
+                write!(writer, "STAT curr_items {}\r\n", stats.len)?;
+                write!(writer, "STAT get_hits {}\r\n", stats.hits)?;
+                write!(writer, "STAT get_misses {}\r\n", stats.misses)?;
+                write!(writer, "STAT evictions {}\r\n", stats.evictions)?;
+                writer.write_all(b"END\r\n")?;
+            }
+            b"version" => write!(writer, "VERSION {}\r\n", env!("CARGO_PKG_VERSION"))?,
+            b"quit" => return Ok(()),
+            _ => writer.write_all(b"ERROR\r\n")?,
+        }
+        writer.flush()?;
+    }
+}
+
+/// Handles a storage command, reading its data block from `reader`.
+///
+/// Returns `None` if the data block cannot be skipped, as its length does
+/// not fit in memory, and the connection cannot go on.
+fn store(
+    cache: &Cache<Vec<u8>, Vec<u8>>,
+    command: &[u8],
+    args: &[&[u8]],
+    reader: &mut impl BufRead,
+    max_item_size: usize,
+) -> Result<Option<&'static [u8]>> {
+    let [key, _flags, _exptime, len, ..] = args else {
+        return Ok(Some(b"ERROR\r\n"));
+    };
+    let Some(len) = std::str::from_utf8(len)
+        .ok()
+        .and_then(|len| len.parse::<usize>().ok())
+    else {
+        return Ok(Some(b"CLIENT_ERROR bad command line format\r\n"));
+    };
+    let Some(block_len) = len.checked_add(2) else {
+        return Ok(None);
+    };
+    if len > max_item_size {
+        // Skip the block without holding it in memory
+        io::copy(&mut reader.by_ref().take(block_len as u64), &mut io::sink())?;
+        return Ok(Some(b"SERVER_ERROR object too large for cache\r\n"));
+    }
+
+    let mut data = vec![0; block_len];
+    reader.read_exact(&mut data)?;
+    if !data.ends_with(b"\r\n") {
+        return Ok(Some(b"CLIENT_ERROR bad data chunk\r\n"));
+    }
+    data.truncate(len);
+    if key.len() > MAX_KEY_LEN {
+        return Ok(Some(b"CLIENT_ERROR key too long\r\n"));
+    }
+
+    let key = key.to_vec();
+    // The check and the insert are separate steps, so racing adds can both
+    // succeed. Peeking leaves the statistics and recency alone.
+    let exists = || cache.peek(&key).is_some();
+    let stored = match command {
+        b"add" => !exists(),
+        b"replace" => exists(),
+        _ => true,
+    };
+    if !stored {
+        return Ok(Some(b"NOT_STORED\r\n"));
+    }
+    cache.insert(key, data);
+    Ok(Some(b"STORED\r\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{serve_connection, serve_with, ServerLimits, DEFAULT_MAX_ITEM_SIZE};
+    use crate::Cache;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    fn session(cache: &Cache<Vec<u8>, Vec<u8>>, input: &str) -> String {
+        let mut output = Vec::new();
+        serve_connection(cache, input.as_bytes(), &mut output, DEFAULT_MAX_ITEM_SIZE).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_protocol() {
+        let cache = Cache::new_lru(10);
+        let output = session(
+            &cache,
+            "set a 5 0 3\r\none\r\n\
+             add a 0 0 3\r\nuno\r\n\
+             replace b 0 0 3\r\ntwo\r\n\
+             set b 0 0 3 noreply\r\ntwo\r\n\
+             get a b c\r\n\
+             delete a\r\n\
+             delete a\r\n\
+             bogus\r\n\
+             quit\r\n\
+             get b\r\n",
+        );
+        assert_eq!(
+            output,
+            "STORED\r\n\
+             NOT_STORED\r\n\
+             NOT_STORED\r\n\
+             VALUE a 0 3\r\none\r\nVALUE b 0 3\r\ntwo\r\nEND\r\n\
+             DELETED\r\n\
+             NOT_FOUND\r\n\
+             ERROR\r\n"
+        );
+        assert_eq!(cache.get(&b"b".to_vec()), Some(b"two".to_vec()));
+        // Checking whether add or replace may store is not a lookup
+        assert_eq!((cache.hits(), cache.misses()), (3, 1));
+    }
+
+    #[test]
+    fn test_limits() {
+        let cache = Cache::new_lru(10);
+        let mut output = Vec::new();
+        let input = "set a 0 0 5\r\nhello\r\nset b 0 0 4\r\nfits\r\nget a b\r\n";
+        serve_connection(&cache, input.as_bytes(), &mut output, 4).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "SERVER_ERROR object too large for cache\r\n\
+             STORED\r\n\
+             VALUE b 0 4\r\nfits\r\nEND\r\n"
+        );
+
+        // Lengths that cannot be held, and endless lines, end the connection
+        let overflowing = format!("set a 0 0 {}\r\nget a\r\n", usize::MAX);
+        assert_eq!(
+            session(&cache, &overflowing),
+            "CLIENT_ERROR bad data chunk\r\n"
+        );
+        let endless = format!("get {}\r\nget b\r\n", "a".repeat(10_000));
+        assert_eq!(session(&cache, &endless), "CLIENT_ERROR line too long\r\n");
+    }
+
+    #[test]
+    fn test_server_limits() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let limits = ServerLimits {
+            max_connections: 1,
+            timeout: Some(Duration::from_millis(100)),
+            ..ServerLimits::default()
+        };
+        std::thread::spawn(move || serve_with(Cache::new_lru(10), listener, limits));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"version\r\n").unwrap();
+        let mut reply = [0; 8];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"VERSION ");
+
+        // A second connection is told why and closed at once
+        let mut refused = TcpStream::connect(addr).unwrap();
+        let mut output = String::new();
+        refused.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "SERVER_ERROR too many open connections\r\n");
+
+        // A client that stalls is disconnected, freeing its slot
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"version\r\n").unwrap();
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"VERSION ");
+    }
+}
@@ -0,0 +1,220 @@
+//! Memory-mapped, lazily deserialized snapshots.
+use crate::persistence::{self, SnapshotMetadata, FRAME_HEADER};
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::ops::{Deref, Range};
+
+/// A read-only mapping of a whole file.
+struct Mmap {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    data: Vec<u8>,
+}
+
+// The mapping is read-only and never aliased mutably
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    #[cfg(unix)]
+    fn open(file: &File) -> Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Mmap {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+        // SAFETY: a private read-only mapping of a file we hold open; the
+        // mapping stays valid after the descriptor is closed
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Mmap { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    fn open(mut file: &File) -> Result<Self> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Mmap { data })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: `ptr` points to `len` mapped, readable bytes
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: unmapping the region returned by `mmap`
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+/// A snapshot file opened through a memory mapping.
+///
+/// Opening verifies the checksums and indexes the keys, but values stay in
+/// the mapping until they are requested with [`MappedSnapshot::get`], so
+/// memory use is proportional to the keys rather than the whole file.
+///
+/// The file must not be truncated while it is mapped: reading a page past
+/// its new end raises `SIGBUS`, which kills the process. Snapshots written
+/// by this crate replace the file by renaming over it, which leaves the
+/// mapped file intact.
+pub struct MappedSnapshot<K, V> {
+    map: Mmap,
+    /// Bytes of each key's value within the mapping
+    index: HashMap<K, Range<usize>>,
+    metadata: Option<SnapshotMetadata>,
+    _marker: PhantomData<fn() -> V>,
+}
+
+impl<K, V> MappedSnapshot<K, V>
+where
+    K: Eq + Hash + for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    /// Maps the snapshot at `file_name` and indexes its keys.
+    ///
+    /// Values are skipped by their length, except in snapshots written
+    /// before version 3, where they must be decoded to find their end.
+    pub fn open(file_name: &str) -> Result<Self> {
+        let file = File::open(file_name)?;
+        let map = Mmap::open(&file)?;
+
+        let mut index = HashMap::new();
+        let pos = persistence::check_header::<K, V>(&map)?;
+        let (metadata, mut pos) = persistence::read_metadata(&map, pos)?;
+        let version = persistence::version(&map);
+        loop {
+            let payload = persistence::read_frame(&map, pos)?;
+            let start = pos + FRAME_HEADER;
+            pos = start + payload.len();
+            if payload.is_empty() {
+                break;
+            }
+
+            // Walk the entries one at a time, keeping only the keys
+            let mut rest = payload;
+            let count: u64 = bincode::deserialize_from(&mut rest)?;
+            for _ in 0..count {
+                let key: K = persistence::decode_from(&mut rest, usize::MAX)?;
+                let value = if version < persistence::VALUE_LEN_VERSION {
+                    let offset = start + payload.len() - rest.len();
+                    let _: V = persistence::decode_from(&mut rest, usize::MAX)?;
+                    offset..start + payload.len() - rest.len()
+                } else {
+                    let value = persistence::split_value(&mut rest)?;
+                    let offset = start + payload.len() - rest.len() - value.len();
+                    offset..offset + value.len()
+                };
+                index.insert(key, value);
+            }
+        }
+        persistence::check_trailer(&map, pos)?;
+
+        Ok(MappedSnapshot {
+            map,
+            index,
+            metadata,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Deserializes the value for `key` from the mapping, failing if the
+    /// bytes of the value do not decode.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        let Some(value) = self.index.get(key) else {
+            return Ok(None);
+        };
+        Ok(Some(persistence::decode_value(&self.map[value.clone()])?))
+    }
+
+    /// Returns the metadata stored with the snapshot, if it has any.
+    pub fn metadata(&self) -> Option<&SnapshotMetadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.index.contains_key(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.index.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MappedSnapshot;
+    use crate::testing::TempPath;
+    use crate::Cache;
+
+    #[test]
+    fn test_mapped_snapshot() {
+        let path = TempPath::new("mapped.cache");
+        let path = path.as_str();
+
+        let cache = Cache::new_unbounded();
+        for i in 0..5000 {
+            cache.insert(i, vec![i; 10]);
+        }
+        cache.write(path).unwrap();
+
+        let snapshot: MappedSnapshot<i32, Vec<i32>> = MappedSnapshot::open(path).unwrap();
+        assert_eq!(snapshot.len(), 5000);
+        assert_eq!(snapshot.get(&1234).unwrap(), Some(vec![1234; 10]));
+        assert_eq!(snapshot.get(&5000).unwrap(), None);
+        assert!(snapshot.contains_key(&0));
+        assert_eq!(snapshot.metadata().unwrap().len, 5000);
+    }
+}
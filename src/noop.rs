@@ -0,0 +1,87 @@
+//! A disabled cache that stores nothing but keeps the statistics of a cache
+//! that never hits.
+use crate::{CacheStats, ShardStats, Statistics, StatsRecorder};
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The state of [`crate::Cache::Noop`]: counters only.
+///
+/// Every lookup is a miss and every insert is counted and dropped, so a
+/// deployment with caching disabled reports the same metrics as one with
+/// it enabled, for comparing the two.
+#[derive(Clone)]
+pub struct Noop {
+    statistics: Arc<Statistics>,
+}
+
+impl Noop {
+    pub(crate) fn new() -> Self {
+        Noop {
+            statistics: Arc::new(Statistics::new(1)),
+        }
+    }
+
+    pub(crate) fn insert(&self) {
+        self.statistics.add_insert(false);
+    }
+
+    pub(crate) fn get(&self) {
+        self.statistics.add_miss(0);
+    }
+
+    pub(crate) fn misses(&self) -> usize {
+        self.statistics.misses()
+    }
+
+    pub(crate) fn recent_hit_ratio(&self, window: Duration) -> f64 {
+        self.statistics.recent_hit_ratio(window)
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.statistics.stats(0, None)
+    }
+
+    pub(crate) fn shard_stats(&self) -> Vec<ShardStats> {
+        self.statistics.shard_stats(std::iter::once(0))
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.statistics.reset();
+    }
+
+    pub(crate) fn created(&self) -> SystemTime {
+        self.statistics.created()
+    }
+
+    pub(crate) fn set_stats_recorder(&self, recorder: Arc<dyn StatsRecorder>) -> Result<()> {
+        self.statistics.set_recorder(recorder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Cache;
+
+    #[test]
+    fn test_noop() {
+        let cache = Cache::new_noop();
+        assert!(cache.is_none());
+        cache.insert(1, 1);
+        cache.insert(1, 2);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.peek(&1), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.len, stats.inserts, stats.updates), (0, 2, 0));
+        assert_eq!((stats.hits, stats.misses), (0, 1));
+        assert_eq!(cache.misses(), 1);
+        assert_eq!(cache.shard_stats()[0].misses, 1);
+        assert!(cache.created().is_some());
+
+        // Plain None counts nothing
+        let none = Cache::<i32, i32>::None;
+        none.get(&1);
+        assert_eq!(none.stats().misses, 0);
+    }
+}
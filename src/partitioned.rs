@@ -0,0 +1,147 @@
+//! An LRU cache split into independent partitions, so that threads using
+//! different keys do not wait on one recency order.
+use crate::{Cache, CacheStats};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// A cache that spreads its keys over a number of LRU caches by hash, each
+/// holding its share of the capacity.
+///
+/// Each partition keeps its own recency order and evicts on its own, so an
+/// entry may be evicted while another partition still has older ones. The
+/// statistics are those of all partitions together.
+pub struct PartitionedCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    partitions: Vec<Cache<K, V>>,
+    hasher: RandomState,
+}
+
+impl<K, V> PartitionedCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    /// Creates `partitions` LRU caches, at least one, sharing `capacity`
+    /// entries between them as evenly as they divide.
+    pub fn new(partitions: usize, capacity: usize) -> Self {
+        let count = partitions.max(1);
+        let partitions = (0..count)
+            .map(|i| Cache::new_lru(capacity / count + usize::from(i < capacity % count)))
+            .collect();
+        PartitionedCache {
+            partitions,
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn partition(&self, key: &K) -> &Cache<K, V> {
+        let index = self.hasher.hash_one(key) as usize % self.partitions.len();
+        &self.partitions[index]
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.partition(&key).insert(key, value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.partition(key).get(key)
+    }
+
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.partition(key).peek(key)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.partition(key).remove(key)
+    }
+
+    pub fn clear(&self) {
+        for partition in &self.partitions {
+            partition.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.partitions.iter().map(Cache::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partitions.iter().all(Cache::is_empty)
+    }
+
+    pub fn partition_count(&self) -> usize {
+        self.partitions.len()
+    }
+
+    /// Returns the statistics of all partitions added up.
+    pub fn stats(&self) -> CacheStats {
+        self.partitions
+            .iter()
+            .map(Cache::stats)
+            .fold(CacheStats::default(), |total, stats| CacheStats {
+                len: total.len + stats.len,
+                capacity: Some(total.capacity.unwrap_or(0) + stats.capacity.unwrap_or(0)),
+                hits: total.hits + stats.hits,
+                misses: total.misses + stats.misses,
+                evictions: total.evictions + stats.evictions,
+                inserts: total.inserts + stats.inserts,
+                updates: total.updates + stats.updates,
+                removals: total.removals + stats.removals,
+            })
+    }
+
+    /// Returns the statistics of each partition, to see how evenly keys
+    /// are spread.
+    pub fn partition_stats(&self) -> Vec<CacheStats> {
+        self.partitions.iter().map(Cache::stats).collect()
+    }
+
+    pub fn hit_ratio(&self) -> f64 {
+        self.stats().hit_ratio()
+    }
+
+    pub fn reset_stats(&self) {
+        for partition in &self.partitions {
+            partition.reset_stats();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PartitionedCache;
+
+    #[test]
+    fn test_partitioned() {
+        let cache = PartitionedCache::new(4, 10);
+        assert_eq!(cache.partition_count(), 4);
+        let capacities: Vec<_> = cache
+            .partition_stats()
+            .iter()
+            .map(|stats| stats.capacity.unwrap())
+            .collect();
+        assert_eq!(capacities, vec![3, 3, 2, 2]);
+
+        for key in 0..100 {
+            cache.insert(key, key);
+        }
+        assert!(cache.len() <= 10);
+        let found = (0..100).filter(|key| cache.get(key).is_some()).count();
+        assert_eq!(found, cache.len());
+
+        let stats = cache.stats();
+        assert_eq!(stats.capacity, Some(10));
+        assert_eq!(stats.inserts, 100);
+        assert_eq!(stats.evictions, 100 - cache.len());
+        assert_eq!((stats.hits, stats.misses), (found, 100 - found));
+
+        cache.clear();
+        assert!(cache.is_empty());
+        cache.reset_stats();
+        assert_eq!(cache.stats().hits, 0);
+    }
+}
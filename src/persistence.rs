@@ -0,0 +1,1319 @@
+//! Reading and writing cache snapshots to disk.
+//!
+//! A snapshot starts with a header holding the magic bytes, the format
+//! version and hashes of the key and value type names, so a file written by
+//! an incompatible version or for other types is rejected up front.
+//!
+//! From version 2 the header is followed by a frame holding the
+//! [`SnapshotMetadata`]. Then comes a sequence of frames, each holding a
+//! bincode-encoded chunk of entries prefixed by its length and CRC-32. From
+//! version 3 each value is prefixed by its length, so it can be skipped
+//! without decoding it. An
+//! empty frame marks the end of the entries and is followed by a CRC-32 of
+//! everything before it, so a truncated or damaged file is reported as
+//! [`PersistenceError::Corrupt`] instead of being partially loaded.
+//!
+//! Files without the magic bytes are read as "v0" snapshots, the bare
+//! bincode `Vec<(K, V)>` written before the header existed.
+use crate::checksum::Crc32;
+use anyhow::Result;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+/// Magic bytes at the start of every snapshot.
+pub(crate) const MAGIC: [u8; 6] = *b"MINNE\0";
+
+/// Version of the snapshot format written by this crate.
+pub(crate) const FORMAT_VERSION: u16 = 3;
+
+/// Oldest snapshot format version this crate can still read.
+pub(crate) const MIN_VERSION: u16 = 1;
+
+/// First version whose snapshots carry a metadata frame, which holds an
+/// `Option<SnapshotMetadata>`.
+const METADATA_VERSION: u16 = 2;
+
+/// First version whose entries hold the length of their value as a `u64`
+/// between the key and the value.
+pub(crate) const VALUE_LEN_VERSION: u16 = 3;
+
+/// Size of the header: magic, version, key type hash and value type hash.
+pub(crate) const HEADER_LEN: usize = MAGIC.len() + 2 + 8 + 8;
+
+/// Number of entries serialized into each frame.
+const CHUNK_ENTRIES: usize = 1024;
+
+/// Size of a frame header: payload length followed by the payload checksum.
+pub(crate) const FRAME_HEADER: usize = 8;
+
+/// The eviction policy of the cache a snapshot was written from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CachePolicy {
+    LRU,
+    Unbounded,
+}
+
+/// The serde form of a cache: how it evicts, and its entries in snapshot order.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CacheState<K, V> {
+    pub(crate) policy: CachePolicy,
+    pub(crate) capacity: Option<usize>,
+    pub(crate) entries: Vec<(K, V)>,
+}
+
+/// Information about the cache stored alongside its entries.
+///
+/// The counters are restored when the snapshot is read back, so hit ratios
+/// can be tracked across restarts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMetadata {
+    pub policy: CachePolicy,
+    /// The capacity of LRU caches
+    pub capacity: Option<usize>,
+    /// Number of entries at the time of writing
+    pub len: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+    /// When the cache was first created, carried over across restores
+    pub created: SystemTime,
+    /// When the snapshot was written
+    pub written: SystemTime,
+}
+
+/// The contents of a snapshot file.
+#[derive(Debug)]
+pub(crate) struct Snapshot<K, V> {
+    /// Absent for snapshots written before metadata was stored
+    pub(crate) metadata: Option<SnapshotMetadata>,
+    pub(crate) entries: Vec<(K, V)>,
+}
+
+/// How entries read from a snapshot combine with those already in the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Entries from the file overwrite existing ones with the same key.
+    #[default]
+    Merge,
+    /// The cache is cleared before the entries are inserted.
+    Replace,
+    /// Existing entries win; entries from the file only fill in missing keys.
+    KeepExisting,
+    /// Nothing is loaded if any key in the file is already in the cache.
+    ErrorOnConflict,
+}
+
+/// Bounds on what reading a snapshot may allocate, for files that cannot
+/// be trusted, see [`crate::Cache::read_limited`].
+///
+/// The default has no limits, as for [`crate::Cache::read`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// The most entries in the file
+    pub max_entries: usize,
+    /// The most bytes of the file
+    pub max_bytes: usize,
+    /// The most serialized bytes of one key and its value
+    pub max_entry_bytes: usize,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits {
+            max_entries: usize::MAX,
+            max_bytes: usize::MAX,
+            max_entry_bytes: usize::MAX,
+        }
+    }
+}
+
+impl ReadLimits {
+    fn exceeded(limit: &'static str, max: usize) -> anyhow::Error {
+        PersistenceError::LimitExceeded { limit, max }.into()
+    }
+}
+
+/// What a best-effort load managed to recover.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    /// Entries that were loaded
+    pub recovered: usize,
+    /// Entries known to be lost to damage
+    pub dropped: usize,
+}
+
+/// Errors specific to the snapshot format.
+///
+/// These are returned inside [`anyhow::Error`] and can be recovered with
+/// `error.downcast_ref::<PersistenceError>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PersistenceError {
+    /// The file is truncated or a checksum did not match.
+    Corrupt(String),
+    /// The file does not start with the snapshot magic bytes.
+    UnknownFormat,
+    /// The file was written with a format version this crate cannot read.
+    UnsupportedVersion(u16),
+    /// The file was written for different key or value types.
+    TypeMismatch {
+        key: &'static str,
+        value: &'static str,
+    },
+    /// Reading with [`ReadMode::ErrorOnConflict`] found this many keys that
+    /// are already in the cache.
+    Conflict(usize),
+    /// The file goes over one of the [`ReadLimits`] it was read with: its
+    /// `max` entries, bytes or bytes per entry.
+    LimitExceeded { limit: &'static str, max: usize },
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistenceError::Corrupt(reason) => write!(f, "Corrupt cache file: {}", reason),
+            PersistenceError::UnknownFormat => write!(f, "Not a cache file"),
+            PersistenceError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported cache file version {}", version)
+            }
+            PersistenceError::TypeMismatch { key, value } => write!(
+                f,
+                "Cache file was not written for key type `{}` and value type `{}`",
+                key, value
+            ),
+            PersistenceError::Conflict(count) => {
+                write!(f, "{} keys in the cache file are already cached", count)
+            }
+            PersistenceError::LimitExceeded { limit, max } => {
+                write!(f, "Cache file exceeds the limit of {} {}", max, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PersistenceError {}
+
+pub(crate) fn corrupt(reason: impl Into<String>) -> anyhow::Error {
+    PersistenceError::Corrupt(reason.into()).into()
+}
+
+/// A hash of a type's name, used to detect snapshots of other types.
+///
+/// `type_name` is not guaranteed to be the same across Rust releases, or
+/// after a type moves between modules, so snapshots written before such a
+/// change may be refused and need to be written again.
+pub(crate) fn type_hash<T: ?Sized>() -> u64 {
+    // FNV-1a, so the hash itself does not change with the standard library
+    std::any::type_name::<T>()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+pub(crate) fn write_header<K, V>(out: &mut Vec<u8>) {
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&type_hash::<K>().to_le_bytes());
+    out.extend_from_slice(&type_hash::<V>().to_le_bytes());
+}
+
+/// Returns the format version of a snapshot whose header was checked.
+pub(crate) fn version(data: &[u8]) -> u16 {
+    u16::from_le_bytes([data[6], data[7]])
+}
+
+/// Checks the header, returning the offset of the first frame.
+pub(crate) fn check_header<K, V>(data: &[u8]) -> Result<usize> {
+    if !data.starts_with(&MAGIC) {
+        return Err(PersistenceError::UnknownFormat.into());
+    }
+    let header = data
+        .get(..HEADER_LEN)
+        .ok_or_else(|| corrupt("unexpected end of file"))?;
+    let version = u16::from_le_bytes(header[6..8].try_into()?);
+    if !(MIN_VERSION..=FORMAT_VERSION).contains(&version) {
+        return Err(PersistenceError::UnsupportedVersion(version).into());
+    }
+    let key_hash = u64::from_le_bytes(header[8..16].try_into()?);
+    let value_hash = u64::from_le_bytes(header[16..24].try_into()?);
+    if key_hash != type_hash::<K>() || value_hash != type_hash::<V>() {
+        return Err(PersistenceError::TypeMismatch {
+            key: std::any::type_name::<K>(),
+            value: std::any::type_name::<V>(),
+        }
+        .into());
+    }
+    Ok(HEADER_LEN)
+}
+
+/// Reads the metadata frame at `pos` of a snapshot whose header was checked,
+/// returning it and the offset of the first entry frame.
+pub(crate) fn read_metadata(data: &[u8], pos: usize) -> Result<(Option<SnapshotMetadata>, usize)> {
+    if version(data) < METADATA_VERSION {
+        return Ok((None, pos));
+    }
+    let payload = read_frame(data, pos)?;
+    let metadata = bincode::deserialize(payload).map_err(|_| corrupt("invalid metadata"))?;
+    Ok((metadata, pos + FRAME_HEADER + payload.len()))
+}
+
+pub(crate) fn write_frame(out: &mut Vec<u8>, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&Crc32::checksum(payload).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Streams entries into checksummed frames without buffering more than one
+/// chunk of serialized entries.
+pub(crate) struct SnapshotWriter<'a, K, V> {
+    out: &'a mut dyn Write,
+    /// Checksum of everything written so far, for the trailer
+    crc: Crc32,
+    /// Serialized entries of the current chunk, after room for the count
+    chunk: Vec<u8>,
+    count: u64,
+    _marker: PhantomData<fn(&K, &V)>,
+}
+
+impl<'a, K, V> SnapshotWriter<'a, K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    pub(crate) fn new(out: &'a mut dyn Write, metadata: Option<&SnapshotMetadata>) -> Result<Self> {
+        let mut writer = SnapshotWriter {
+            out,
+            crc: Crc32::new(),
+            chunk: Vec::new(),
+            count: 0,
+            _marker: PhantomData,
+        };
+        let mut header = Vec::new();
+        write_header::<K, V>(&mut header);
+        write_frame(&mut header, &bincode::serialize(&metadata)?);
+        writer.emit(&header)?;
+        writer.start_chunk();
+        Ok(writer)
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<()> {
+        self.crc.update(bytes);
+        self.out.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn start_chunk(&mut self) {
+        self.chunk.clear();
+        // Placeholder for the entry count
+        self.chunk.extend_from_slice(&0u64.to_le_bytes());
+        self.count = 0;
+    }
+
+    fn flush_chunk(&mut self) -> Result<()> {
+        self.chunk[..8].copy_from_slice(&self.count.to_le_bytes());
+        let mut frame = Vec::with_capacity(FRAME_HEADER + self.chunk.len());
+        write_frame(&mut frame, &self.chunk);
+        self.emit(&frame)?;
+        self.start_chunk();
+        Ok(())
+    }
+
+    /// Serializes one entry.
+    pub(crate) fn push(&mut self, key: &K, value: &V) -> Result<()> {
+        bincode::serialize_into(&mut self.chunk, key)?;
+        // Placeholder for the length of the value
+        let len_at = self.chunk.len();
+        self.chunk.extend_from_slice(&0u64.to_le_bytes());
+        bincode::serialize_into(&mut self.chunk, value)?;
+        let len = (self.chunk.len() - len_at - 8) as u64;
+        self.chunk[len_at..len_at + 8].copy_from_slice(&len.to_le_bytes());
+        self.count += 1;
+        if self.count as usize == CHUNK_ENTRIES {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last chunk, the end frame and the whole-file checksum.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        if self.count > 0 {
+            self.flush_chunk()?;
+        }
+        let mut end = Vec::new();
+        write_frame(&mut end, &[]);
+        self.emit(&end)?;
+        let checksum = self.crc.finish();
+        self.out.write_all(&checksum.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+/// Encodes the entries as checksummed frames.
+#[cfg(test)]
+fn encode<K, V>(entries: &[(K, V)], metadata: Option<&SnapshotMetadata>) -> Result<Vec<u8>>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    let mut out = Vec::new();
+    let mut writer = SnapshotWriter::new(&mut out, metadata)?;
+    for (key, value) in entries {
+        writer.push(key, value)?;
+    }
+    writer.finish()?;
+    Ok(out)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or_else(|| corrupt("unexpected end of file"))?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+/// Returns the payload of the frame starting at `pos`, verifying its checksum.
+///
+/// The next frame starts at `pos + FRAME_HEADER + payload.len()`.
+pub(crate) fn read_frame(data: &[u8], pos: usize) -> Result<&[u8]> {
+    let len = read_u32(data, pos)? as usize;
+    let checksum = read_u32(data, pos + 4)?;
+    let payload = data
+        .get(pos + FRAME_HEADER..pos + FRAME_HEADER + len)
+        .ok_or_else(|| corrupt("unexpected end of file"))?;
+    if Crc32::checksum(payload) != checksum {
+        return Err(corrupt(format!(
+            "checksum mismatch in frame at byte {}",
+            pos
+        )));
+    }
+    Ok(payload)
+}
+
+/// Decodes the frames written by [`encode`], verifying every checksum.
+fn decode<K, V>(data: &[u8]) -> Result<Snapshot<K, V>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    decode_filtered(data, &mut |_| true, usize::MAX, &ReadLimits::default())
+}
+
+/// Decodes the entries whose keys pass `keep`, stopping after `limit` of them.
+///
+/// Every checksum is still verified, but only the kept entries are collected.
+/// Entry counts are checked against `limits` before the entries are decoded.
+fn decode_filtered<K, V>(
+    data: &[u8],
+    keep: &mut dyn FnMut(&K) -> bool,
+    limit: usize,
+    limits: &ReadLimits,
+) -> Result<Snapshot<K, V>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    if data.len() > limits.max_bytes {
+        return Err(ReadLimits::exceeded("bytes", limits.max_bytes));
+    }
+    if !data.starts_with(&MAGIC) {
+        let snapshot = decode_v0(data, keep, limit)?;
+        if snapshot.entries.len() > limits.max_entries {
+            return Err(ReadLimits::exceeded("entries", limits.max_entries));
+        }
+        return Ok(snapshot);
+    }
+
+    let mut entries = Vec::new();
+    let mut counted = 0usize;
+    let (metadata, mut pos) = read_metadata(data, check_header::<K, V>(data)?)?;
+    let version = version(data);
+    loop {
+        let payload = read_frame(data, pos)?;
+        pos += FRAME_HEADER + payload.len();
+
+        if payload.is_empty() {
+            break;
+        }
+        let count = payload
+            .get(..8)
+            .map_or(0, |count| u64::from_le_bytes(count.try_into().unwrap()));
+        counted = counted.saturating_add(count as usize);
+        if counted > limits.max_entries {
+            return Err(ReadLimits::exceeded("entries", limits.max_entries));
+        }
+        if entries.len() >= limit {
+            continue;
+        }
+
+        let max_entry_bytes = limits.max_entry_bytes;
+        let decoded = decode_chunk(payload, version, keep, limit, max_entry_bytes, &mut entries);
+        decoded.map_err(|e| {
+            if matches!(*e, bincode::ErrorKind::SizeLimit) {
+                return ReadLimits::exceeded("bytes per entry", max_entry_bytes);
+            }
+            e.into()
+        })?;
+    }
+
+    check_trailer(data, pos)?;
+    Ok(Snapshot { metadata, entries })
+}
+
+/// Decodes every intact frame, skipping damaged ones instead of failing.
+///
+/// After a frame whose length is damaged, scanning resumes at the next
+/// offset that holds a frame with a valid checksum.
+fn decode_lossy<K, V>(data: &[u8]) -> Result<(Snapshot<K, V>, LoadReport)>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    if !data.starts_with(&MAGIC) {
+        // v0 snapshots are not framed, so they load fully or not at all
+        let snapshot = decode_v0(data, &mut |_| true, usize::MAX)?;
+        let report = LoadReport {
+            recovered: snapshot.entries.len(),
+            dropped: 0,
+        };
+        return Ok((snapshot, report));
+    }
+
+    let mut pos = check_header::<K, V>(data)?;
+    let version = version(data);
+    let mut metadata: Option<SnapshotMetadata> = None;
+    if version >= METADATA_VERSION {
+        match read_frame(data, pos) {
+            Ok(payload) => {
+                metadata = bincode::deserialize(payload).ok().flatten();
+                pos += FRAME_HEADER + payload.len();
+            }
+            Err(_) => pos = resync(data, pos + 1),
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut dropped = 0;
+    while pos < data.len() {
+        match read_frame(data, pos) {
+            Ok([]) => break,
+            Ok(payload) => {
+                let mut chunk = Vec::new();
+                match decode_chunk(
+                    payload,
+                    version,
+                    &mut |_| true,
+                    usize::MAX,
+                    usize::MAX,
+                    &mut chunk,
+                ) {
+                    Ok(()) => entries.extend(chunk),
+                    Err(_) => dropped += chunk_count(payload).unwrap_or(0),
+                }
+                pos += FRAME_HEADER + payload.len();
+            }
+            Err(_) => {
+                if let Some(payload) = data.get(pos + FRAME_HEADER..) {
+                    dropped += chunk_count(payload).unwrap_or(0);
+                }
+                pos = resync(data, pos + 1);
+            }
+        }
+    }
+
+    // The metadata knows how many entries there should have been
+    if let Some(metadata) = &metadata {
+        dropped = metadata.len.saturating_sub(entries.len());
+    }
+    let report = LoadReport {
+        recovered: entries.len(),
+        dropped,
+    };
+    Ok((Snapshot { metadata, entries }, report))
+}
+
+/// Returns the entry count at the start of a chunk payload, if it is plausible.
+fn chunk_count(payload: &[u8]) -> Option<usize> {
+    let count = u64::from_le_bytes(payload.get(..8)?.try_into().ok()?) as usize;
+    (1..=CHUNK_ENTRIES).contains(&count).then_some(count)
+}
+
+/// Returns the first offset from `pos` holding an intact, non-empty entry
+/// frame, or the end of `data` if there is none.
+fn resync(data: &[u8], mut pos: usize) -> usize {
+    while pos + FRAME_HEADER < data.len() {
+        // Check the entry count before the more expensive checksum
+        let plausible = data
+            .get(pos + FRAME_HEADER..)
+            .and_then(chunk_count)
+            .is_some();
+        if plausible && matches!(read_frame(data, pos), Ok(payload) if !payload.is_empty()) {
+            return pos;
+        }
+        pos += 1;
+    }
+    data.len()
+}
+
+/// Decodes a v0 snapshot. These have no checksums, so only a file that
+/// decodes cleanly to its last byte is accepted.
+fn decode_v0<K, V>(
+    data: &[u8],
+    keep: &mut dyn FnMut(&K) -> bool,
+    limit: usize,
+) -> Result<Snapshot<K, V>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    // The options of `bincode::deserialize`, but rejecting trailing bytes
+    let entries: Vec<(K, V)> = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(data)
+        .map_err(|_| PersistenceError::UnknownFormat)?;
+    let entries = entries
+        .into_iter()
+        .filter(|(key, _)| keep(key))
+        .take(limit)
+        .collect();
+    Ok(Snapshot {
+        metadata: None,
+        entries,
+    })
+}
+
+/// Walks the entries of a chunk written with format `version` one at a
+/// time, so entries that are filtered out are dropped as soon as their key
+/// is decoded.
+///
+/// An entry taking more than `max_entry_bytes` fails with
+/// [`bincode::ErrorKind::SizeLimit`] before anything larger is allocated.
+fn decode_chunk<K, V>(
+    payload: &[u8],
+    version: u16,
+    keep: &mut dyn FnMut(&K) -> bool,
+    limit: usize,
+    max_entry_bytes: usize,
+    entries: &mut Vec<(K, V)>,
+) -> bincode::Result<()>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    let mut rest = payload;
+    let count: u64 = bincode::deserialize_from(&mut rest)?;
+    for _ in 0..count {
+        let start = rest.len();
+        let key: K = decode_from(&mut rest, max_entry_bytes)?;
+        let left = max_entry_bytes - (start - rest.len());
+        if version < VALUE_LEN_VERSION {
+            // Values must be decoded to find where they end, even if skipped
+            let value: V = decode_from(&mut rest, left)?;
+            if entries.len() >= limit {
+                break;
+            }
+            if keep(&key) {
+                entries.push((key, value));
+            }
+            continue;
+        }
+        let value = split_value(&mut rest)?;
+        // The length of the value is not counted, as in older versions
+        if value.len() > left {
+            return Err(bincode::ErrorKind::SizeLimit.into());
+        }
+        if entries.len() >= limit {
+            break;
+        }
+        if keep(&key) {
+            entries.push((key, decode_value(value)?));
+        }
+    }
+    Ok(())
+}
+
+/// Splits the length-prefixed bytes of a value, as written from format
+/// version 3, off the front of `rest`.
+pub(crate) fn split_value<'a>(rest: &mut &'a [u8]) -> bincode::Result<&'a [u8]> {
+    let past_end = || {
+        bincode::Error::from(bincode::ErrorKind::Custom(
+            "entry runs past the end of its frame".to_string(),
+        ))
+    };
+    let (len, tail) = rest.split_first_chunk::<8>().ok_or_else(past_end)?;
+    let len = usize::try_from(u64::from_le_bytes(*len))
+        .ok()
+        .filter(|&len| len <= tail.len())
+        .ok_or_else(past_end)?;
+    let (value, tail) = tail.split_at(len);
+    *rest = tail;
+    Ok(value)
+}
+
+/// Decodes a value split off by [`split_value`], which must take all of its bytes.
+pub(crate) fn decode_value<V>(mut value: &[u8]) -> bincode::Result<V>
+where
+    V: for<'a> Deserialize<'a>,
+{
+    let len = value.len();
+    let decoded = decode_from(&mut value, len)?;
+    if !value.is_empty() {
+        return Err(bincode::ErrorKind::Custom("value is shorter than its length".into()).into());
+    }
+    Ok(decoded)
+}
+
+/// Decodes one item from the front of `rest`, failing with
+/// [`bincode::ErrorKind::SizeLimit`] if it takes more than `max` bytes.
+///
+/// Nothing larger than `rest` is allocated, so a length that runs past the
+/// end, as when reading a snapshot of other types, fails without one.
+pub(crate) fn decode_from<T>(rest: &mut &[u8], max: usize) -> bincode::Result<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    // The options of `bincode::deserialize_from`, with a size limit
+    let options = |max: usize| {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(max as u64)
+    };
+    if max < rest.len() {
+        return options(max).deserialize_from(rest);
+    }
+    options(rest.len())
+        .deserialize_from(rest)
+        .map_err(|e| match *e {
+            bincode::ErrorKind::SizeLimit => {
+                bincode::ErrorKind::Custom("entry runs past the end of its frame".to_string())
+                    .into()
+            }
+            _ => e,
+        })
+}
+
+/// Verifies the whole-file checksum that follows the end frame at `pos`.
+pub(crate) fn check_trailer(data: &[u8], pos: usize) -> Result<()> {
+    if read_u32(data, pos)? != Crc32::checksum(&data[..pos]) {
+        return Err(corrupt("file checksum mismatch"));
+    }
+    if pos + 4 != data.len() {
+        return Err(corrupt("trailing data after end of snapshot"));
+    }
+    Ok(())
+}
+
+/// Returns a unique temporary path in the same directory as `target`, so the
+/// final rename stays on one filesystem and is atomic.
+pub(crate) fn temp_path(target: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
+    target.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), unique))
+}
+
+/// Writes a snapshot to `file_name`, with `fill` pushing the entries.
+///
+/// The snapshot is written to a temporary file which is then renamed over
+/// `file_name`, so a crash mid-write never leaves a half-written snapshot.
+pub(crate) fn write<K, V, F>(
+    file_name: &str,
+    metadata: Option<&SnapshotMetadata>,
+    fill: F,
+) -> Result<()>
+where
+    K: Serialize,
+    V: Serialize,
+    F: FnOnce(&mut SnapshotWriter<K, V>) -> Result<()>,
+{
+    let target = Path::new(file_name);
+    let temp = temp_path(target);
+
+    if let Err(e) = write_file(&temp, metadata, fill) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    fs::rename(&temp, target).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })?;
+
+    Ok(())
+}
+
+fn write_file<K, V, F>(path: &Path, metadata: Option<&SnapshotMetadata>, fill: F) -> Result<()>
+where
+    K: Serialize,
+    V: Serialize,
+    F: FnOnce(&mut SnapshotWriter<K, V>) -> Result<()>,
+{
+    // Open a file in write mode
+    let file = File::create(path)?;
+
+    let mut writer = BufWriter::new(file);
+
+    // Stream the entries into the buffered writer
+    let mut snapshot = SnapshotWriter::new(&mut writer, metadata)?;
+    fill(&mut snapshot)?;
+    snapshot.finish()?;
+
+    // Ensure all data is flushed to the file and reaches the disk before the rename
+    writer.flush()?;
+    writer.into_inner()?.sync_all()?;
+
+    Ok(())
+}
+
+/// Reads the entries written by [`write`].
+pub(crate) fn read<K, V>(file_name: &str) -> Result<Snapshot<K, V>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    decode(&read_snapshot(file_name, usize::MAX)?)
+}
+
+/// Like [`read`], but returns only the first `limit` entries whose keys pass `keep`.
+pub(crate) fn read_filtered<K, V>(
+    file_name: &str,
+    keep: &mut dyn FnMut(&K) -> bool,
+    limit: usize,
+) -> Result<Snapshot<K, V>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    decode_filtered(
+        &read_snapshot(file_name, usize::MAX)?,
+        keep,
+        limit,
+        &ReadLimits::default(),
+    )
+}
+
+/// Like [`read`], but fails with [`PersistenceError::LimitExceeded`] as
+/// soon as the file is found to go over `limits`.
+pub(crate) fn read_limited<K, V>(file_name: &str, limits: &ReadLimits) -> Result<Snapshot<K, V>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    let data = read_snapshot(file_name, limits.max_bytes)?;
+    decode_filtered(&data, &mut |_| true, usize::MAX, limits)
+}
+
+/// Like [`read`], but skips damaged frames instead of failing.
+pub(crate) fn read_lossy<K, V>(file_name: &str) -> Result<(Snapshot<K, V>, LoadReport)>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    decode_lossy(&read_snapshot(file_name, usize::MAX)?)
+}
+
+/// Rewrites the snapshot at `file_name` in the current format, keeping its
+/// entries and metadata.
+pub(crate) fn migrate<K, V>(file_name: &str) -> Result<()>
+where
+    K: Serialize + for<'a> Deserialize<'a>,
+    V: Serialize + for<'a> Deserialize<'a>,
+{
+    let snapshot = read::<K, V>(file_name)?;
+    write(file_name, snapshot.metadata.as_ref(), |writer| {
+        for (key, value) in &snapshot.entries {
+            writer.push(key, value)?;
+        }
+        Ok(())
+    })
+}
+
+/// Reads only the metadata of the snapshot at `file_name`.
+pub(crate) fn read_metadata_of<K, V>(file_name: &str) -> Result<Option<SnapshotMetadata>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    let data = read_snapshot(file_name, usize::MAX)?;
+    if !data.starts_with(&MAGIC) {
+        decode_v0::<K, V>(&data, &mut |_| false, 0)?;
+        return Ok(None);
+    }
+    let pos = check_header::<K, V>(&data)?;
+    Ok(read_metadata(&data, pos)?.0)
+}
+
+/// Reads `file_name`, failing if it takes more than `max_bytes`.
+fn read_snapshot(file_name: &str, max_bytes: usize) -> Result<Vec<u8>> {
+    if std::fs::metadata(file_name).is_ok_and(|file| file.len() > max_bytes as u64) {
+        return Err(ReadLimits::exceeded("bytes", max_bytes));
+    }
+    // Read the encoded entries from a file
+    let encoded = std::fs::read(file_name)?;
+
+    // Check if the file was empty
+    if encoded.is_empty() {
+        return Err(anyhow::anyhow!("File is empty"));
+    }
+
+    Ok(encoded)
+}
+
+/// Returns the path of shard `index` of a sharded snapshot.
+fn shard_path(file_name: &str, index: usize) -> String {
+    format!("{}.{}", file_name, index)
+}
+
+/// The most shards a sharded snapshot may have, as each is written and read
+/// on a thread of its own.
+pub(crate) const MAX_SHARDS: usize = 1024;
+
+/// Writes a snapshot as `shards` files in parallel, with `fill` pushing the
+/// entries of the shard it is given.
+///
+/// The shards are written to `<file_name>.<index>`. A manifest recording the
+/// shard count is written to `file_name` once all shards are in place, and
+/// shards left over from an earlier snapshot with more of them are removed.
+/// Every shard carries the same `metadata`.
+pub(crate) fn write_sharded<K, V, F>(
+    file_name: &str,
+    shards: usize,
+    metadata: Option<&SnapshotMetadata>,
+    fill: F,
+) -> Result<()>
+where
+    K: Serialize,
+    V: Serialize,
+    F: Fn(usize, &mut SnapshotWriter<K, V>) -> Result<()> + Sync,
+{
+    if shards == 0 || shards > MAX_SHARDS {
+        return Err(anyhow::anyhow!(
+            "A sharded snapshot needs between 1 and {} shards, not {}",
+            MAX_SHARDS,
+            shards
+        ));
+    }
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..shards)
+            .map(|index| {
+                let fill = &fill;
+                scope.spawn(move || {
+                    write(&shard_path(file_name, index), metadata, |writer| {
+                        fill(index, writer)
+                    })
+                })
+            })
+            .collect();
+        handles.into_iter().try_for_each(|handle| {
+            handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("Shard writer panicked"))?
+        })
+    })?;
+
+    let mut manifest = Vec::new();
+    write_header::<K, V>(&mut manifest);
+    manifest.extend_from_slice(&(shards as u32).to_le_bytes());
+    let temp = temp_path(Path::new(file_name));
+    fs::write(&temp, &manifest)?;
+    fs::rename(&temp, file_name)?;
+
+    // Shards are numbered from 0, so the leftovers end at the first gap
+    for index in shards..MAX_SHARDS {
+        match fs::remove_file(shard_path(file_name, index)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Reads a snapshot written by [`write_sharded`], decoding the shards in
+/// parallel. The shards are returned in order.
+///
+/// `limits` bound the shards taken together: their total size is checked
+/// before any is read, and the entries of all of them are counted.
+pub(crate) fn read_sharded<K, V>(
+    file_name: &str,
+    limits: &ReadLimits,
+) -> Result<Vec<Snapshot<K, V>>>
+where
+    K: for<'a> Deserialize<'a> + Send,
+    V: for<'a> Deserialize<'a> + Send,
+{
+    let manifest = fs::read(file_name)?;
+    let pos = check_header::<K, V>(&manifest)?;
+    let shards = read_u32(&manifest, pos)? as usize;
+    if shards == 0 || shards > MAX_SHARDS {
+        return Err(corrupt(format!("implausible shard count {}", shards)));
+    }
+
+    let mut total_bytes = 0u64;
+    for index in 0..shards {
+        total_bytes += fs::metadata(shard_path(file_name, index))?.len();
+    }
+    if total_bytes > limits.max_bytes as u64 {
+        return Err(ReadLimits::exceeded("bytes", limits.max_bytes));
+    }
+
+    let snapshots: Vec<Snapshot<K, V>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..shards)
+            .map(|index| scope.spawn(move || read_limited(&shard_path(file_name, index), limits)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("Shard reader panicked"))?
+            })
+            .collect::<Result<_>>()
+    })?;
+
+    let entries: usize = snapshots.iter().map(|shard| shard.entries.len()).sum();
+    if entries > limits.max_entries {
+        return Err(ReadLimits::exceeded("entries", limits.max_entries));
+    }
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<(u32, String)> {
+        (0..3000).map(|i| (i, format!("value {}", i))).collect()
+    }
+
+    fn metadata() -> SnapshotMetadata {
+        SnapshotMetadata {
+            policy: CachePolicy::LRU,
+            capacity: Some(5000),
+            len: 3000,
+            hits: 10,
+            misses: 2,
+            evictions: 1,
+            created: SystemTime::UNIX_EPOCH,
+            written: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let encoded = encode(&entries(), Some(&metadata())).unwrap();
+        let decoded: Snapshot<u32, String> = decode(&encoded).unwrap();
+        assert_eq!(decoded.entries, entries());
+        assert_eq!(decoded.metadata, Some(metadata()));
+
+        let empty: Vec<(u32, String)> = Vec::new();
+        let decoded: Snapshot<u32, String> =
+            decode(&encode(&empty, Some(&metadata())).unwrap()).unwrap();
+        assert!(decoded.entries.is_empty());
+    }
+
+    #[test]
+    fn test_read_limits() {
+        let encoded = encode(&entries(), Some(&metadata())).unwrap();
+        let decode_with = |limits: ReadLimits| {
+            decode_filtered::<u32, String>(&encoded, &mut |_| true, usize::MAX, &limits)
+        };
+        let exceeded = |result: Result<Snapshot<u32, String>>| {
+            result.unwrap_err().downcast::<PersistenceError>().unwrap()
+        };
+        assert_eq!(
+            exceeded(decode_with(ReadLimits {
+                max_entries: 2999,
+                ..ReadLimits::default()
+            })),
+            PersistenceError::LimitExceeded {
+                limit: "entries",
+                max: 2999
+            }
+        );
+        assert_eq!(
+            exceeded(decode_with(ReadLimits {
+                max_bytes: encoded.len() - 1,
+                ..ReadLimits::default()
+            })),
+            PersistenceError::LimitExceeded {
+                limit: "bytes",
+                max: encoded.len() - 1
+            }
+        );
+        // The largest entry is a u32 and a 10 character string with its length
+        assert_eq!(
+            exceeded(decode_with(ReadLimits {
+                max_entry_bytes: 21,
+                ..ReadLimits::default()
+            })),
+            PersistenceError::LimitExceeded {
+                limit: "bytes per entry",
+                max: 21
+            }
+        );
+        let limits = ReadLimits {
+            max_entries: 3000,
+            max_bytes: encoded.len(),
+            max_entry_bytes: 22,
+        };
+        assert_eq!(decode_with(limits).unwrap().entries, entries());
+    }
+
+    #[test]
+    fn test_sharded() {
+        let path = crate::testing::TempPath::new("sharded.cache");
+        let path = path.as_str();
+        let _shards: Vec<_> = (0..4)
+            .map(|i| crate::testing::TempPath::new(&format!("sharded.cache.{}", i)))
+            .collect();
+        let write = |shards: usize| {
+            write_sharded::<u32, String, _>(path, shards, None, |index, writer| {
+                for (key, value) in entries()
+                    .iter()
+                    .filter(|(key, _)| *key as usize % shards == index)
+                {
+                    writer.push(key, value)?;
+                }
+                Ok(())
+            })
+        };
+        let read = |limits: ReadLimits| read_sharded::<u32, String>(path, &limits);
+
+        write(4).unwrap();
+        let total: usize = read(ReadLimits::default())
+            .unwrap()
+            .iter()
+            .map(|shard| shard.entries.len())
+            .sum();
+        assert_eq!(total, 3000);
+        let error = |limits| {
+            read(limits)
+                .unwrap_err()
+                .downcast::<PersistenceError>()
+                .unwrap()
+        };
+        assert_eq!(
+            error(ReadLimits {
+                max_entries: 2999,
+                ..ReadLimits::default()
+            }),
+            PersistenceError::LimitExceeded {
+                limit: "entries",
+                max: 2999
+            }
+        );
+        assert_eq!(
+            error(ReadLimits {
+                max_bytes: 1000,
+                ..ReadLimits::default()
+            }),
+            PersistenceError::LimitExceeded {
+                limit: "bytes",
+                max: 1000
+            }
+        );
+
+        // Writing fewer shards removes the ones left over
+        write(2).unwrap();
+        assert_eq!(read(ReadLimits::default()).unwrap().len(), 2);
+        assert!(!Path::new(&shard_path(path, 2)).exists());
+        assert!(!Path::new(&shard_path(path, 3)).exists());
+        assert!(write(MAX_SHARDS + 1).is_err());
+
+        // The shard count of the manifest is checked before reading
+        let mut manifest = fs::read(path).unwrap();
+        let len = manifest.len();
+        manifest[len - 4..].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(path, &manifest).unwrap();
+        assert!(matches!(
+            error(ReadLimits::default()),
+            PersistenceError::Corrupt(_)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_is_corrupt() {
+        let encoded = encode(&entries(), Some(&metadata())).unwrap();
+        for len in [
+            HEADER_LEN - 1,
+            HEADER_LEN + 3,
+            encoded.len() / 2,
+            encoded.len() - 1,
+        ] {
+            let err = decode::<u32, String>(&encoded[..len]).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<PersistenceError>(),
+                Some(PersistenceError::Corrupt(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_flipped_byte_is_corrupt() {
+        let mut encoded = encode(&entries(), Some(&metadata())).unwrap();
+        encoded[100] ^= 0x01;
+        let err = decode::<u32, String>(&encoded).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PersistenceError>(),
+            Some(PersistenceError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_mismatch() {
+        let encoded = encode(&entries(), Some(&metadata())).unwrap();
+
+        let err = decode::<u32, String>(b"not a cache file").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PersistenceError>(),
+            Some(&PersistenceError::UnknownFormat)
+        );
+
+        let mut future = encoded.clone();
+        future[6] = 99;
+        let err = decode::<u32, String>(&future).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PersistenceError>(),
+            Some(&PersistenceError::UnsupportedVersion(99))
+        );
+
+        let err = decode::<u64, String>(&encoded).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PersistenceError>(),
+            Some(PersistenceError::TypeMismatch { key: "u64", .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_replaces_atomically() {
+        let dir = std::env::temp_dir().join("minne_atomic_write");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.bin");
+        let path = path.to_str().unwrap();
+
+        let fill = |n: usize| {
+            move |writer: &mut SnapshotWriter<u32, String>| {
+                for (key, value) in &entries()[..n] {
+                    writer.push(key, value)?;
+                }
+                Ok(())
+            }
+        };
+        write(path, Some(&metadata()), fill(3000)).unwrap();
+        write(path, Some(&metadata()), fill(10)).unwrap();
+
+        let loaded: Snapshot<u32, String> = read(path).unwrap();
+        assert_eq!(loaded.entries, entries()[..10]);
+
+        // Only the target remains, no temporary files are left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_and_migrate_v0() {
+        let path = std::env::temp_dir().join("minne_v0.cache");
+        let path = path.to_str().unwrap();
+        fs::write(path, bincode::serialize(&entries()).unwrap()).unwrap();
+
+        let loaded: Snapshot<u32, String> = read(path).unwrap();
+        assert_eq!(loaded.entries, entries());
+        assert_eq!(loaded.metadata, None);
+        assert_eq!(read_metadata_of::<u32, String>(path).unwrap(), None);
+
+        migrate::<u32, String>(path).unwrap();
+        assert!(fs::read(path).unwrap().starts_with(&MAGIC));
+        let loaded: Snapshot<u32, String> = read(path).unwrap();
+        assert_eq!(loaded.entries, entries());
+        assert_eq!(loaded.metadata, None);
+
+        // A v0 file is only accepted if it decodes to its last byte
+        let mut extra = bincode::serialize(&entries()).unwrap();
+        extra.push(0);
+        fs::write(path, extra).unwrap();
+        let err = read::<u32, String>(path).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PersistenceError>(),
+            Some(&PersistenceError::UnknownFormat)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decode_v2() {
+        // Before version 3, entries are bare bincode `(K, V)` pairs
+        let mut encoded = Vec::new();
+        write_header::<u32, String>(&mut encoded);
+        encoded[6..8].copy_from_slice(&2u16.to_le_bytes());
+        write_frame(
+            &mut encoded,
+            &bincode::serialize(&Some(metadata())).unwrap(),
+        );
+        write_frame(&mut encoded, &bincode::serialize(&entries()).unwrap());
+        write_frame(&mut encoded, &[]);
+        let checksum = Crc32::checksum(&encoded);
+        encoded.extend_from_slice(&checksum.to_le_bytes());
+
+        let decoded = decode::<u32, String>(&encoded).unwrap();
+        assert_eq!(decoded.entries, entries());
+        assert_eq!(decoded.metadata, Some(metadata()));
+        let (lossy, report) = decode_lossy::<u32, String>(&encoded).unwrap();
+        assert_eq!(lossy.entries, entries());
+        assert_eq!(report.dropped, 0);
+    }
+
+    #[test]
+    fn test_decode_lossy_skips_damaged_frames() {
+        let encoded = encode(&entries(), Some(&metadata())).unwrap();
+        // Find the second frame of entries, after the metadata and first frame
+        let mut second = HEADER_LEN;
+        for _ in 0..2 {
+            second += FRAME_HEADER + read_frame(&encoded, second).unwrap().len();
+        }
+
+        let mut damaged = encoded.clone();
+        damaged[second + FRAME_HEADER + 20] ^= 0x01;
+        let (snapshot, report) = decode_lossy::<u32, String>(&damaged).unwrap();
+        assert_eq!(
+            report,
+            LoadReport {
+                recovered: 3000 - CHUNK_ENTRIES,
+                dropped: CHUNK_ENTRIES
+            }
+        );
+        assert_eq!(
+            snapshot.entries[..CHUNK_ENTRIES],
+            entries()[..CHUNK_ENTRIES]
+        );
+        assert_eq!(snapshot.metadata, Some(metadata()));
+
+        // A damaged length is skipped by scanning for the next intact frame
+        let mut damaged = encoded.clone();
+        damaged[second + 2] ^= 0x40;
+        let (snapshot, report) = decode_lossy::<u32, String>(&damaged).unwrap();
+        assert_eq!(report.recovered, 3000 - CHUNK_ENTRIES);
+        assert_eq!(snapshot.entries.last(), entries().last());
+
+        let (_, report) = decode_lossy::<u32, String>(&encoded).unwrap();
+        assert_eq!(
+            report,
+            LoadReport {
+                recovered: 3000,
+                dropped: 0
+            }
+        );
+    }
+}
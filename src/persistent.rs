@@ -0,0 +1,176 @@
+//! A cache that is loaded from and saved back to a file automatically.
+use crate::report::report;
+use crate::Cache;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::ops::Deref;
+use std::path::Path;
+
+/// Wraps a [`Cache`] so that it is loaded from `file_name` when opened and
+/// written back when closed or dropped.
+///
+/// Caches opened with [`PersistentCache::open_durable`] also survive crashes.
+///
+/// ```no_run
+/// use minne::{Cache, PersistentCache};
+///
+/// let cache = PersistentCache::open(Cache::new_lru(1000), "memo.cache")?;
+/// cache.insert(1, "one".to_string());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct PersistentCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    cache: Cache<K, V>,
+    file_name: String,
+    closed: bool,
+    /// Whether mutations are logged to a write-ahead log
+    durable: bool,
+}
+
+impl<K, V> PersistentCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    /// Loads `file_name` into `cache` if the file exists.
+    pub fn open(cache: Cache<K, V>, file_name: &str) -> Result<Self> {
+        if Path::new(file_name).exists() {
+            cache.read(file_name)?;
+        }
+        Ok(PersistentCache {
+            cache,
+            file_name: file_name.to_string(),
+            closed: false,
+            durable: false,
+        })
+    }
+
+    /// Like [`PersistentCache::open`], but also logs every mutation to a
+    /// write-ahead log at `<file_name>.wal`, so that no entries are lost if
+    /// the process exits without dropping the cache.
+    ///
+    /// Opening replays the log over the snapshot; closing folds it back into
+    /// the snapshot.
+    pub fn open_durable(cache: Cache<K, V>, file_name: &str) -> Result<Self> {
+        if Path::new(file_name).exists() {
+            cache.read(file_name)?;
+        }
+        cache.enable_wal(&wal_path(file_name))?;
+        Ok(PersistentCache {
+            cache,
+            file_name: file_name.to_string(),
+            closed: false,
+            durable: true,
+        })
+    }
+
+    /// Writes the cache back to its file.
+    ///
+    /// Unlike dropping, which can only hand an error from writing to the
+    /// [`crate::set_error_hook`] hook, this returns it.
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        if self.durable {
+            self.cache.compact_wal(&self.file_name)
+        } else {
+            self.cache.write(&self.file_name)
+        }
+    }
+}
+
+fn wal_path(file_name: &str) -> String {
+    format!("{}.wal", file_name)
+}
+
+impl<K, V> Deref for PersistentCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    type Target = Cache<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cache
+    }
+}
+
+impl<K, V> Drop for PersistentCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.save() {
+            report(e.context(format!("Failed to write cache to '{}'", self.file_name)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PersistentCache;
+    use crate::Cache;
+
+    #[test]
+    fn test_save_on_drop() {
+        let path = std::env::temp_dir().join("minne_persistent.cache");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        {
+            let cache = PersistentCache::open(Cache::new_lru(10), path).unwrap();
+            assert!(cache.is_empty());
+            cache.insert(1, "one".to_string());
+        }
+
+        let cache = PersistentCache::open(Cache::new_lru(10), path).unwrap();
+        assert_eq!(cache.get(&1), Some("one".to_string()));
+        cache.insert(2, "two".to_string());
+        cache.close().unwrap();
+
+        let cache: PersistentCache<i32, String> =
+            PersistentCache::open(Cache::new_unbounded(), path).unwrap();
+        assert_eq!(cache.len(), 2);
+        drop(cache);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_durable_survives_without_drop() {
+        let path = std::env::temp_dir().join("minne_persistent_durable.cache");
+        let path = path.to_str().unwrap();
+        let wal = format!("{}.wal", path);
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(&wal);
+
+        let cache = PersistentCache::open_durable(Cache::new_unbounded(), path).unwrap();
+        cache.insert(1, "one".to_string());
+        cache.sync_wal().unwrap();
+        // Simulate a crash: the cache is never dropped or closed
+        std::mem::forget(cache);
+
+        let cache: PersistentCache<i32, String> =
+            PersistentCache::open_durable(Cache::new_unbounded(), path).unwrap();
+        assert_eq!(cache.get(&1), Some("one".to_string()));
+        cache.insert(2, "two".to_string());
+        cache.close().unwrap();
+
+        let cache: PersistentCache<i32, String> =
+            PersistentCache::open(Cache::new_unbounded(), path).unwrap();
+        assert_eq!(cache.len(), 2);
+        cache.close().unwrap();
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(&wal).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+//! Reading memory pressure, for shrinking caches before the process runs out
+//! of memory, see [`crate::Cache::shrink_under_pressure`].
+use std::fs;
+
+/// Returns the share of its memory limit the container is using, read from
+/// the cgroup v2 or v1 files of the process, or `None` outside a container
+/// or without a limit.
+pub fn cgroup_memory_usage() -> Option<f64> {
+    let (usage, limit) = read_pair("/sys/fs/cgroup/memory.current", "/sys/fs/cgroup/memory.max")
+        .or_else(|| {
+            read_pair(
+                "/sys/fs/cgroup/memory/memory.usage_in_bytes",
+                "/sys/fs/cgroup/memory/memory.limit_in_bytes",
+            )
+        })?;
+    // cgroup v1 reports no limit as a huge number rather than "max"
+    if limit == 0 || limit >= u64::MAX / 2 {
+        return None;
+    }
+    Some(usage as f64 / limit as f64)
+}
+
+fn read_pair(usage: &str, limit: &str) -> Option<(u64, u64)> {
+    let read = |path: &str| fs::read_to_string(path).ok()?.trim().parse::<u64>().ok();
+    Some((read(usage)?, read(limit)?))
+}
+
+/// Returns the share of its capacity a cache may use at `usage` of the
+/// memory limit: all of it up to `threshold`, then less in proportion to
+/// the memory left, down to a tenth when memory is full.
+pub fn share_at(usage: f64, threshold: f64) -> f64 {
+    if usage <= threshold || threshold >= 1.0 {
+        return 1.0;
+    }
+    ((1.0 - usage) / (1.0 - threshold)).clamp(0.1, 1.0)
+}
+
+/// Returns a pressure reader for [`crate::Cache::shrink_under_pressure`]
+/// that shrinks the cache once the container uses more than `threshold`
+/// of its memory limit, see [`share_at`].
+///
+/// Without a readable cgroup memory limit the cache keeps its capacity.
+pub fn cgroup(threshold: f64) -> impl FnMut() -> f64 + Send + 'static {
+    move || cgroup_memory_usage().map_or(1.0, |usage| share_at(usage, threshold))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::share_at;
+
+    #[test]
+    fn test_share_at() {
+        assert_eq!(share_at(0.5, 0.8), 1.0);
+        assert_eq!(share_at(0.8, 0.8), 1.0);
+        assert!((share_at(0.9, 0.8) - 0.5).abs() < 1e-9);
+        assert_eq!(share_at(1.0, 0.8), 0.1);
+        assert_eq!(share_at(1.2, 0.8), 0.1);
+    }
+}
@@ -0,0 +1,217 @@
+//! Rendering of cache statistics in the Prometheus text exposition format.
+use crate::sync::Recover;
+use crate::Cache;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+/// The statistics of one cache at the time of rendering.
+struct Sample {
+    hits: usize,
+    misses: usize,
+    evictions: usize,
+    inserts: usize,
+    updates: usize,
+    removals: usize,
+    entries: usize,
+    spilled: usize,
+}
+
+impl Sample {
+    fn of<K, V>(cache: &Cache<K, V>) -> Self
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+        V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    {
+        let stats = cache.stats();
+        Sample {
+            hits: stats.hits,
+            misses: stats.misses,
+            evictions: stats.evictions,
+            inserts: stats.inserts,
+            updates: stats.updates,
+            removals: stats.removals,
+            entries: stats.len,
+            spilled: cache.spilled_len(),
+        }
+    }
+}
+
+/// A metric family as (name, type, help, value).
+type Family = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&Sample) -> usize,
+);
+
+const FAMILIES: [Family; 8] = [
+    (
+        "minne_cache_hits_total",
+        "counter",
+        "Lookups that found a value.",
+        |s| s.hits,
+    ),
+    (
+        "minne_cache_misses_total",
+        "counter",
+        "Lookups that found no value.",
+        |s| s.misses,
+    ),
+    (
+        "minne_cache_evictions_total",
+        "counter",
+        "Entries evicted to make room for others.",
+        |s| s.evictions,
+    ),
+    (
+        "minne_cache_inserts_total",
+        "counter",
+        "Inserts of keys that were not cached.",
+        |s| s.inserts,
+    ),
+    (
+        "minne_cache_updates_total",
+        "counter",
+        "Inserts that overwrote a cached value.",
+        |s| s.updates,
+    ),
+    (
+        "minne_cache_removals_total",
+        "counter",
+        "Explicit removals of cached keys.",
+        |s| s.removals,
+    ),
+    (
+        "minne_cache_entries",
+        "gauge",
+        "Entries held in memory.",
+        |s| s.entries,
+    ),
+    (
+        "minne_cache_spilled_entries",
+        "gauge",
+        "Entries spilled to disk.",
+        |s| s.spilled,
+    ),
+];
+
+/// Renders the statistics of `cache`, labelled with `name`.
+pub fn render<K, V>(name: &str, cache: &Cache<K, V>) -> String
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    render_samples(&[(name.to_string(), Sample::of(cache))])
+}
+
+fn render_samples(samples: &[(String, Sample)]) -> String {
+    let mut out = String::new();
+    for (family, kind, help, value) in FAMILIES {
+        let _ = writeln!(out, "# HELP {} {}", family, help);
+        let _ = writeln!(out, "# TYPE {} {}", family, kind);
+        for (name, sample) in samples {
+            let _ = writeln!(
+                out,
+                "{}{{cache=\"{}\"}} {}",
+                family,
+                escape(name),
+                value(sample)
+            );
+        }
+    }
+    out
+}
+
+/// Escapes a label value as the exposition format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+type Sampler = Box<dyn Fn() -> Sample + Send + Sync>;
+
+/// A set of named caches whose statistics are rendered together.
+///
+/// Registering a cache keeps it alive until it is unregistered or the
+/// registry is dropped.
+#[derive(Default)]
+pub struct Registry {
+    caches: Mutex<Vec<(String, Sampler)>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `cache` under `name`, replacing any cache already registered under it.
+    pub fn register<K, V>(&self, name: &str, cache: &Cache<K, V>)
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+        V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    {
+        let cache = cache.clone();
+        let sampler: Sampler = Box::new(move || Sample::of(&cache));
+        let mut caches = self.caches.lock().recover();
+        match caches.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => *existing = sampler,
+            None => caches.push((name.to_string(), sampler)),
+        }
+    }
+
+    /// Removes the cache registered under `name`, returning whether there was one.
+    pub fn unregister(&self, name: &str) -> bool {
+        let mut caches = self.caches.lock().recover();
+        let len = caches.len();
+        caches.retain(|(n, _)| n != name);
+        caches.len() != len
+    }
+
+    /// Renders the statistics of every registered cache.
+    pub fn render(&self) -> String {
+        let samples: Vec<_> = self
+            .caches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, sampler)| (name.clone(), sampler()))
+            .collect();
+        render_samples(&samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Registry};
+    use crate::Cache;
+
+    #[test]
+    fn test_render() {
+        let cache = Cache::new_lru(1);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.get(&2);
+        cache.get(&1);
+
+        let text = render("users", &cache);
+        assert!(text.contains("# TYPE minne_cache_hits_total counter\n"));
+        assert!(text.contains("minne_cache_hits_total{cache=\"users\"} 1\n"));
+        assert!(text.contains("minne_cache_misses_total{cache=\"users\"} 1\n"));
+        assert!(text.contains("minne_cache_evictions_total{cache=\"users\"} 1\n"));
+        assert!(text.contains("minne_cache_inserts_total{cache=\"users\"} 2\n"));
+        assert!(text.contains("minne_cache_entries{cache=\"users\"} 1\n"));
+
+        let registry = Registry::new();
+        registry.register("users", &cache);
+        registry.register("a \"quoted\" name", &Cache::<i32, i32>::new_unbounded());
+        let text = registry.render();
+        assert_eq!(text.matches("# TYPE minne_cache_entries gauge").count(), 1);
+        assert!(text.contains("minne_cache_entries{cache=\"a \\\"quoted\\\" name\"} 0\n"));
+        assert!(registry.unregister("users"));
+        assert!(!registry.render().contains("users"));
+    }
+}
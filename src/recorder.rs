@@ -0,0 +1,88 @@
+//! Sending the statistics of a cache somewhere other than its own counters.
+
+/// Records the statistics of a cache in place of its own atomic counters.
+///
+/// A cache counts its statistics with built-in atomic counters by default.
+/// Once it is given a recorder with [`crate::Cache::set_stats_recorder`],
+/// every hit, miss, eviction, insert and removal goes to the recorder only,
+/// for example to forward it to statsd or in-house telemetry. The methods
+/// are called on the thread doing the lookup or change, so they should be
+/// cheap. Every method does nothing by default.
+pub trait StatsRecorder: Send + Sync {
+    fn record_hit(&self) {}
+
+    fn record_miss(&self) {}
+
+    fn record_eviction(&self) {}
+
+    /// Records an insert, as an update if it overwrote a value.
+    fn record_insert(&self, _replaced: bool) {}
+
+    fn record_removal(&self) {}
+}
+
+/// A recorder that drops everything, for caches whose statistics are not
+/// worth the cost of counting them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopRecorder;
+
+impl StatsRecorder for NoopRecorder {}
+
+#[cfg(test)]
+mod tests {
+    use super::{NoopRecorder, StatsRecorder};
+    use crate::Cache;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct Counting {
+        hits: AtomicUsize,
+        updates: AtomicUsize,
+        evictions: AtomicUsize,
+    }
+
+    impl StatsRecorder for Counting {
+        fn record_hit(&self) {
+            self.hits.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn record_eviction(&self) {
+            self.evictions.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn record_insert(&self, replaced: bool) {
+            if replaced {
+                self.updates.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn test_recorder() {
+        let cache = Cache::new_lru(1);
+        cache.get(&1);
+        let recorder = Arc::new(Counting::default());
+        cache.set_stats_recorder(recorder.clone()).unwrap();
+        assert!(cache.set_stats_recorder(Arc::new(NoopRecorder)).is_err());
+
+        cache.insert(1, 1);
+        cache.insert(1, 2);
+        cache.get(&1);
+        cache.insert(2, 2);
+        assert_eq!(recorder.hits.load(Ordering::SeqCst), 1);
+        assert_eq!(recorder.updates.load(Ordering::SeqCst), 1);
+        assert_eq!(recorder.evictions.load(Ordering::SeqCst), 1);
+
+        // The cache's own counters stopped when the recorder was set
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.inserts), (0, 1, 0));
+        assert_eq!(stats.len, 1);
+
+        let quiet = Cache::new_unbounded();
+        quiet.set_stats_recorder(Arc::new(NoopRecorder)).unwrap();
+        quiet.insert(1, 1);
+        quiet.get(&1);
+        assert_eq!(quiet.stats().hits, 0);
+    }
+}
@@ -0,0 +1,416 @@
+//! Sharing one cache between machines over TCP.
+//!
+//! Requests and responses are bincode-encoded and sent as frames prefixed
+//! with their length as a little-endian `u32`.
+use crate::report::report;
+use crate::sync::Recover;
+use crate::{Cache, CacheEvent, CacheStats};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The largest frame read by default; larger ones are rejected rather
+/// than allocated.
+pub const DEFAULT_MAX_FRAME_LEN: u32 = 8 * 1024 * 1024;
+
+/// What a server started with [`serve_with`] accepts from its peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerLimits {
+    /// The largest request read, in bytes
+    pub max_frame_len: u32,
+    /// The most connections served at once; more are closed on accept
+    pub max_connections: usize,
+    /// How long a read or write may wait on a peer before its connection
+    /// is closed, or `None` to wait as long as it takes
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        ServerLimits {
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            max_connections: 64,
+            timeout: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Counts a connection being served until dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Request<K, V> {
+    Get(K),
+    Insert(K, V),
+    Remove(K),
+    Clear,
+    Stats,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response<V> {
+    Value(Option<V>),
+    Done,
+    Stats(CacheStats),
+}
+
+/// Accepts connections on `listener` and serves `cache` to each on its
+/// own thread, within the default [`ServerLimits`].
+pub fn serve<K, V>(cache: Cache<K, V>, listener: TcpListener) -> Result<()>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    serve_with(cache, listener, ServerLimits::default())
+}
+
+/// Like [`serve`], but within `limits`.
+pub fn serve_with<K, V>(
+    cache: Cache<K, V>,
+    listener: TcpListener,
+    limits: ServerLimits,
+) -> Result<()>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                report(anyhow!(e).context("Failed to accept connection"));
+                continue;
+            }
+        };
+        if connections.fetch_add(1, Ordering::SeqCst) >= limits.max_connections {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            report(anyhow!("Refused connection: too many connections"));
+            continue;
+        }
+        let slot = ConnectionSlot(connections.clone());
+        let cache = cache.clone();
+        std::thread::spawn(move || {
+            let _slot = slot;
+            if let Err(e) = serve_stream(&cache, stream, &limits) {
+                report(e.context("Connection failed"));
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve_stream<K, V>(cache: &Cache<K, V>, stream: TcpStream, limits: &ServerLimits) -> Result<()>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(limits.timeout)?;
+    stream.set_write_timeout(limits.timeout)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    while let Some(request) = read_message(&mut reader, limits.max_frame_len)? {
+        let response = match request {
+            Request::Get(key) => Response::Value(cache.get(&key)),
+            Request::Insert(key, value) => {
+                cache.insert(key, value);
+                Response::Done
+            }
+            Request::Remove(key) => Response::Value(cache.remove(&key)),
+            Request::Clear => {
+                cache.clear();
+                Response::Done
+            }
+            Request::Stats => Response::Stats(cache.stats()),
+        };
+        write_message(&mut writer, &response)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// A client for a cache served with [`serve`].
+///
+/// Calls are sent over a single connection, one at a time.
+pub struct RemoteCache<K, V> {
+    connection: Mutex<(BufReader<TcpStream>, BufWriter<TcpStream>)>,
+    /// The largest response read
+    max_frame_len: u32,
+    _marker: std::marker::PhantomData<fn(K) -> V>,
+}
+
+impl<K, V> RemoteCache<K, V>
+where
+    K: Serialize + for<'a> Deserialize<'a>,
+    V: Serialize + for<'a> Deserialize<'a>,
+{
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(RemoteCache {
+            connection: Mutex::new((BufReader::new(stream.try_clone()?), BufWriter::new(stream))),
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            _marker: std::marker::PhantomData,
+        })
+    }
+
+    /// Sets the largest response read, for values larger than the
+    /// [`DEFAULT_MAX_FRAME_LEN`].
+    pub fn with_max_frame_len(mut self, max_frame_len: u32) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        match self.call(&Request::<&K, &V>::Get(key))? {
+            Response::Value(value) => Ok(value),
+            _ => bail!("Unexpected response to get"),
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Result<()> {
+        match self.call(&Request::Insert(&key, &value))? {
+            Response::Done => Ok(()),
+            _ => bail!("Unexpected response to insert"),
+        }
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        match self.call(&Request::<&K, &V>::Clear)? {
+            Response::Done => Ok(()),
+            _ => bail!("Unexpected response to clear"),
+        }
+    }
+
+    pub fn remove(&self, key: &K) -> Result<Option<V>> {
+        match self.call(&Request::<&K, &V>::Remove(key))? {
+            Response::Value(value) => Ok(value),
+            _ => bail!("Unexpected response to remove"),
+        }
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        match self.call(&Request::<&K, &V>::Stats)? {
+            Response::Stats(stats) => Ok(stats),
+            _ => bail!("Unexpected response to stats"),
+        }
+    }
+
+    fn call(&self, request: &Request<&K, &V>) -> Result<Response<V>> {
+        let mut connection = self.connection.lock().recover();
+        let (reader, writer) = &mut *connection;
+        write_message(writer, request)?;
+        writer.flush()?;
+        read_message(reader, self.max_frame_len)?
+            .ok_or_else(|| anyhow!("Server closed the connection"))
+    }
+}
+
+/// Handle to a thread started by [`replicate`].
+///
+/// Dropping the handle sends the changes made so far, then stops the thread.
+pub struct Replication {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Replication {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Streams the inserts, removals and clears made to `cache` to `peers` on a
+/// background thread, for instance to keep a warm standby.
+///
+/// Replication is best-effort: a peer that cannot be reached misses the
+/// changes made meanwhile, and evictions are not replicated, as each peer
+/// manages its own capacity. Each change sends the key's value at the time
+/// it is replicated, so rapid updates of a key may be coalesced, and an
+/// entry evicted before it is replicated is not sent at all.
+pub fn replicate<K, V>(cache: &Cache<K, V>, peers: Vec<RemoteCache<K, V>>) -> Replication
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    let events = cache.subscribe();
+    let cache = cache.clone();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let thread = std::thread::spawn(move || {
+        let mut missed = 0;
+        loop {
+            // Once stopped, only the changes already made are sent
+            let stopping = stopped.load(Ordering::Relaxed);
+            let event = match stopping {
+                true => events.try_recv(),
+                false => events.recv_timeout(Duration::from_millis(100)),
+            };
+            let Some(event) = event else {
+                match stopping {
+                    true => break,
+                    false => continue,
+                }
+            };
+            if events.missed() != missed {
+                missed = events.missed();
+                report(anyhow!(
+                    "Replication fell behind; peers missed {} changes",
+                    missed
+                ));
+            }
+            for peer in &peers {
+                let result = match &event {
+                    // A key gone since was removed, which is replicated next, or evicted
+                    CacheEvent::Inserted(key) | CacheEvent::Updated(key) => match cache.peek(key) {
+                        Some(value) => peer.insert(key.clone(), value),
+                        None => Ok(()),
+                    },
+                    CacheEvent::Removed(key) => peer.remove(key).map(|_| ()),
+                    CacheEvent::Cleared => peer.clear(),
+                    CacheEvent::Evicted(_) => Ok(()),
+                };
+                if let Err(e) = result {
+                    report(e.context("Failed to replicate change"));
+                }
+            }
+        }
+    });
+    Replication {
+        stop,
+        thread: Some(thread),
+    }
+}
+
+fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<()> {
+    let payload = bincode::serialize(message)?;
+    let len = u32::try_from(payload.len())
+        .map_err(|_| anyhow!("Frame of {} bytes is too large", payload.len()))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&payload)?;
+    Ok(())
+}
+
+/// Reads the next message, or returns `None` if the stream ended between messages.
+fn read_message<T: for<'a> Deserialize<'a>>(
+    reader: &mut impl Read,
+    max_frame_len: u32,
+) -> Result<Option<T>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let len = u32::from_le_bytes(len);
+    if len > max_frame_len {
+        bail!("Frame of {} bytes is too large", len);
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(bincode::deserialize(&payload)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{replicate, serve, serve_with, RemoteCache, ServerLimits};
+    use crate::{Cache, CacheStats};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    #[test]
+    fn test_remote_cache() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cache: Cache<i32, String> = Cache::new_lru(1);
+        std::thread::spawn(move || serve(cache, listener));
+
+        let client: RemoteCache<i32, String> = RemoteCache::connect(addr).unwrap();
+        client.insert(1, "one".to_string()).unwrap();
+        client.insert(2, "two".to_string()).unwrap();
+        assert_eq!(client.get(&1).unwrap(), None);
+        assert_eq!(client.get(&2).unwrap(), Some("two".to_string()));
+        assert_eq!(client.remove(&2).unwrap(), Some("two".to_string()));
+        assert_eq!(
+            client.stats().unwrap(),
+            CacheStats {
+                len: 0,
+                capacity: Some(1),
+                hits: 1,
+                misses: 1,
+                evictions: 1,
+                inserts: 2,
+                updates: 0,
+                removals: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_server_limits() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let cache: Cache<i32, String> = Cache::new_unbounded();
+        let limits = ServerLimits {
+            max_frame_len: 64,
+            max_connections: 1,
+            timeout: Some(Duration::from_millis(100)),
+        };
+        std::thread::spawn(move || serve_with(cache, listener, limits));
+
+        let client: RemoteCache<i32, String> = RemoteCache::connect(addr).unwrap();
+        client.insert(1, "one".to_string()).unwrap();
+        // A second connection is closed at once
+        let refused: RemoteCache<i32, String> = RemoteCache::connect(addr).unwrap();
+        assert!(refused.get(&1).is_err());
+        // A request larger than a frame may be ends the connection
+        assert!(client.insert(2, "x".repeat(100)).is_err());
+
+        // A peer that stalls is disconnected, freeing its slot
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        stalled.write_all(&[1]).unwrap();
+        let mut rest = Vec::new();
+        stalled.read_to_end(&mut rest).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let client: RemoteCache<i32, String> = RemoteCache::connect(addr).unwrap();
+        assert_eq!(client.get(&1).unwrap(), Some("one".to_string()));
+    }
+
+    #[test]
+    fn test_replicate() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let standby: Cache<i32, String> = Cache::new_unbounded();
+        let served = standby.clone();
+        std::thread::spawn(move || serve(served, listener));
+
+        let primary = Cache::new_lru(2);
+        let replication = replicate(&primary, vec![RemoteCache::connect(addr).unwrap()]);
+        primary.insert(1, "one".to_string());
+        primary.insert(2, "two".to_string());
+        primary.insert(2, "dos".to_string());
+        primary.remove(&1);
+        primary.insert(3, "three".to_string());
+        drop(replication);
+
+        assert_eq!(standby.len(), 2);
+        assert_eq!(standby.peek(&2), Some("dos".to_string()));
+        assert_eq!(standby.peek(&3), Some("three".to_string()));
+    }
+}
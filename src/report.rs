@@ -0,0 +1,47 @@
+//! Errors without a caller to return them to: those of background threads,
+//! of callbacks users give the cache, and of work done on behalf of calls
+//! that cannot fail.
+use crate::sync::Recover;
+use std::sync::{Arc, RwLock};
+
+type ErrorHook = Arc<dyn Fn(&anyhow::Error) + Send + Sync>;
+
+static HOOK: RwLock<Option<ErrorHook>> = RwLock::new(None);
+
+/// Calls `hook` with every error that has no caller to return it to, e.g.
+/// a failed background snapshot, a panicking listener or a dropped server
+/// connection, in place of the previous hook.
+///
+/// The hook is shared by all caches in the process. Without one, these
+/// errors are dropped. It runs on the thread that hit the error, so it
+/// should hand slow work off elsewhere.
+pub fn set_error_hook(hook: impl Fn(&anyhow::Error) + Send + Sync + 'static) {
+    *HOOK.write().recover() = Some(Arc::new(hook));
+}
+
+/// Hands `error` to the hook set with [`set_error_hook`], if any.
+pub(crate) fn report(error: impl Into<anyhow::Error>) {
+    let hook = HOOK.read().recover().clone();
+    if let Some(hook) = hook {
+        hook(&error.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_error_hook() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let seen = reported.clone();
+        set_error_hook(move |e| seen.lock().unwrap().push(e.to_string()));
+        report(anyhow::anyhow!("Failed to snapshot cache"));
+        // Other tests may report errors of their own meanwhile
+        assert!(reported
+            .lock()
+            .unwrap()
+            .contains(&"Failed to snapshot cache".to_string()));
+    }
+}
@@ -0,0 +1,40 @@
+//! Spreading keys over the shards of the maps the way `DashMap` does, for
+//! statistics kept per shard, using only the public API of the map.
+use dashmap::DashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::OnceLock;
+
+/// The number of shards the maps of the caches are created with, the same
+/// as `DashMap` picks by default.
+pub(crate) fn amount() -> usize {
+    static AMOUNT: OnceLock<usize> = OnceLock::new();
+    *AMOUNT.get_or_init(|| {
+        (std::thread::available_parallelism().map_or(1, usize::from) * 4).next_power_of_two()
+    })
+}
+
+/// Returns the shard holding `key` in a map created with [`amount`] shards.
+pub(crate) fn of<K, V, S, Q>(map: &DashMap<K, V, S>, key: &Q) -> usize
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+    Q: Hash + ?Sized,
+{
+    // The shard is taken from the bits of the hash below the top 7, which
+    // the map leaves for the tables inside its shards
+    let shift = usize::BITS - amount().trailing_zeros();
+    (map.hash_usize(&key) << 7) >> shift
+}
+
+/// Returns the number of entries in each shard of `map`.
+pub(crate) fn lens<K, V, S>(map: &DashMap<K, V, S>) -> Vec<usize>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone,
+{
+    let mut lens = vec![0; amount()];
+    for entry in map.iter() {
+        lens[of(map, entry.key())] += 1;
+    }
+    lens
+}
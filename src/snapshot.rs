@@ -0,0 +1,275 @@
+//! Periodic background tasks of a cache: snapshots, log compaction,
+//! statistics logging, shrinking under memory pressure, purging idle
+//! entries and autotuning capacity.
+use crate::report::report;
+use crate::sync::catch;
+use crate::{Cache, CacheStats};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+enum Command {
+    Flush(Sender<Result<()>>),
+    Stop,
+}
+
+/// Handle to a background thread started by [`Cache::persist_every`],
+/// [`Cache::compact_wal_every`], [`Cache::log_stats_every`],
+/// [`Cache::shrink_under_pressure`], [`Cache::purge_idle_every`] or
+/// [`Cache::autotune`].
+///
+/// Dropping the handle stops the thread.
+pub struct SnapshotHandle {
+    sender: Sender<Command>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SnapshotHandle {
+    pub(crate) fn spawn<K, V>(cache: Cache<K, V>, file_name: String, interval: Duration) -> Self
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+        V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    {
+        // Generation of the cache at the last successful snapshot
+        let mut written = None;
+        Self::spawn_task(interval, move |forced| {
+            let generation = cache.generation();
+            if !forced && written == Some(generation) {
+                return Ok(());
+            }
+            cache.write(&file_name)?;
+            written = Some(generation);
+            Ok(())
+        })
+    }
+
+    pub(crate) fn spawn_compaction<K, V>(
+        cache: Cache<K, V>,
+        snapshot_file: String,
+        max_bytes: u64,
+        interval: Duration,
+    ) -> Self
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+        V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    {
+        Self::spawn_task(interval, move |forced| {
+            if !forced && cache.wal_len()? <= max_bytes {
+                return Ok(());
+            }
+            cache.compact_wal(&snapshot_file)
+        })
+    }
+
+    pub(crate) fn spawn_stats_log<K, V>(cache: Cache<K, V>, interval: Duration) -> Self
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+        V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    {
+        let mut previous = cache.stats();
+        Self::spawn_task(interval, move |_| {
+            let current = cache.stats();
+            eprintln!("{}", stats_line(&current.since(&previous)));
+            previous = current;
+            Ok(())
+        })
+    }
+
+    pub(crate) fn spawn_pressure<K, V, F>(
+        cache: Cache<K, V>,
+        interval: Duration,
+        mut share: F,
+    ) -> Self
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+        V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+        F: FnMut() -> f64 + Send + 'static,
+    {
+        Self::spawn_task(interval, move |_| {
+            cache.scale_capacity(share());
+            Ok(())
+        })
+    }
+
+    pub(crate) fn spawn_idle_purge<F>(interval: Duration, mut purge: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self::spawn_task(interval, move |_| {
+            purge();
+            Ok(())
+        })
+    }
+
+    pub(crate) fn spawn_autotune<F>(interval: Duration, mut tune: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        Self::spawn_task(interval, move |_| {
+            tune();
+            Ok(())
+        })
+    }
+
+    /// Runs `task` every `interval`, and with `true` whenever a flush is requested.
+    fn spawn_task<F>(interval: Duration, mut task: F) -> Self
+    where
+        F: FnMut(bool) -> Result<()> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || loop {
+            match receiver.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Err(e) = catch("Background task", || task(false)).and_then(|r| r) {
+                        report(e.context("Failed to snapshot cache"));
+                    }
+                }
+                Ok(Command::Flush(reply)) => {
+                    let _ = reply.send(catch("Background task", || task(true)).and_then(|r| r));
+                }
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        });
+
+        SnapshotHandle {
+            sender,
+            thread: Some(thread),
+        }
+    }
+
+    /// Runs the task immediately, e.g. writes a snapshot, waiting for it to complete.
+    pub fn flush(&self) -> Result<()> {
+        let (reply, response) = mpsc::channel();
+        self.sender
+            .send(Command::Flush(reply))
+            .map_err(|_| anyhow!("Snapshot thread has stopped"))?;
+        response
+            .recv()
+            .map_err(|_| anyhow!("Snapshot thread has stopped"))?
+    }
+
+    /// Stops the background thread, waiting for an in-progress snapshot to finish.
+    pub fn stop(self) {
+        // Dropping does the work
+    }
+}
+
+/// Formats the activity of an interval as one line of `key=value` pairs.
+fn stats_line(delta: &CacheStats) -> String {
+    let capacity = match delta.capacity {
+        Some(capacity) => capacity.to_string(),
+        None => "none".to_string(),
+    };
+    format!(
+        "cache_stats len={} capacity={} hit_ratio={:.3} hits={} misses={} evictions={} inserts={} updates={} removals={}",
+        delta.len,
+        capacity,
+        delta.hit_ratio(),
+        delta.hits,
+        delta.misses,
+        delta.evictions,
+        delta.inserts,
+        delta.updates,
+        delta.removals
+    )
+}
+
+impl Drop for SnapshotHandle {
+    fn drop(&mut self) {
+        let _ = self.sender.send(Command::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stats_line;
+    use crate::Cache;
+    use std::time::Duration;
+
+    #[test]
+    fn test_stats_line() {
+        let cache = Cache::new_lru(2);
+        cache.insert(1, 1);
+        cache.get(&1);
+        let earlier = cache.stats();
+        for i in 2..5 {
+            cache.insert(i, i);
+        }
+        cache.get(&4);
+        cache.get(&1);
+
+        assert_eq!(
+            stats_line(&cache.stats().since(&earlier)),
+            "cache_stats len=2 capacity=2 hit_ratio=0.500 hits=1 misses=1 evictions=2 inserts=3 updates=0 removals=0"
+        );
+        let handle = cache.log_stats_every(Duration::from_secs(60));
+        handle.flush().unwrap();
+    }
+
+    #[test]
+    fn test_persist_every() {
+        let path = std::env::temp_dir().join("minne_persist_every.cache");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let cache = Cache::new_unbounded();
+        cache.insert(1, "one".to_string());
+        let handle = cache.persist_every(path, Duration::from_millis(20));
+
+        std::thread::sleep(Duration::from_millis(100));
+        let loaded: Cache<i32, String> = Cache::new_unbounded();
+        loaded.read(path).unwrap();
+        assert_eq!(loaded.get(&1), Some("one".to_string()));
+
+        // Unchanged caches are not rewritten
+        let modified = std::fs::metadata(path).unwrap().modified().unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            std::fs::metadata(path).unwrap().modified().unwrap(),
+            modified
+        );
+
+        cache.insert(2, "two".to_string());
+        handle.flush().unwrap();
+        let loaded: Cache<i32, String> = Cache::new_unbounded();
+        loaded.read(path).unwrap();
+        assert_eq!(loaded.get(&2), Some("two".to_string()));
+
+        handle.stop();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_compact_wal_every() {
+        let dir = std::env::temp_dir();
+        let log = dir.join("minne_compact_every.log");
+        let snapshot = dir.join("minne_compact_every.cache");
+        let (log, snapshot) = (log.to_str().unwrap(), snapshot.to_str().unwrap());
+        let _ = std::fs::remove_file(log);
+
+        let cache = Cache::new_unbounded();
+        cache.enable_wal(log).unwrap();
+        let handle = cache.compact_wal_every(snapshot, 4096, Duration::from_millis(10));
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        handle.flush().unwrap();
+        assert!(std::fs::metadata(log).unwrap().len() < 4096);
+        drop(handle);
+        drop(cache);
+
+        let recovered: Cache<i32, i32> = Cache::new_unbounded();
+        recovered.read(snapshot).unwrap();
+        recovered.enable_wal(log).unwrap();
+        assert_eq!(recovered.len(), 1000);
+        std::fs::remove_file(log).unwrap();
+        std::fs::remove_file(snapshot).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+//! On-disk store for entries evicted from an LRU cache.
+//!
+//! Values are appended to a scratch file as checksummed frames, with an
+//! in-memory index from key to frame. Taking a value back out only drops it
+//! from the index; the file is rewritten once most of it is dead space.
+use crate::persistence;
+use crate::sync::Recover;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The file is only compacted once it is at least this large.
+const MIN_COMPACT_LEN: u64 = 1 << 20;
+
+pub(crate) struct Spill<K, V> {
+    path: PathBuf,
+    state: Mutex<SpillState<K>>,
+    _marker: PhantomData<fn(V) -> V>,
+}
+
+struct SpillState<K> {
+    file: File,
+    /// Offset and length of each key's frame
+    index: HashMap<K, (u64, usize)>,
+    /// Length of the file
+    len: u64,
+    /// Bytes of the file still referenced by the index
+    live: u64,
+}
+
+impl<K, V> Spill<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Creates the store at `file_name`, discarding anything already there.
+    pub(crate) fn open(file_name: &str) -> Result<Self> {
+        let file = open_scratch(Path::new(file_name))?;
+        Ok(Spill {
+            path: PathBuf::from(file_name),
+            state: Mutex::new(SpillState {
+                file,
+                index: HashMap::new(),
+                len: 0,
+                live: 0,
+            }),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Writes `value` to disk under `key`, replacing any earlier value.
+    pub(crate) fn put(&self, key: &K, value: &V) -> Result<()> {
+        let mut frame = Vec::new();
+        persistence::write_frame(&mut frame, &bincode::serialize(value)?);
+
+        let mut state = self.state.lock().recover();
+        let offset = state.len;
+        state.file.seek(SeekFrom::Start(offset))?;
+        state.file.write_all(&frame)?;
+        state.len += frame.len() as u64;
+        state.live += frame.len() as u64;
+        if let Some((_, old)) = state.index.insert(key.clone(), (offset, frame.len())) {
+            state.live -= old as u64;
+        }
+
+        if state.len > MIN_COMPACT_LEN && state.len > 2 * state.live {
+            self.compact(&mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the value for `key` from the store and returns it.
+    pub(crate) fn take(&self, key: &K) -> Result<Option<V>> {
+        let mut state = self.state.lock().recover();
+        let Some((offset, len)) = state.index.remove(key) else {
+            return Ok(None);
+        };
+        state.live -= len as u64;
+
+        let frame = read_at(&mut state.file, offset, len)?;
+        let value = bincode::deserialize(persistence::read_frame(&frame, 0)?)?;
+        Ok(Some(value))
+    }
+
+    /// Reads the value for `key`, leaving it in the store.
+    pub(crate) fn get(&self, key: &K) -> Result<Option<V>> {
+        let mut state = self.state.lock().recover();
+        let Some(&(offset, len)) = state.index.get(key) else {
+            return Ok(None);
+        };
+        let frame = read_at(&mut state.file, offset, len)?;
+        Ok(Some(bincode::deserialize(persistence::read_frame(
+            &frame, 0,
+        )?)?))
+    }
+
+    /// Forgets the value for `key`, returning whether there was one.
+    pub(crate) fn discard(&self, key: &K) -> bool {
+        let mut state = self.state.lock().recover();
+        match state.index.remove(key) {
+            Some((_, len)) => {
+                state.live -= len as u64;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes every value from the store and returns them.
+    pub(crate) fn take_all(&self) -> Result<Vec<(K, V)>> {
+        let mut state = self.state.lock().recover();
+        let index = std::mem::take(&mut state.index);
+        let mut entries = Vec::with_capacity(index.len());
+        for (key, (offset, len)) in index {
+            let frame = read_at(&mut state.file, offset, len)?;
+            entries.push((
+                key,
+                bincode::deserialize(persistence::read_frame(&frame, 0)?)?,
+            ));
+        }
+        state.file.set_len(0)?;
+        state.len = 0;
+        state.live = 0;
+        Ok(entries)
+    }
+
+    pub(crate) fn clear(&self) -> Result<()> {
+        let mut state = self.state.lock().recover();
+        state.index.clear();
+        state.file.set_len(0)?;
+        state.len = 0;
+        state.live = 0;
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().recover().index.len()
+    }
+
+    /// Rewrites the file with only the live frames.
+    fn compact(&self, state: &mut SpillState<K>) -> Result<()> {
+        let temp = persistence::temp_path(&self.path);
+        let mut out = open_scratch(&temp)?;
+        let mut len = 0;
+        let mut index = HashMap::with_capacity(state.index.len());
+        for (key, &(offset, frame_len)) in &state.index {
+            let frame = read_at(&mut state.file, offset, frame_len)?;
+            out.write_all(&frame)?;
+            index.insert(key.clone(), (len, frame_len));
+            len += frame_len as u64;
+        }
+        fs::rename(&temp, &self.path).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })?;
+
+        state.file = out;
+        state.index = index;
+        state.len = len;
+        state.live = len;
+        Ok(())
+    }
+}
+
+impl<K, V> Drop for Spill<K, V> {
+    fn drop(&mut self) {
+        // The index is lost with the cache, so the file is of no further use
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn open_scratch(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let mut frame = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut frame)?;
+    Ok(frame)
+}
@@ -0,0 +1,75 @@
+//! Keeping a panic on one thread from spreading to others: recovering
+//! poisoned locks, and isolating the callbacks users give the cache.
+//!
+//! A thread that panics while holding a lock poisons it, and unwrapping
+//! every later lock would spread that one panic to every thread using the
+//! cache. The state behind the locks stays consistent enough to carry on
+//! with: at worst an entry is counted, ordered or logged once too often or
+//! not at all, which a cache can live with.
+use crate::report::report;
+use anyhow::{anyhow, Result};
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{LockResult, PoisonError};
+use std::task::{Context, Poll};
+
+pub(crate) trait Recover<T> {
+    /// Returns the guard, whether or not the lock was poisoned.
+    fn recover(self) -> T;
+}
+
+impl<T> Recover<T> for LockResult<T> {
+    fn recover(self) -> T {
+        self.unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Runs the callback `f`, turning a panic into an error naming `what`.
+pub(crate) fn catch<T>(what: &str, f: impl FnOnce() -> T) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .map_err(|panic| anyhow!("{} panicked: {}", what, message(&*panic)))
+}
+
+/// Runs the callback `f`, reporting a panic instead of unwinding through
+/// the cache, and returns its result if it did not panic.
+pub(crate) fn isolate<T>(what: &str, f: impl FnOnce() -> T) -> Option<T> {
+    catch(what, f).map_err(report).ok()
+}
+
+fn message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown cause", String::as_str),
+    }
+}
+
+/// A future whose panics become errors, for loads run by user code.
+pub(crate) struct Isolated<F> {
+    what: &'static str,
+    inner: Pin<Box<F>>,
+}
+
+impl<F> Isolated<F> {
+    pub(crate) fn new(what: &'static str, inner: F) -> Self {
+        Isolated {
+            what,
+            inner: Box::pin(inner),
+        }
+    }
+}
+
+impl<F: Future<Output = Result<T>>, T> Future for Isolated<F> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let what = self.what;
+        match catch(what, || self.inner.as_mut().poll(cx)) {
+            Ok(poll) => poll,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
@@ -0,0 +1,22 @@
+//! Helpers shared by the tests of several modules.
+use std::path::PathBuf;
+
+/// A path under the temp directory unique to one test run, with the file
+/// at it removed when dropped.
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    pub(crate) fn new(name: &str) -> Self {
+        TempPath(std::env::temp_dir().join(format!("minne_{}_{}", std::process::id(), name)))
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,143 @@
+//! A local cache in front of a shared, remote second tier.
+use crate::AsyncCache;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::hash::Hash;
+
+/// A shared store behind the local cache, such as Redis.
+///
+/// Implementations map these calls onto their client, e.g. `GET`, `SET` and
+/// `DEL` for Redis, encoding keys and values as they see fit.
+pub trait Tier<K, V>: Send + Sync {
+    fn get(&self, key: &K) -> impl Future<Output = Result<Option<V>>> + Send;
+
+    fn insert(&self, key: &K, value: &V) -> impl Future<Output = Result<()>> + Send;
+
+    fn remove(&self, key: &K) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// A two-level cache: lookups that miss locally fall through to `remote`,
+/// and values found there are cached locally.
+///
+/// With write-through enabled, inserts and removals are applied to the remote
+/// tier as well, so other instances sharing it see them.
+pub struct TieredCache<K, V, T>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    local: AsyncCache<K, V>,
+    remote: T,
+    write_through: bool,
+}
+
+impl<K, V, T> TieredCache<K, V, T>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    T: Tier<K, V>,
+{
+    pub fn new(local: AsyncCache<K, V>, remote: T) -> Self {
+        TieredCache {
+            local,
+            remote,
+            write_through: false,
+        }
+    }
+
+    /// Sets whether inserts and removals are also applied to the remote tier.
+    pub fn write_through(mut self, enabled: bool) -> Self {
+        self.write_through = enabled;
+        self
+    }
+
+    /// Returns the value for `key` from the local cache, or else from the remote tier.
+    pub async fn get(&self, key: &K) -> Result<Option<V>> {
+        if let Some(value) = self.local.get(key).await {
+            return Ok(Some(value));
+        }
+        let value = self.remote.get(key).await?;
+        if let Some(value) = &value {
+            self.local.insert(key.clone(), value.clone()).await;
+        }
+        Ok(value)
+    }
+
+    /// Inserts into the local cache, and into the remote tier with write-through.
+    ///
+    /// If the remote insert fails, nothing is inserted locally either.
+    pub async fn insert(&self, key: K, value: V) -> Result<()> {
+        if self.write_through {
+            self.remote.insert(&key, &value).await?;
+        }
+        self.local.insert(key, value).await;
+        Ok(())
+    }
+
+    /// Removes from the local cache, and from the remote tier with write-through.
+    pub async fn remove(&self, key: &K) -> Result<Option<V>> {
+        let value = self.local.remove(key).await;
+        if self.write_through {
+            self.remote.remove(key).await?;
+        }
+        Ok(value)
+    }
+
+    pub fn local(&self) -> &AsyncCache<K, V> {
+        &self.local
+    }
+
+    pub fn remote(&self) -> &T {
+        &self.remote
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tier, TieredCache};
+    use crate::async_cache::block_on;
+    use crate::{AsyncCache, Cache};
+    use anyhow::Result;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Stands in for a shared store.
+    #[derive(Default)]
+    struct Remote(Mutex<HashMap<i32, String>>);
+
+    impl Tier<i32, String> for Remote {
+        async fn get(&self, key: &i32) -> Result<Option<String>> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        async fn insert(&self, key: &i32, value: &String) -> Result<()> {
+            self.0.lock().unwrap().insert(*key, value.clone());
+            Ok(())
+        }
+
+        async fn remove(&self, key: &i32) -> Result<()> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tiered_cache() {
+        let remote = Remote::default();
+        remote.0.lock().unwrap().insert(1, "one".to_string());
+        let cache =
+            TieredCache::new(AsyncCache::new(Cache::new_lru(10)), remote).write_through(true);
+
+        block_on(async {
+            assert_eq!(cache.get(&1).await.unwrap(), Some("one".to_string()));
+            assert_eq!(cache.local().get(&1).await, Some("one".to_string()));
+            assert_eq!(cache.get(&2).await.unwrap(), None);
+
+            cache.insert(2, "two".to_string()).await.unwrap();
+            assert!(cache.remote().0.lock().unwrap().contains_key(&2));
+            cache.remove(&1).await.unwrap();
+            assert!(!cache.remote().0.lock().unwrap().contains_key(&1));
+        });
+    }
+}
@@ -0,0 +1,290 @@
+//! Recording the accesses to a cache to a file, and replaying them against
+//! other caches to tune the capacity or policy offline.
+use crate::report::report;
+use crate::{Cache, CacheStats};
+use anyhow::{anyhow, bail, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// The first bytes of a trace file.
+const MAGIC: &[u8; 8] = b"MINNETR1";
+
+/// The bytes of one record: the time, the operation and the key hash.
+const RECORD_LEN: usize = 8 + 1 + 8;
+
+/// Records waiting to be written; more are dropped rather than slowing
+/// the cache down.
+const BUFFERED: usize = 64 * 1024;
+
+/// An operation on a cache, as recorded in a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp {
+    Get,
+    Insert,
+    Remove,
+    Clear,
+}
+
+/// One access to a cache, see [`Cache::record_trace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Microseconds since recording started
+    pub micros: u64,
+    pub op: TraceOp,
+    /// The hash of the key, 0 for clears
+    pub key_hash: u64,
+}
+
+impl TraceRecord {
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0; RECORD_LEN];
+        bytes[..8].copy_from_slice(&self.micros.to_le_bytes());
+        bytes[8] = self.op as u8;
+        bytes[9..].copy_from_slice(&self.key_hash.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let op = match bytes[8] {
+            0 => TraceOp::Get,
+            1 => TraceOp::Insert,
+            2 => TraceOp::Remove,
+            3 => TraceOp::Clear,
+            op => bail!("Unknown trace operation {}", op),
+        };
+        Ok(TraceRecord {
+            micros: u64::from_le_bytes(bytes[..8].try_into()?),
+            op,
+            key_hash: u64::from_le_bytes(bytes[9..].try_into()?),
+        })
+    }
+}
+
+/// Hands accesses to the thread writing them, without blocking the cache.
+pub(crate) struct Tracer {
+    start: Instant,
+    recording: AtomicBool,
+    sender: SyncSender<Option<TraceRecord>>,
+    dropped: AtomicUsize,
+}
+
+impl Tracer {
+    /// Records an access to `key`, or a clear without one.
+    pub(crate) fn record<K: Hash>(&self, op: TraceOp, key: Option<&K>) {
+        if !self.recording.load(Ordering::Relaxed) {
+            return;
+        }
+        let record = TraceRecord {
+            micros: self.start.elapsed().as_micros() as u64,
+            op,
+            key_hash: key.map_or(0, hash_key),
+        };
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(Some(record)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Hashes keys the same way in every run of a build, so traces recorded
+/// at different times can be compared.
+fn hash_key<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// What a recording wrote, see [`TraceHandle::stop`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceSummary {
+    /// Records written to the file
+    pub recorded: usize,
+    /// Records dropped because the writer fell behind
+    pub dropped: usize,
+}
+
+/// Controls a recording started by [`Cache::record_trace`].
+///
+/// Dropping the handle stops recording, as does [`TraceHandle::stop`],
+/// which also reports what was written.
+pub struct TraceHandle {
+    tracer: Arc<Tracer>,
+    thread: Option<JoinHandle<Result<usize>>>,
+}
+
+impl TraceHandle {
+    /// Starts a recording to `file_name` and returns the tracer to give to
+    /// the cache with the handle.
+    pub(crate) fn start(file_name: &str) -> Result<(Arc<Tracer>, Self)> {
+        let mut file = BufWriter::new(File::create(file_name)?);
+        file.write_all(MAGIC)?;
+        let (sender, receiver) = sync_channel(BUFFERED);
+        let tracer = Arc::new(Tracer {
+            start: Instant::now(),
+            recording: AtomicBool::new(true),
+            sender,
+            dropped: AtomicUsize::new(0),
+        });
+        let thread = std::thread::Builder::new()
+            .name("minne-trace".to_string())
+            .spawn(move || write_records(file, receiver))?;
+        let handle = TraceHandle {
+            tracer: tracer.clone(),
+            thread: Some(thread),
+        };
+        Ok((tracer, handle))
+    }
+
+    /// Stops recording and waits for the buffered records to be written.
+    pub fn stop(mut self) -> Result<TraceSummary> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<TraceSummary> {
+        self.tracer.recording.store(false, Ordering::Relaxed);
+        let _ = self.tracer.sender.send(None);
+        let recorded = match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .map_err(|_| anyhow!("The trace writer panicked"))??,
+            None => 0,
+        };
+        Ok(TraceSummary {
+            recorded,
+            dropped: self.tracer.dropped.load(Ordering::Relaxed),
+        })
+    }
+}
+
+impl Drop for TraceHandle {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            report(e.context("Failed to write trace"));
+        }
+    }
+}
+
+/// Writes records until told to stop, flushing whenever it catches up.
+fn write_records(
+    mut file: BufWriter<File>,
+    receiver: Receiver<Option<TraceRecord>>,
+) -> Result<usize> {
+    let mut recorded = 0;
+    while let Ok(Some(record)) = receiver.recv() {
+        file.write_all(&record.encode())?;
+        recorded += 1;
+        for record in receiver.try_iter() {
+            match record {
+                Some(record) => file.write_all(&record.encode())?,
+                None => {
+                    file.flush()?;
+                    return Ok(recorded);
+                }
+            }
+            recorded += 1;
+        }
+        file.flush()?;
+    }
+    file.flush()?;
+    Ok(recorded)
+}
+
+/// The accesses recorded to a trace file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    records: Vec<TraceRecord>,
+}
+
+impl Trace {
+    /// Reads a trace written by [`Cache::record_trace`]. A record cut
+    /// short, by a process that died while recording, is left out.
+    pub fn read(file_name: &str) -> Result<Self> {
+        let mut data = Vec::new();
+        File::open(file_name)?.read_to_end(&mut data)?;
+        let records = data
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| anyhow!("'{}' is not a trace file", file_name))?;
+        let records = records
+            .chunks_exact(RECORD_LEN)
+            .map(TraceRecord::decode)
+            .collect::<Result<_>>()?;
+        Ok(Trace { records })
+    }
+
+    pub fn records(&self) -> &[TraceRecord] {
+        &self.records
+    }
+
+    /// Replays the accesses against `cache`, keyed by the key hashes, and
+    /// returns its statistics for the replay.
+    ///
+    /// Replaying against caches of different capacities or policies shows
+    /// how they would have fared with the recorded traffic. Misses are not
+    /// filled, as the application filled them with the inserts recorded.
+    pub fn replay(&self, cache: &Cache<u64, ()>) -> CacheStats {
+        let before = cache.stats();
+        for record in &self.records {
+            match record.op {
+                TraceOp::Get => {
+                    cache.get(&record.key_hash);
+                }
+                TraceOp::Insert => cache.insert(record.key_hash, ()),
+                TraceOp::Remove => {
+                    cache.remove(&record.key_hash);
+                }
+                TraceOp::Clear => cache.clear(),
+            }
+        }
+        cache.stats().since(&before)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Trace, TraceOp};
+    use crate::Cache;
+
+    #[test]
+    fn test_trace() {
+        let path = std::env::temp_dir().join("minne_trace.bin");
+        let path = path.to_str().unwrap();
+
+        let cache = Cache::new_lru(10);
+        cache.insert("untraced".to_string(), 0);
+        let handle = cache.record_trace(path).unwrap();
+        assert!(cache.record_trace(path).is_err());
+        for key in ["a", "b", "a", "c"] {
+            if cache.get(&key.to_string()).is_none() {
+                cache.insert(key.to_string(), 1);
+            }
+        }
+        cache.remove(&"b".to_string());
+        let summary = handle.stop().unwrap();
+        assert_eq!((summary.recorded, summary.dropped), (8, 0));
+        cache.get(&"a".to_string());
+
+        let trace = Trace::read(path).unwrap();
+        let ops: Vec<TraceOp> = trace.records().iter().map(|r| r.op).collect();
+        use TraceOp::*;
+        assert_eq!(ops, [Get, Insert, Get, Insert, Get, Get, Insert, Remove]);
+        let records = trace.records();
+        assert_eq!(records[0].key_hash, records[4].key_hash);
+        assert!(records.windows(2).all(|w| w[0].micros <= w[1].micros));
+
+        // Too small a cache for the recorded traffic misses more
+        let stats = trace.replay(&Cache::new_lru(10));
+        assert_eq!((stats.hits, stats.misses, stats.inserts), (1, 3, 3));
+        let stats = trace.replay(&Cache::new_lru(1));
+        assert_eq!((stats.hits, stats.misses), (0, 4));
+
+        std::fs::write(path, b"not a trace").unwrap();
+        assert!(Trace::read(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
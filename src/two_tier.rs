@@ -0,0 +1,185 @@
+//! A small LRU cache in front of a larger second tier in the same process.
+use crate::{Cache, CacheStats};
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A cache of two tiers holding each entry in one of them: a small LRU
+/// cache (L1) for the hottest entries, and a larger tier (L2), such as an
+/// unbounded cache or an LRU cache spilling to disk, for the rest.
+///
+/// Entries are inserted into L1. Those it evicts are demoted to L2, and a
+/// hit in L2 promotes the entry back to L1. An entry is only gone once L2
+/// evicts it. Concurrent lookups of an entry being moved between the tiers
+/// may miss it.
+pub struct TwoTierCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    l1: Cache<K, V>,
+    l2: Cache<K, V>,
+    hits: AtomicUsize,
+    /// Hits found in L2, a subset of `hits`
+    l2_hits: AtomicUsize,
+    misses: AtomicUsize,
+    inserts: AtomicUsize,
+    updates: AtomicUsize,
+    removals: AtomicUsize,
+}
+
+impl<K, V> TwoTierCache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    /// Puts an LRU cache of `l1_capacity` entries in front of `l2`.
+    pub fn new(l1_capacity: usize, l2: Cache<K, V>) -> Self {
+        let l1 = Cache::new_lru(l1_capacity);
+        let demoted = l2.clone();
+        l1.on_evict(move |key, value| demoted.insert(key, value));
+        TwoTierCache {
+            l1,
+            l2,
+            hits: AtomicUsize::new(0),
+            l2_hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            inserts: AtomicUsize::new(0),
+            updates: AtomicUsize::new(0),
+            removals: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        // The key may only be in one tier
+        let replaced = self.l1.peek(&key).is_some() || self.l2.remove(&key).is_some();
+        self.l1.insert(key, value);
+        match replaced {
+            true => self.updates.fetch_add(1, Ordering::Relaxed),
+            false => self.inserts.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Returns the value for `key`, promoting it to L1 if it was in L2.
+    pub fn get(&self, key: &K) -> Option<V> {
+        if let Some(value) = self.l1.get(key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Some(value);
+        }
+        match self.l2.remove(key) {
+            Some(value) => {
+                self.l1.insert(key.clone(), value.clone());
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                self.l2_hits.fetch_add(1, Ordering::Relaxed);
+                Some(value)
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Returns the value for `key` without counting a lookup or moving it.
+    pub fn peek(&self, key: &K) -> Option<V> {
+        self.l1.peek(key).or_else(|| self.l2.peek(key))
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let value = self.l1.remove(key).or_else(|| self.l2.remove(key));
+        if value.is_some() {
+            self.removals.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    pub fn clear(&self) {
+        self.l1.clear();
+        self.l2.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.l1.len() + self.l2.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.l1.is_empty() && self.l2.is_empty()
+    }
+
+    pub fn l1(&self) -> &Cache<K, V> {
+        &self.l1
+    }
+
+    pub fn l2(&self) -> &Cache<K, V> {
+        &self.l2
+    }
+
+    /// Returns the statistics of both tiers as one cache.
+    ///
+    /// Evictions are those of entries leaving L2, and so the cache, and
+    /// the capacity is `None` if L2 is unbounded. Moves between the tiers
+    /// are not counted, see [`TwoTierCache::l2_hits`].
+    pub fn stats(&self) -> CacheStats {
+        let (l1, l2) = (self.l1.stats(), self.l2.stats());
+        CacheStats {
+            len: l1.len + l2.len,
+            capacity: l1.capacity.zip(l2.capacity).map(|(l1, l2)| l1 + l2),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: l2.evictions,
+            inserts: self.inserts.load(Ordering::Relaxed),
+            updates: self.updates.load(Ordering::Relaxed),
+            removals: self.removals.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns how many hits were found in L2 and promoted.
+    pub fn l2_hits(&self) -> usize {
+        self.l2_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn hit_ratio(&self) -> f64 {
+        self.stats().hit_ratio()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TwoTierCache;
+    use crate::Cache;
+
+    #[test]
+    fn test_two_tier() {
+        let cache = TwoTierCache::new(2, Cache::new_lru(3));
+        for key in 0..4 {
+            cache.insert(key, key * 10);
+        }
+        // The least recently used were demoted rather than lost
+        assert_eq!(cache.l1().len(), 2);
+        assert_eq!(cache.l2().peek(&0), Some(0));
+        assert_eq!(cache.len(), 4);
+
+        // An L2 hit is promoted, demoting the least recent of L1
+        assert_eq!(cache.get(&0), Some(0));
+        assert_eq!(cache.l1().peek(&0), Some(0));
+        assert_eq!(cache.l2().peek(&0), None);
+        assert_eq!(cache.l2().peek(&2), Some(20));
+
+        // Updates take the key out of L2
+        cache.insert(1, 11);
+        assert_eq!(cache.l2().peek(&1), None);
+        assert_eq!(cache.get(&1), Some(11));
+
+        // Only what L2 evicts is gone
+        cache.insert(4, 40);
+        cache.insert(5, 50);
+        assert_eq!(cache.get(&9), None);
+        let stats = cache.stats();
+        assert_eq!(cache.len(), 5);
+        assert_eq!(stats.capacity, Some(5));
+        assert_eq!(stats.evictions, 1);
+        assert_eq!((stats.inserts, stats.updates), (6, 1));
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!(cache.l2_hits(), 1);
+    }
+}
@@ -1,11 +1,27 @@
-use crate::Statistics;
-use anyhow::Result;
+use crate::audit::{AuditLog, Mutation};
+use crate::dirty::{self, DirtySet};
+use crate::events::{
+    Broadcast, CacheEvent, HookId, Hooks, Listeners, Overflow, Receiver, RemovalCause,
+};
+use crate::frequency::KeyTraffic;
+use crate::idle::Touched;
+use crate::invalidation::InvalidationBus;
+use crate::persistence::{
+    self, CachePolicy, CacheState, LoadReport, PersistenceError, ReadLimits, ReadMode,
+    SnapshotMetadata,
+};
+use crate::report::report;
+use crate::shard;
+use crate::trace::{TraceHandle, TraceOp, Tracer};
+use crate::wal::{Record, Wal};
+use crate::{CacheStats, ShardStats, Statistics, StatsRecorder};
+use anyhow::{anyhow, Result};
 use dashmap::DashMap;
-use serde::{Deserialize, Serialize};
-use std::fs::File;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::hash::Hash;
-use std::io::{BufWriter, Write};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLockReadGuard};
+use std::time::{Duration, SystemTime};
 
 /// An unbounded cache that stores key-value pairs in a `DashMap`.
 pub struct Unbounded<K, V>
@@ -35,6 +51,21 @@ where
 {
     map: DashMap<K, V>,
     statistics: Statistics,
+    wal: OnceLock<Wal<K, V>>,
+    generation: AtomicU64,
+    dirty: DirtySet<K>,
+    events: Broadcast<CacheEvent<K>>,
+    bus: OnceLock<Arc<dyn InvalidationBus<K>>>,
+    listeners: Listeners<K, V>,
+    insert_hooks: Hooks<K, V>,
+    hit_hooks: Hooks<K, V>,
+    /// Access counts, once tracking of hot keys is enabled
+    hot: OnceLock<KeyTraffic<K>>,
+    audit: OnceLock<Arc<AuditLog<K>>>,
+    /// Last use of each entry, once idle entries are purged
+    touched: OnceLock<Touched<K>>,
+    /// Where accesses are recorded, once traced
+    trace: OnceLock<Arc<Tracer>>,
 }
 
 impl<K, V> Unbounded<K, V>
@@ -46,8 +77,20 @@ where
     pub(crate) fn new() -> Self {
         Unbounded {
             inner: Arc::new(UnboundedInner {
-                map: DashMap::with_capacity(10_000),
-                statistics: Statistics::new(),
+                statistics: Statistics::new(shard::amount()),
+                map: DashMap::with_capacity_and_shard_amount(10_000, shard::amount()),
+                wal: OnceLock::new(),
+                generation: AtomicU64::new(0),
+                dirty: DirtySet::new(),
+                events: Broadcast::new(),
+                bus: OnceLock::new(),
+                listeners: Listeners::new(),
+                insert_hooks: Hooks::new(),
+                hit_hooks: Hooks::new(),
+                hot: OnceLock::new(),
+                audit: OnceLock::new(),
+                touched: OnceLock::new(),
+                trace: OnceLock::new(),
             }),
         }
     }
@@ -60,25 +103,319 @@ where
 {
     /// Inserts a key-value pair into the cache.
     pub(crate) fn insert(&self, key: K, value: V) {
-        self.inner.map.insert(key, value);
+        self.trace(TraceOp::Insert, Some(&key));
+        let applying = self.begin();
+        self.log(Record::Insert(&key, &value));
+        let hooked = self
+            .inner
+            .insert_hooks
+            .is_active()
+            .then(|| (key.clone(), value.clone()));
+        let replaced = self.insert_entry(key, value);
+        self.inner.statistics.add_insert(replaced);
+        drop(applying);
+        if let Some((key, value)) = hooked {
+            self.inner.insert_hooks.call(&key, &value);
+        }
+    }
+
+    /// Inserts without logging, for entries that are already durable.
+    /// Returns whether a value was replaced.
+    fn insert_entry(&self, key: K, value: V) -> bool {
+        if let Some(touched) = self.inner.touched.get() {
+            touched.touch(&key);
+        }
+        // Only clone the key when it has to be remembered
+        let replaced = if self.inner.dirty.is_enabled()
+            || self.inner.events.is_active()
+            || self.inner.listeners.is_active()
+        {
+            let previous = self.inner.map.insert(key.clone(), value);
+            let replaced = previous.is_some();
+            if let Some(previous) = previous {
+                self.inner
+                    .listeners
+                    .notify(key.clone(), previous, RemovalCause::Replaced);
+            }
+            self.inner.dirty.mark(&key);
+            self.inner.events.send_with(|| match replaced {
+                true => CacheEvent::Updated(key),
+                false => CacheEvent::Inserted(key),
+            });
+            replaced
+        } else {
+            self.inner.map.insert(key, value).is_some()
+        };
+        self.inner.generation.fetch_add(1, Ordering::Relaxed);
+        replaced
     }
 
     pub(crate) fn get(&self, key: &K) -> Option<V> {
-        if let Some(value) = self.inner.map.get(key) {
-            self.inner.statistics.add_hit();
-            Some(value.clone())
+        self.trace(TraceOp::Get, Some(key));
+        if let Some(hot) = self.inner.hot.get() {
+            hot.lookups.record(key);
+        }
+        let shard = shard::of(&self.inner.map, key);
+        let value = self.inner.map.get(key).map(|value| value.clone());
+        if let Some(value) = value {
+            if let Some(touched) = self.inner.touched.get() {
+                touched.touch(key);
+            }
+            self.inner.statistics.add_hit(shard);
+            self.inner.hit_hooks.call(key, &value);
+            Some(value)
         } else {
-            self.inner.statistics.add_miss();
+            if let Some(hot) = self.inner.hot.get() {
+                hot.misses.record(key);
+            }
+            self.inner.statistics.add_miss(shard);
             None
         }
     }
 
+    /// Returns the value for `key` without counting a lookup.
+    pub(crate) fn peek(&self, key: &K) -> Option<V> {
+        self.inner.map.get(key).map(|value| value.clone())
+    }
+
     pub(crate) fn remove(&self, key: &K) -> Option<V> {
-        self.inner.map.remove(key).map(|(_, v)| v)
+        self.trace(TraceOp::Remove, Some(key));
+        let value = self.remove_unpublished(key);
+        // Other instances may hold the key even if this one did not
+        if let Some(bus) = self.inner.bus.get() {
+            bus.publish(key);
+        }
+        value
+    }
+
+    /// Removes without telling other caches on the invalidation bus.
+    fn remove_unpublished(&self, key: &K) -> Option<V> {
+        let _applying = self.begin();
+        let value = self.remove_entry(key);
+        if value.is_some() {
+            self.log(Record::Remove(key));
+            self.inner.statistics.add_removal();
+        }
+        value
+    }
+
+    fn remove_entry(&self, key: &K) -> Option<V> {
+        let value = self.inner.map.remove(key).map(|(_, v)| v);
+        if let Some(value) = &value {
+            if let Some(touched) = self.inner.touched.get() {
+                touched.forget(key);
+            }
+            self.inner.dirty.mark(key);
+            self.inner
+                .events
+                .send_with(|| CacheEvent::Removed(key.clone()));
+            if self.inner.listeners.is_active() {
+                self.inner
+                    .listeners
+                    .notify(key.clone(), value.clone(), RemovalCause::Explicit);
+            }
+            self.inner.generation.fetch_add(1, Ordering::Relaxed);
+        }
+        value
     }
 
     pub(crate) fn clear(&self) {
+        self.trace(TraceOp::Clear, None);
+        let _applying = self.begin();
+        self.log(Record::Clear);
+        self.clear_entries();
+    }
+
+    fn clear_entries(&self) {
+        let mut cleared = Vec::new();
+        if self.inner.listeners.is_active() {
+            let keys: Vec<K> = self.inner.map.iter().map(|e| e.key().clone()).collect();
+            cleared.extend(
+                keys.into_iter()
+                    .filter_map(|key| self.inner.map.remove(&key)),
+            );
+        }
         self.inner.map.clear();
+        if let Some(touched) = self.inner.touched.get() {
+            touched.forget_all();
+        }
+        self.inner.dirty.mark_cleared();
+        self.inner.events.send_with(|| CacheEvent::Cleared);
+        for (key, value) in cleared {
+            self.inner
+                .listeners
+                .notify(key, value, RemovalCause::Explicit);
+        }
+        self.inner.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns a counter that changes whenever the contents change.
+    pub(crate) fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_stats_recorder(&self, recorder: Arc<dyn StatsRecorder>) -> Result<()> {
+        self.inner.statistics.set_recorder(recorder)
+    }
+
+    /// Publishes removals to `bus` and applies the removals published on it.
+    pub(crate) fn join_invalidation(&self, bus: Arc<dyn InvalidationBus<K>>) -> Result<()> {
+        self.inner
+            .bus
+            .set(bus.clone())
+            .map_err(|_| anyhow!("An invalidation bus is already joined"))?;
+        // A weak reference, as the bus is owned by the cache
+        let cache = Arc::downgrade(&self.inner);
+        bus.subscribe(Box::new(move |key| {
+            if let Some(inner) = cache.upgrade() {
+                Unbounded { inner }.remove_unpublished(key);
+            }
+        }));
+        Ok(())
+    }
+
+    pub(crate) fn on_insert(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) -> HookId {
+        self.inner.insert_hooks.add(hook)
+    }
+
+    pub(crate) fn on_hit(&self, hook: impl Fn(&K, &V) + Send + Sync + 'static) {
+        self.inner.hit_hooks.add(hook);
+    }
+
+    pub(crate) fn on_removal(
+        &self,
+        listener: impl Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    ) -> HookId {
+        self.inner.listeners.add(listener)
+    }
+
+    /// Removes hooks added by [`Self::on_insert`] and [`Self::on_removal`].
+    pub(crate) fn remove_hooks(&self, insert_hook: HookId, listener: HookId) {
+        self.inner.insert_hooks.remove(insert_hook);
+        self.inner.listeners.remove(listener);
+    }
+
+    pub(crate) fn record_trace(&self, file_name: &str) -> Result<TraceHandle> {
+        if self.inner.trace.get().is_some() {
+            return Err(anyhow!("Accesses are already traced"));
+        }
+        let (tracer, handle) = TraceHandle::start(file_name)?;
+        self.inner
+            .trace
+            .set(tracer)
+            .map_err(|_| anyhow!("Accesses are already traced"))?;
+        Ok(handle)
+    }
+
+    fn trace(&self, op: TraceOp, key: Option<&K>) {
+        if let Some(tracer) = self.inner.trace.get() {
+            tracer.record(op, key);
+        }
+    }
+
+    pub(crate) fn enable_audit(&self, capacity: usize, file_name: Option<&str>) -> Result<()> {
+        if self.inner.audit.get().is_some() {
+            return Err(anyhow!("An audit log is already enabled"));
+        }
+        let audit = Arc::new(AuditLog::open(capacity, file_name)?);
+        self.inner
+            .audit
+            .set(audit.clone())
+            .map_err(|_| anyhow!("An audit log is already enabled"))?;
+        self.inner.events.set_tap(move |event| audit.record(event))
+    }
+
+    pub(crate) fn recent_mutations(&self, n: usize) -> Vec<Mutation<K>> {
+        match self.inner.audit.get() {
+            Some(audit) => audit.recent(n),
+            None => Vec::new(),
+        }
+    }
+
+    /// Starts recording when entries are used, for [`Unbounded::purge_idle`].
+    /// Entries already cached count as used now.
+    pub(crate) fn track_idle(&self) {
+        if self.inner.touched.get().is_some() {
+            return;
+        }
+        let touched = self.inner.touched.get_or_init(Touched::new);
+        for entry in self.inner.map.iter() {
+            touched.touch(entry.key());
+        }
+    }
+
+    /// Drops the entries neither inserted nor hit for `max_idle`, returning
+    /// how many were dropped.
+    pub(crate) fn purge_idle(&self, max_idle: Duration) -> usize {
+        let Some(touched) = self.inner.touched.get() else {
+            return 0;
+        };
+        let mut purged = 0;
+        for key in touched.idle(max_idle) {
+            // The entry may have been used since it was found idle
+            let removed = self
+                .inner
+                .map
+                .remove_if(&key, |key, _| touched.is_idle(key, max_idle));
+            if let Some((key, value)) = removed {
+                touched.forget(&key);
+                self.inner.statistics.add_removal();
+                self.inner.dirty.mark(&key);
+                self.inner
+                    .events
+                    .send_with(|| CacheEvent::Removed(key.clone()));
+                self.inner
+                    .listeners
+                    .notify(key, value, RemovalCause::Explicit);
+                self.inner.generation.fetch_add(1, Ordering::Relaxed);
+                purged += 1;
+            }
+        }
+        purged
+    }
+
+    pub(crate) fn evictions(&self) -> usize {
+        self.inner.statistics.evictions()
+    }
+
+    pub(crate) fn track_hot_keys(&self, tracked: usize) -> Result<()> {
+        self.inner
+            .hot
+            .set(KeyTraffic::new(tracked))
+            .map_err(|_| anyhow!("Hot keys are already tracked"))
+    }
+
+    pub(crate) fn hottest(&self, n: usize) -> Vec<(K, u64)> {
+        match self.inner.hot.get() {
+            Some(hot) => hot.lookups.hottest(n),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) fn estimated_frequency(&self, key: &K) -> u64 {
+        self.inner
+            .hot
+            .get()
+            .map_or(0, |hot| hot.lookups.estimate(key))
+    }
+
+    pub(crate) fn top_misses(&self, n: usize) -> Vec<(K, u64)> {
+        match self.inner.hot.get() {
+            Some(hot) => hot.misses.hottest(n),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<CacheEvent<K>> {
+        self.inner.events.subscribe()
+    }
+
+    pub(crate) fn subscribe_with(
+        &self,
+        capacity: usize,
+        overflow: Overflow,
+    ) -> Receiver<CacheEvent<K>> {
+        self.inner.events.subscribe_with(capacity, overflow)
     }
 
     pub(crate) fn len(&self) -> usize {
@@ -97,72 +434,237 @@ where
         self.inner.statistics.misses()
     }
 
-    pub(crate) fn write(&self, file_name: &str) -> Result<()> {
-        // Open a file in write mode
-        let file = File::create(file_name).map_err(|e| {
-            eprintln!("Failed to create file '{}': {}", file_name, e); // Add debug output
-            e
-        })?;
+    pub(crate) fn recent_hit_ratio(&self, window: Duration) -> f64 {
+        self.inner.statistics.recent_hit_ratio(window)
+    }
 
-        let mut writer = BufWriter::new(file);
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.inner.statistics.stats(self.len(), None)
+    }
 
-        // Collect all entries from the dashmap
-        let entries: Vec<(K, V)> = self
-            .inner
+    pub(crate) fn shard_stats(&self) -> Vec<ShardStats> {
+        let lens = shard::lens(&self.inner.map);
+        self.inner.statistics.shard_stats(lens.into_iter())
+    }
+
+    pub(crate) fn reset_stats(&self) {
+        self.inner.statistics.reset();
+    }
+
+    pub(crate) fn created(&self) -> SystemTime {
+        self.inner.statistics.created()
+    }
+
+    fn metadata(&self) -> SnapshotMetadata {
+        self.inner
+            .statistics
+            .metadata(CachePolicy::Unbounded, None, self.len())
+    }
+
+    pub(crate) fn export(&self) -> impl Iterator<Item = (K, V)> + '_ {
+        self.inner
             .map
             .iter()
             .map(|entry| (entry.key().clone(), entry.value().clone()))
-            .collect();
-
-        // Use bincode to serialize the entries
-        let encoded: Vec<u8> = bincode::serialize(&entries).map_err(|e| {
-            eprintln!("Serialization failed: {:?}", e); // Add debug output
-            e
-        })?;
-
-        // Write the encoded entries to the buffered writer
-        writer.write_all(&encoded).map_err(|e| {
-            eprintln!("Failed to write to file '{}': {}", file_name, e); // Add debug output
-            e
-        })?;
-
-        // Ensure all data is flushed to the file
-        writer.flush().map_err(|e| {
-            eprintln!("Failed to flush file '{}': {}", file_name, e); // Add debug output
-            e
-        })?;
+    }
+
+    pub(crate) fn find(&self, predicate: impl Fn(&K, &V) -> bool) -> Vec<K> {
+        let entries = self.inner.map.iter();
+        let found = entries.filter(|entry| predicate(entry.key(), entry.value()));
+        found.map(|entry| entry.key().clone()).collect()
+    }
+
+    pub(crate) fn import(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+
+    pub(crate) fn write(&self, file_name: &str) -> Result<()> {
+        // Stream the entries straight from the dashmap
+        persistence::write(file_name, Some(&self.metadata()), |writer| {
+            for (key, value) in self.export() {
+                writer.push(&key, &value)?;
+            }
+            Ok(())
+        })
+    }
 
+    pub(crate) fn write_sharded(&self, file_name: &str, shards: usize) -> Result<()> {
+        let metadata = self.metadata();
+        persistence::write_sharded(file_name, shards, Some(&metadata), |index, writer| {
+            for entry in self.inner.map.iter() {
+                if self.inner.map.hash_usize(entry.key()) % shards == index {
+                    writer.push(entry.key(), entry.value())?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    pub(crate) fn read_sharded(&self, file_name: &str, limits: &ReadLimits) -> Result<()> {
+        let shards = persistence::read_sharded::<K, V>(file_name, limits)?;
+        // Every shard carries the same metadata
+        if let Some(metadata) = shards.first().and_then(|shard| shard.metadata.as_ref()) {
+            self.inner.statistics.restore(metadata);
+        }
+        std::thread::scope(|scope| {
+            for shard in shards {
+                scope.spawn(move || {
+                    for (key, value) in shard.entries {
+                        self.insert_entry(key, value);
+                    }
+                });
+            }
+        });
         Ok(())
     }
 
-    pub(crate) fn read(&self, file_name: &str) -> Result<()> {
-        // Read the encoded entries from a file
-        let encoded = std::fs::read(file_name).map_err(|e| {
-            eprintln!("Failed to read file '{}': {}", file_name, e); // Add debug output
-            e
-        })?;
+    /// Inserts entries read from a snapshot, combining them with the
+    /// existing entries according to `mode`.
+    fn load(&self, mut entries: Vec<(K, V)>, mode: ReadMode) -> Result<()> {
+        match mode {
+            ReadMode::Merge => {}
+            ReadMode::Replace => self.clear_entries(),
+            ReadMode::KeepExisting => entries.retain(|(key, _)| !self.inner.map.contains_key(key)),
+            ReadMode::ErrorOnConflict => {
+                let conflicts = entries
+                    .iter()
+                    .filter(|(key, _)| self.inner.map.contains_key(key))
+                    .count();
+                if conflicts > 0 {
+                    return Err(PersistenceError::Conflict(conflicts).into());
+                }
+            }
+        }
+        for (key, value) in entries {
+            self.insert_entry(key, value);
+        }
+        Ok(())
+    }
 
-        // Check if the file was empty
-        if encoded.is_empty() {
-            eprintln!(
-                "File '{}' is empty or was not written correctly.",
-                file_name
-            );
-            return Err(anyhow::anyhow!("File is empty"));
+    pub(crate) fn read(&self, file_name: &str, mode: ReadMode, limits: &ReadLimits) -> Result<()> {
+        let snapshot = persistence::read_limited::<K, V>(file_name, limits)?;
+
+        // Insert the entries, then carry over the statistics
+        self.load(snapshot.entries, mode)?;
+        if let Some(metadata) = &snapshot.metadata {
+            self.inner.statistics.restore(metadata);
         }
+        Ok(())
+    }
 
-        // Use bincode to deserialize the entries
-        let entries: Vec<(K, V)> = bincode::deserialize(&encoded).map_err(|e| {
-            eprintln!("Deserialization failed: {:?}", e); // Add debug output
-            e
-        })?;
+    pub(crate) fn read_lossy(&self, file_name: &str) -> Result<LoadReport> {
+        let (snapshot, report) = persistence::read_lossy::<K, V>(file_name)?;
+        if let Some(metadata) = &snapshot.metadata {
+            self.inner.statistics.restore(metadata);
+        }
 
-        // Insert the entries into the dashmap
-        for (key, value) in entries {
-            self.inner.map.insert(key, value);
+        for (key, value) in snapshot.entries {
+            self.insert_entry(key, value);
+        }
+        Ok(report)
+    }
+
+    pub(crate) fn read_filtered(
+        &self,
+        file_name: &str,
+        keep: &mut dyn FnMut(&K) -> bool,
+        limit: usize,
+    ) -> Result<()> {
+        // The statistics describe the whole snapshot, so a partial load leaves them alone
+        let snapshot = persistence::read_filtered::<K, V>(file_name, keep, limit)?;
+
+        for (key, value) in snapshot.entries {
+            self.insert_entry(key, value);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_incremental(&self, file_name: &str) -> Result<()> {
+        dirty::write_incremental(
+            file_name,
+            &self.inner.dirty,
+            || self.write(file_name),
+            |key| self.inner.map.get(key).map(|value| value.clone()),
+        )
+    }
+
+    pub(crate) fn read_incremental(&self, file_name: &str) -> Result<()> {
+        // Decode both parts before touching the cache
+        let records = dirty::read_delta(file_name)?;
+        self.read(file_name, ReadMode::Merge, &ReadLimits::default())?;
+        for record in records {
+            self.apply(record);
         }
         Ok(())
     }
+
+    /// Replays the log at `file_name` into the cache and appends all further
+    /// mutations to it.
+    pub(crate) fn enable_wal(&self, file_name: &str) -> Result<()> {
+        let (wal, records) = Wal::open(file_name)?;
+        for record in records {
+            self.apply(record);
+        }
+        self.inner
+            .wal
+            .set(wal)
+            .map_err(|_| anyhow!("A write-ahead log is already enabled"))
+    }
+
+    pub(crate) fn sync_wal(&self) -> Result<()> {
+        match self.inner.wal.get() {
+            Some(wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Folds the log into a snapshot at `snapshot_file` and drops the
+    /// records it covers.
+    pub(crate) fn compact_wal(&self, snapshot_file: &str) -> Result<()> {
+        let wal = self
+            .inner
+            .wal
+            .get()
+            .ok_or_else(|| anyhow!("No write-ahead log is enabled"))?;
+        let checkpoint = wal.checkpoint()?;
+        self.write(snapshot_file)?;
+        wal.truncate_before(checkpoint)
+    }
+
+    pub(crate) fn wal_len(&self) -> Result<u64> {
+        match self.inner.wal.get() {
+            Some(wal) => wal.len(),
+            None => Ok(0),
+        }
+    }
+
+    /// Holds off log compaction while a mutation is logged and applied.
+    fn begin(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        self.inner.wal.get().map(Wal::begin)
+    }
+
+    /// Applies a logged mutation without logging it again.
+    fn apply(&self, record: Record<K, V>) {
+        match record {
+            Record::Insert(key, value) => {
+                self.insert_entry(key, value);
+            }
+            Record::Remove(key) => {
+                self.remove_entry(&key);
+            }
+            Record::Clear => self.clear_entries(),
+        }
+    }
+
+    fn log(&self, record: Record<&K, &V>) {
+        if let Some(wal) = self.inner.wal.get() {
+            if let Err(e) = wal.append(&record) {
+                report(e.context("Failed to append to write-ahead log"));
+            }
+        }
+    }
 }
 
 impl<K, V> Default for Unbounded<K, V>
@@ -175,10 +677,130 @@ where
     }
 }
 
-#[cfg(test)]
+impl<K, V> Serialize for Unbounded<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        CacheState {
+            policy: CachePolicy::Unbounded,
+            capacity: None,
+            entries: self.export().collect(),
+        }
+        .serialize(serializer)
+    }
+}
 
+impl<'de, K, V> Deserialize<'de> for Unbounded<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let state = CacheState::<K, V>::deserialize(deserializer)?;
+        if state.policy != CachePolicy::Unbounded {
+            return Err(de::Error::custom("not an unbounded cache"));
+        }
+        let cache = Unbounded::new();
+        cache.import(state.entries);
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
 mod tests {
-    use crate::Cache;
+    use crate::{Cache, PersistenceError, ReadMode};
+
+    #[test]
+    fn test_shard_stats() {
+        let cache = Cache::new_unbounded();
+        for i in 0..100 {
+            cache.insert(i, i);
+        }
+        for i in 50..120 {
+            cache.get(&i);
+        }
+
+        let shards = cache.shard_stats();
+        assert!(shards.len() > 1);
+        assert_eq!(shards.iter().map(|s| s.len).sum::<usize>(), 100);
+        assert_eq!(shards.iter().map(|s| s.hits).sum::<usize>(), 50);
+        assert_eq!(shards.iter().map(|s| s.misses).sum::<usize>(), 20);
+        cache.reset_stats();
+        assert!(cache.shard_stats().iter().all(|s| s.hits + s.misses == 0));
+    }
+
+    #[test]
+    fn test_hooks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let cache = Cache::new_unbounded();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counted = hits.clone();
+        cache.on_hit(move |_, value| {
+            counted.fetch_add(*value, Ordering::SeqCst);
+        });
+        // Primes a dependent cache with every insert
+        let doubled = Cache::new_unbounded();
+        let primed = doubled.clone();
+        cache.on_insert(move |key, value| primed.insert(*key, value * 2));
+
+        cache.insert(1, 10);
+        cache.get(&1);
+        cache.get(&2);
+        cache.peek(&1);
+        assert_eq!(hits.load(Ordering::SeqCst), 10);
+        assert_eq!(doubled.get(&1), Some(20));
+    }
+
+    #[test]
+    fn test_purge_idle() {
+        use std::time::Duration;
+
+        let cache = Cache::new_unbounded();
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        let handle = cache
+            .purge_idle_every(Duration::from_millis(50), Duration::from_secs(3600))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        cache.get(&2);
+        cache.insert(3, 3);
+        handle.flush().unwrap();
+
+        // Only the entry left alone was purged
+        assert_eq!(cache.peek(&1), None);
+        assert_eq!(cache.len(), 2);
+        // Unbounded caches never evict, purges are removals
+        assert_eq!(cache.evictions(), 0);
+        assert!(Cache::<i32, i32>::new_lru(1)
+            .purge_idle_every(Duration::from_secs(1), Duration::from_secs(1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_hottest() {
+        let cache = Cache::new_unbounded();
+        cache.insert(1, 1);
+        assert!(cache.hottest(1).is_empty());
+        cache.get(&1);
+        assert_eq!(cache.estimated_frequency(&1), 0);
+        cache.track_hot_keys(10).unwrap();
+        assert!(cache.track_hot_keys(10).is_err());
+
+        for _ in 0..3 {
+            cache.get(&1);
+        }
+        cache.get(&2);
+        cache.get(&2);
+        cache.get(&3);
+        assert_eq!(cache.hottest(10), vec![(1, 3), (2, 2), (3, 1)]);
+        assert_eq!(cache.top_misses(1), vec![(2, 2)]);
+        assert_eq!(cache.estimated_frequency(&2), 2);
+        assert_eq!(cache.estimated_frequency(&4), 0);
+    }
 
     #[test]
     fn test_insert_and_get() {
@@ -240,4 +862,146 @@ mod tests {
             assert_eq!(cache.get(&i), Some(i * 2));
         }
     }
+
+    #[test]
+    fn test_write_and_read_sharded() {
+        let path = std::env::temp_dir().join("minne_unbounded_sharded.cache");
+        let path = path.to_str().unwrap();
+
+        let cache = Cache::new_unbounded();
+        for i in 0..1000 {
+            cache.insert(i, i * 2);
+        }
+        cache.write_sharded(path, 4).unwrap();
+
+        let loaded = Cache::new_unbounded();
+        loaded.read_sharded(path).unwrap();
+        assert_eq!(loaded.len(), 1000);
+        assert_eq!(loaded.get(&500), Some(1000));
+
+        std::fs::remove_file(path).unwrap();
+        for i in 0..4 {
+            std::fs::remove_file(format!("{}.{}", path, i)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_filtered() {
+        let path = std::env::temp_dir().join("minne_unbounded_filtered.cache");
+        let path = path.to_str().unwrap();
+
+        let cache = Cache::new_unbounded();
+        for i in 0..3000 {
+            cache.insert(i, i * 2);
+        }
+        cache.write(path).unwrap();
+
+        let even: Cache<i32, i32> = Cache::new_unbounded();
+        even.read_filtered(path, |key| key % 2 == 0).unwrap();
+        assert_eq!(even.len(), 1500);
+        assert_eq!(even.get(&1), None);
+        assert_eq!(even.get(&2000), Some(4000));
+
+        let range: Cache<i32, i32> = Cache::new_unbounded();
+        range.read_range(path, 100..200, None).unwrap();
+        assert_eq!(range.len(), 100);
+        assert_eq!(range.get(&150), Some(300));
+
+        let limited: Cache<i32, i32> = Cache::new_unbounded();
+        limited.read_range(path, 1000.., Some(10)).unwrap();
+        assert_eq!(limited.len(), 10);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_modes() {
+        let path = std::env::temp_dir().join("minne_unbounded_read_modes.cache");
+        let path = path.to_str().unwrap();
+
+        let file = Cache::new_unbounded();
+        file.insert(1, "file".to_string());
+        file.insert(2, "file".to_string());
+        file.write(path).unwrap();
+
+        let warm = || {
+            let cache = Cache::new_unbounded();
+            cache.insert(2, "cache".to_string());
+            cache.insert(3, "cache".to_string());
+            cache
+        };
+
+        let cache = warm();
+        cache.read_with(path, ReadMode::Merge).unwrap();
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get(&2), Some("file".to_string()));
+
+        let cache = warm();
+        cache.read_with(path, ReadMode::Replace).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&3), None);
+
+        let cache = warm();
+        cache.read_with(path, ReadMode::KeepExisting).unwrap();
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get(&2), Some("cache".to_string()));
+
+        let cache = warm();
+        let err = cache
+            .read_with(path, ReadMode::ErrorOnConflict)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PersistenceError>(),
+            Some(&PersistenceError::Conflict(1))
+        );
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.hits(), 0);
+
+        let cache: Cache<i32, String> = Cache::new_unbounded();
+        cache.read_with(path, ReadMode::ErrorOnConflict).unwrap();
+        assert_eq!(cache.len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_incremental() {
+        let path = std::env::temp_dir().join("minne_unbounded_incremental.cache");
+        let path = path.to_str().unwrap();
+        let delta = format!("{}.delta", path);
+        let _ = std::fs::remove_file(&delta);
+
+        let cache = Cache::new_unbounded();
+        for i in 0..1000 {
+            cache.insert(i, i);
+        }
+        cache.write_incremental(path).unwrap();
+        assert!(!std::path::Path::new(&delta).exists());
+
+        cache.insert(1, 100);
+        cache.remove(&2);
+        cache.write_incremental(path).unwrap();
+        let base = std::fs::metadata(path).unwrap().len();
+        assert!(std::fs::metadata(&delta).unwrap().len() < base / 10);
+
+        let loaded: Cache<i32, i32> = Cache::new_unbounded();
+        loaded.read_incremental(path).unwrap();
+        assert_eq!(loaded.len(), 999);
+        assert_eq!(loaded.get(&1), Some(100));
+        assert_eq!(loaded.get(&2), None);
+
+        // Once the delta outgrows the snapshot it is folded into a new one
+        cache.clear();
+        for i in 0..2000 {
+            cache.insert(i, -i);
+        }
+        cache.write_incremental(path).unwrap();
+        cache.write_incremental(path).unwrap();
+        assert!(!std::path::Path::new(&delta).exists());
+
+        let loaded: Cache<i32, i32> = Cache::new_unbounded();
+        loaded.read_incremental(path).unwrap();
+        assert_eq!(loaded.len(), 2000);
+        assert_eq!(loaded.get(&1999), Some(-1999));
+        std::fs::remove_file(path).unwrap();
+    }
 }
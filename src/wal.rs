@@ -0,0 +1,263 @@
+//! Append-only write-ahead log of cache mutations.
+//!
+//! The log uses the snapshot header followed by one checksummed frame per
+//! mutation. Each record is written with a single `write` call, so after a
+//! crash at most the last record is torn; it is dropped on recovery.
+use crate::persistence::{self, FRAME_HEADER};
+use crate::report::report;
+use crate::sync::Recover;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock, RwLockReadGuard};
+
+/// A single logged mutation.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Record<K, V> {
+    Insert(K, V),
+    Remove(K),
+    Clear,
+}
+
+pub(crate) struct Wal<K, V> {
+    path: PathBuf,
+    file: Mutex<File>,
+    /// Held for reading from logging a mutation until it is applied
+    applying: RwLock<()>,
+    _marker: PhantomData<fn(K, V)>,
+}
+
+impl<K, V> Wal<K, V>
+where
+    K: Serialize + for<'a> Deserialize<'a>,
+    V: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Opens or creates the log at `file_name`, returning it together with
+    /// the records already in it.
+    pub(crate) fn open(file_name: &str) -> Result<(Self, Vec<Record<K, V>>)> {
+        let data = read_file(file_name)?;
+        let (records, valid_len) = decode_records(&data)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_name)?;
+        if data.is_empty() {
+            let mut header = Vec::new();
+            persistence::write_header::<K, V>(&mut header);
+            file.write_all(&header)?;
+        } else if valid_len < data.len() {
+            report(anyhow!(
+                "Dropping {} bytes of torn records from '{}'",
+                data.len() - valid_len,
+                file_name
+            ));
+            file.set_len(valid_len as u64)?;
+        }
+
+        let wal = Wal {
+            path: PathBuf::from(file_name),
+            file: Mutex::new(file),
+            applying: RwLock::new(()),
+            _marker: PhantomData,
+        };
+        Ok((wal, records))
+    }
+
+    /// Appends a record to the log.
+    pub(crate) fn append(&self, record: &Record<&K, &V>) -> Result<()> {
+        let mut frame = Vec::new();
+        persistence::write_frame(&mut frame, &bincode::serialize(record)?);
+        self.file.lock().recover().write_all(&frame)?;
+        Ok(())
+    }
+
+    /// Flushes the log to disk.
+    pub(crate) fn sync(&self) -> Result<()> {
+        self.file.lock().recover().sync_data()?;
+        Ok(())
+    }
+
+    /// Marks a mutation as in progress until the guard is dropped. Take it
+    /// before logging and hold it until the mutation is applied.
+    pub(crate) fn begin(&self) -> RwLockReadGuard<'_, ()> {
+        self.applying.read().recover()
+    }
+
+    /// Returns the current size of the log in bytes.
+    pub(crate) fn len(&self) -> Result<u64> {
+        Ok(self.file.lock().recover().metadata()?.len())
+    }
+
+    /// Returns the current end of the log once every mutation logged before
+    /// it has been applied, so a snapshot taken afterwards covers them all.
+    pub(crate) fn checkpoint(&self) -> Result<u64> {
+        let _applied = self.applying.write().recover();
+        self.len()
+    }
+
+    /// Drops the records before `checkpoint`, which a snapshot now covers.
+    ///
+    /// The shortened log is written next to the old one and renamed over it,
+    /// so a crash leaves one of the two intact.
+    pub(crate) fn truncate_before(&self, checkpoint: u64) -> Result<()> {
+        let mut file = self.file.lock().recover();
+        let data = fs::read(&self.path)?;
+
+        let mut compacted = Vec::new();
+        persistence::write_header::<K, V>(&mut compacted);
+        compacted.extend_from_slice(&data[checkpoint as usize..]);
+
+        let temp = persistence::temp_path(&self.path);
+        let mut out = File::create(&temp)?;
+        out.write_all(&compacted)?;
+        out.sync_all()?;
+        fs::rename(&temp, &self.path).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })?;
+
+        *file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Returns the records in the log at `file_name`, or none if it does not
+/// exist. A torn last record is ignored.
+pub(crate) fn read_records<K, V>(file_name: &str) -> Result<Vec<Record<K, V>>>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    Ok(decode_records(&read_file(file_name)?)?.0)
+}
+
+/// Appends `records` to the log at `file_name` with a single write and
+/// flushes them to disk, creating the log if needed.
+pub(crate) fn append_records<K, V>(file_name: &str, records: &[Record<&K, &V>]) -> Result<()>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_name)?;
+    let mut out = Vec::new();
+    if file.metadata()?.len() == 0 {
+        persistence::write_header::<K, V>(&mut out);
+    }
+    for record in records {
+        persistence::write_frame(&mut out, &bincode::serialize(record)?);
+    }
+    file.write_all(&out)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+fn read_file(file_name: &str) -> Result<Vec<u8>> {
+    match fs::read(file_name) {
+        Ok(data) => Ok(data),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Decodes the records in `data`, returning them with the length of the
+/// intact prefix.
+fn decode_records<K, V>(data: &[u8]) -> Result<(Vec<Record<K, V>>, usize)>
+where
+    K: for<'a> Deserialize<'a>,
+    V: for<'a> Deserialize<'a>,
+{
+    let mut records = Vec::new();
+    if data.is_empty() {
+        return Ok((records, 0));
+    }
+    let mut pos = persistence::check_header::<K, V>(data)?;
+    while pos < data.len() {
+        let Ok(payload) = persistence::read_frame(data, pos) else {
+            break;
+        };
+        let Ok(record) = bincode::deserialize(payload) else {
+            break;
+        };
+        records.push(record);
+        pos += FRAME_HEADER + payload.len();
+    }
+    Ok((records, pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Cache;
+    use std::io::Write;
+
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_replay() {
+        let path = path("minne_wal_replay.log");
+
+        let cache = Cache::new_unbounded();
+        cache.enable_wal(&path).unwrap();
+        cache.insert(1, "one".to_string());
+        cache.insert(2, "two".to_string());
+        cache.insert(3, "three".to_string());
+        cache.remove(&2);
+        drop(cache);
+
+        let recovered: Cache<i32, String> = Cache::new_unbounded();
+        recovered.enable_wal(&path).unwrap();
+        assert_eq!(recovered.len(), 2);
+        assert_eq!(recovered.get(&1), Some("one".to_string()));
+        assert_eq!(recovered.get(&2), None);
+
+        // New mutations are appended after the replayed ones
+        recovered.clear();
+        recovered.insert(4, "four".to_string());
+        drop(recovered);
+
+        let lru: Cache<i32, String> = Cache::new_lru(10);
+        lru.enable_wal(&path).unwrap();
+        assert_eq!(lru.len(), 1);
+        assert_eq!(lru.get(&4), Some("four".to_string()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_record_is_dropped() {
+        let path = path("minne_wal_torn.log");
+
+        let cache = Cache::new_lru(10);
+        cache.enable_wal(&path).unwrap();
+        cache.insert(1, 10);
+        cache.insert(2, 20);
+        drop(cache);
+
+        // Simulate a crash in the middle of appending a record
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let recovered: Cache<i32, i32> = Cache::new_lru(10);
+        recovered.enable_wal(&path).unwrap();
+        recovered.insert(3, 30);
+        drop(recovered);
+
+        let recovered: Cache<i32, i32> = Cache::new_lru(10);
+        recovered.enable_wal(&path).unwrap();
+        assert_eq!(recovered.len(), 3);
+        assert_eq!(recovered.get(&3), Some(30));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
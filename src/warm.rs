@@ -0,0 +1,168 @@
+//! Loading a list of keys into a cache before it serves traffic.
+use crate::report::report;
+use crate::sync::{catch, isolate, Recover};
+use crate::Cache;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::task::Poll;
+
+/// How far warming a cache has got, reported after each key and returned
+/// once all are done.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WarmProgress {
+    /// Keys to warm
+    pub total: usize,
+    /// Keys loaded, or found already cached
+    pub loaded: usize,
+    /// Keys whose load failed
+    pub failed: usize,
+}
+
+impl WarmProgress {
+    /// Returns how many keys are done, loaded or not.
+    pub fn done(&self) -> usize {
+        self.loaded + self.failed
+    }
+
+    /// Counts the outcome of one key, reporting a failure.
+    fn count<T>(&mut self, result: Result<T>) {
+        match result {
+            Ok(_) => self.loaded += 1,
+            Err(e) => {
+                report(e.context("Failed to warm entry"));
+                self.failed += 1;
+            }
+        }
+    }
+}
+
+/// Loads `keys` missing from `cache` on `concurrency` threads, see
+/// [`Cache::warm`].
+pub(crate) fn warm<K, V>(
+    cache: &Cache<K, V>,
+    keys: Vec<K>,
+    concurrency: usize,
+    load: impl Fn(&K) -> Result<V> + Sync,
+    progress: impl Fn(WarmProgress) + Sync,
+) -> WarmProgress
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    let next = AtomicUsize::new(0);
+    let report = Mutex::new(WarmProgress {
+        total: keys.len(),
+        ..Default::default()
+    });
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, keys.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(key) = keys.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = match cache.peek(key) {
+                        Some(_) => Ok(()),
+                        None => catch("Loader", || load(key))
+                            .and_then(|value| value)
+                            .map(|value| cache.insert(key.clone(), value)),
+                    };
+                    // Reporting under the lock keeps the reports in order
+                    let mut report = report.lock().recover();
+                    report.count(result);
+                    isolate("Progress", || progress(*report));
+                }
+            });
+        }
+    });
+    report.into_inner().recover()
+}
+
+/// Runs the futures `tasks` yields, at most `concurrency` at once, and
+/// counts their results into a report passed to `progress` after each.
+pub(crate) async fn warm_concurrently<T, F>(
+    tasks: impl ExactSizeIterator<Item = F>,
+    concurrency: usize,
+    progress: impl Fn(WarmProgress),
+) -> WarmProgress
+where
+    F: Future<Output = Result<T>>,
+{
+    let mut report = WarmProgress {
+        total: tasks.len(),
+        ..Default::default()
+    };
+    let mut tasks = tasks;
+    let mut running: Vec<Pin<Box<F>>> = Vec::new();
+    std::future::poll_fn(|cx| loop {
+        while running.len() < concurrency.max(1) {
+            match tasks.next() {
+                Some(task) => running.push(Box::pin(task)),
+                None => break,
+            }
+        }
+        if running.is_empty() {
+            return Poll::Ready(());
+        }
+        let mut finished = false;
+        let mut i = 0;
+        while i < running.len() {
+            match running[i].as_mut().poll(cx) {
+                Poll::Ready(result) => {
+                    running.swap_remove(i);
+                    report.count(result);
+                    isolate("Progress", || progress(report));
+                    finished = true;
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+        // Start more tasks in place of the finished ones, or wait
+        if !finished {
+            return Poll::Pending;
+        }
+    })
+    .await;
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WarmProgress;
+    use crate::Cache;
+    use anyhow::bail;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_warm() {
+        let cache = Cache::new_lru(100);
+        cache.insert(0, 0);
+        let reports = Mutex::new(Vec::new());
+        let report = cache.warm(
+            0..50,
+            4,
+            |key| match key % 10 {
+                0 => bail!("No value for {}", key),
+                9 => panic!("Nor for {}", key),
+                _ => Ok(key * 2),
+            },
+            |progress| reports.lock().unwrap().push(progress),
+        );
+        // Cached keys are not loaded again
+        let expected = WarmProgress {
+            total: 50,
+            loaded: 41,
+            failed: 9,
+        };
+        assert_eq!(report, expected);
+        assert_eq!(cache.len(), 41);
+        assert_eq!(cache.peek(&21), Some(42));
+
+        let reports = reports.into_inner().unwrap();
+        assert_eq!(reports.len(), 50);
+        assert!(reports.iter().enumerate().all(|(i, r)| r.done() == i + 1));
+        assert_eq!(reports.last(), Some(&expected));
+    }
+}
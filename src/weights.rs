@@ -0,0 +1,102 @@
+//! How the weights of the entries of a weighted cache are spread.
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Weights are counted in buckets of powers of two, after one for weight 0.
+const BUCKETS: usize = u32::BITS as usize + 1;
+
+/// The entries of a weighted cache in one range of weights, see
+/// [`crate::Cache::weight_histogram`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct WeightBucket {
+    /// The least weight in the range
+    pub min: u32,
+    /// The greatest weight in the range
+    pub max: u32,
+    /// Entries in memory whose weight is in the range
+    pub entries: usize,
+}
+
+/// Counts the entries in memory by weight, as they are weighed.
+pub(crate) struct WeightHistogram {
+    buckets: [AtomicUsize; BUCKETS],
+}
+
+impl WeightHistogram {
+    pub(crate) fn new() -> Self {
+        WeightHistogram {
+            buckets: std::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+
+    /// Bucket b holds the weights below 2^b that bucket b - 1 does not.
+    fn bucket(weight: u32) -> usize {
+        (u32::BITS - weight.leading_zeros()) as usize
+    }
+
+    pub(crate) fn add(&self, weight: u32) {
+        self.buckets[Self::bucket(weight)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn remove(&self, weight: u32) {
+        // A weigher that changes its mind must not wrap a bucket around
+        let _ = self.buckets[Self::bucket(weight)].fetch_update(
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+            |n| n.checked_sub(1),
+        );
+    }
+
+    pub(crate) fn clear(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the buckets holding entries, lightest first.
+    pub(crate) fn buckets(&self) -> Vec<WeightBucket> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(bucket, entries)| {
+                let entries = entries.load(Ordering::Relaxed);
+                let min = match bucket {
+                    0 => 0,
+                    _ => 1u32 << (bucket - 1),
+                };
+                let max = ((1u64 << bucket) - 1) as u32;
+                (entries > 0).then_some(WeightBucket { min, max, entries })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WeightBucket;
+    use crate::Cache;
+
+    #[test]
+    fn test_weight_histogram() {
+        let cache = Cache::new_weighted(usize::MAX, |_: &i32, value: &String| value.len() as u32);
+        cache.insert(1, String::new());
+        cache.insert(2, "a".repeat(5));
+        cache.insert(3, "a".repeat(7));
+        cache.insert(4, "a".repeat(1000));
+        let bucket = |min, max, entries| WeightBucket { min, max, entries };
+        assert_eq!(
+            cache.weight_histogram(),
+            [bucket(0, 0, 1), bucket(4, 7, 2), bucket(512, 1023, 1)]
+        );
+
+        // Replacing and removing move entries out of their buckets
+        cache.insert(4, "a".repeat(6));
+        cache.remove(&1);
+        assert_eq!(cache.weight_histogram(), [bucket(4, 7, 3)]);
+        cache.clear();
+        assert!(cache.weight_histogram().is_empty());
+        assert!(Cache::<i32, String>::new_lru(10)
+            .weight_histogram()
+            .is_empty());
+    }
+}
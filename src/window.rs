@@ -0,0 +1,135 @@
+//! Lookup counts over the recent past, for windowed hit ratios.
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// The span of time counted by each bucket.
+const BUCKET: Duration = Duration::from_secs(60);
+
+/// The number of buckets kept, and so the longest window in minutes.
+const BUCKETS: usize = 60;
+
+#[derive(Default)]
+struct Bucket {
+    /// The minute since `started` this bucket counts, plus one; zero when unused
+    minute: AtomicU64,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+/// Hits and misses per minute over the last hour.
+///
+/// A bucket is reused once its minute has passed, so a lookup racing with
+/// the reset may go uncounted; the counts are meant for monitoring only.
+pub(crate) struct RecentLookups {
+    started: Instant,
+    buckets: [Bucket; BUCKETS],
+}
+
+impl RecentLookups {
+    pub(crate) fn new() -> Self {
+        RecentLookups {
+            started: Instant::now(),
+            buckets: std::array::from_fn(|_| Bucket::default()),
+        }
+    }
+
+    /// Forgets the lookups counted so far.
+    pub(crate) fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.minute.store(0, Ordering::Release);
+        }
+    }
+
+    pub(crate) fn add(&self, hit: bool) {
+        self.add_at(Instant::now(), hit);
+    }
+
+    fn add_at(&self, now: Instant, hit: bool) {
+        let minute = self.minute(now);
+        let bucket = &self.buckets[minute as usize % BUCKETS];
+        let stamp = bucket.minute.load(Ordering::Acquire);
+        if stamp != minute + 1
+            && bucket
+                .minute
+                .compare_exchange(stamp, minute + 1, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            bucket.hits.store(0, Ordering::Relaxed);
+            bucket.misses.store(0, Ordering::Relaxed);
+        }
+        let counter = match hit {
+            true => &bucket.hits,
+            false => &bucket.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the hits and misses within `window`, counted in whole
+    /// minutes including the current one, and at most an hour.
+    pub(crate) fn within(&self, window: Duration) -> (usize, usize) {
+        self.within_at(Instant::now(), window)
+    }
+
+    fn within_at(&self, now: Instant, window: Duration) -> (usize, usize) {
+        let current = self.minute(now);
+        let minutes = window
+            .as_secs()
+            .div_ceil(BUCKET.as_secs())
+            .clamp(1, BUCKETS as u64);
+        let oldest = (current + 1).saturating_sub(minutes);
+        self.buckets
+            .iter()
+            .filter(|bucket| {
+                let stamp = bucket.minute.load(Ordering::Acquire);
+                stamp > oldest && stamp <= current + 1
+            })
+            .fold((0, 0), |(hits, misses), bucket| {
+                (
+                    hits + bucket.hits.load(Ordering::Relaxed),
+                    misses + bucket.misses.load(Ordering::Relaxed),
+                )
+            })
+    }
+
+    fn minute(&self, now: Instant) -> u64 {
+        now.duration_since(self.started).as_secs() / BUCKET.as_secs()
+    }
+}
+
+/// Returns the share of lookups that were hits, or 0 without lookups.
+pub(crate) fn ratio(hits: usize, misses: usize) -> f64 {
+    match hits + misses {
+        0 => 0.0,
+        total => hits as f64 / total as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ratio, RecentLookups, BUCKET};
+    use std::time::Duration;
+
+    #[test]
+    fn test_recent_lookups() {
+        let lookups = RecentLookups::new();
+        let start = lookups.started;
+        lookups.add_at(start, true);
+        lookups.add_at(start, false);
+        lookups.add_at(start + BUCKET * 30, true);
+        lookups.add_at(start + BUCKET * 30, true);
+        lookups.add_at(start + BUCKET * 30, false);
+
+        let now = start + BUCKET * 30;
+        assert_eq!(lookups.within_at(now, Duration::from_secs(1)), (2, 1));
+        assert_eq!(lookups.within_at(now, Duration::from_secs(3600)), (3, 2));
+
+        // An hour later the first bucket is reused
+        let later = start + BUCKET * 60;
+        lookups.add_at(later, false);
+        assert_eq!(lookups.within_at(later, Duration::from_secs(3600)), (2, 2));
+        assert_eq!(lookups.within_at(later, BUCKET * 30), (0, 1));
+
+        assert_eq!(ratio(0, 0), 0.0);
+        assert_eq!(ratio(3, 1), 0.75);
+    }
+}
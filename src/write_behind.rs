@@ -0,0 +1,261 @@
+//! Write-behind caching: changes reach the backing store in the background.
+use crate::report::report;
+use crate::sync::{catch, Recover};
+use crate::Cache;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Persists batches of changes to the store behind a [`WriteBehind`] cache.
+pub trait Writer<K, V>: Send + 'static {
+    /// Writes each entry, where `None` means the key was removed.
+    fn write(&mut self, batch: &[(K, Option<V>)]) -> Result<()>;
+}
+
+struct Queue<K, V> {
+    /// The latest change to each key not written yet
+    pending: Mutex<Pending<K, V>>,
+    wake: Condvar,
+}
+
+struct Pending<K, V> {
+    changes: HashMap<K, Option<V>>,
+    stopping: bool,
+}
+
+/// A cache whose inserts and removals return at once and are written to a
+/// [`Writer`] by a background thread.
+///
+/// Changes are written in batches, every `interval` or as soon as
+/// `max_batch` keys have changed. Several changes to one key between writes
+/// are coalesced into the last. A failed batch is retried with the next one,
+/// and dropping the cache writes what is still pending.
+pub struct WriteBehind<K, V, W>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    W: Writer<K, V>,
+{
+    cache: Cache<K, V>,
+    queue: Arc<Queue<K, V>>,
+    writer: Arc<Mutex<W>>,
+    max_batch: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<K, V, W> WriteBehind<K, V, W>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    W: Writer<K, V>,
+{
+    pub fn new(cache: Cache<K, V>, writer: W, interval: Duration, max_batch: usize) -> Self {
+        let queue = Arc::new(Queue {
+            pending: Mutex::new(Pending {
+                changes: HashMap::new(),
+                stopping: false,
+            }),
+            wake: Condvar::new(),
+        });
+        let writer = Arc::new(Mutex::new(writer));
+
+        let thread = {
+            let (queue, writer) = (queue.clone(), writer.clone());
+            // After a failure, wait out the interval even with a full batch
+            let mut failed = false;
+            thread::spawn(move || loop {
+                let pending = queue.pending.lock().recover();
+                let (pending, _) = queue
+                    .wake
+                    .wait_timeout_while(pending, interval, |p| {
+                        !p.stopping && (failed || p.changes.len() < max_batch)
+                    })
+                    .recover();
+                if pending.stopping {
+                    break;
+                }
+                drop(pending);
+                failed = match write_pending(&queue, &writer) {
+                    Ok(()) => false,
+                    Err(e) => {
+                        report(e.context("Failed to write behind"));
+                        true
+                    }
+                };
+            })
+        };
+
+        WriteBehind {
+            cache,
+            queue,
+            writer,
+            max_batch,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.cache.get(key)
+    }
+
+    /// Inserts into the cache and queues the write.
+    pub fn insert(&self, key: K, value: V) {
+        self.cache.insert(key.clone(), value.clone());
+        self.enqueue(key, Some(value));
+    }
+
+    /// Removes from the cache and queues the removal.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let value = self.cache.remove(key);
+        self.enqueue(key.clone(), None);
+        value
+    }
+
+    /// Writes the pending changes on the calling thread.
+    pub fn flush(&self) -> Result<()> {
+        write_pending(&self.queue, &self.writer)
+    }
+
+    /// Returns the number of changes not written yet.
+    pub fn pending(&self) -> usize {
+        self.queue.pending.lock().recover().changes.len()
+    }
+
+    pub fn cache(&self) -> &Cache<K, V> {
+        &self.cache
+    }
+
+    fn enqueue(&self, key: K, value: Option<V>) {
+        let mut pending = self.queue.pending.lock().recover();
+        pending.changes.insert(key, value);
+        if pending.changes.len() >= self.max_batch {
+            self.queue.wake.notify_one();
+        }
+    }
+}
+
+/// Takes the pending changes and writes them, putting them back on failure
+/// unless the key has changed again since.
+fn write_pending<K, V, W>(queue: &Queue<K, V>, writer: &Mutex<W>) -> Result<()>
+where
+    K: Eq + Hash,
+    W: Writer<K, V>,
+{
+    // Holding the writer keeps batches in order
+    let mut writer = writer.lock().recover();
+    let changes = std::mem::take(&mut queue.pending.lock().recover().changes);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let batch: Vec<(K, Option<V>)> = changes.into_iter().collect();
+    catch("Writer", || writer.write(&batch))
+        .and_then(|result| result)
+        .inspect_err(|_| {
+            let mut pending = queue.pending.lock().recover();
+            for (key, value) in batch {
+                pending.changes.entry(key).or_insert(value);
+            }
+        })
+}
+
+impl<K, V, W> Drop for WriteBehind<K, V, W>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    W: Writer<K, V>,
+{
+    fn drop(&mut self) {
+        self.queue.pending.lock().recover().stopping = true;
+        self.queue.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Err(e) = self.flush() {
+            report(e.context("Failed to write behind on shutdown"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WriteBehind, Writer};
+    use crate::Cache;
+    use anyhow::{bail, Result};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Stands in for a slow store, failing while `down` is set.
+    #[derive(Clone, Default)]
+    struct Store {
+        rows: Arc<Mutex<HashMap<i32, i32>>>,
+        down: Arc<Mutex<bool>>,
+    }
+
+    impl Writer<i32, i32> for Store {
+        fn write(&mut self, batch: &[(i32, Option<i32>)]) -> Result<()> {
+            if *self.down.lock().unwrap() {
+                bail!("Store is down");
+            }
+            let mut rows = self.rows.lock().unwrap();
+            for (key, value) in batch {
+                match value {
+                    Some(value) => rows.insert(*key, *value),
+                    None => rows.remove(key),
+                };
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_behind() {
+        let store = Store::default();
+        let cache = WriteBehind::new(
+            Cache::new_unbounded(),
+            store.clone(),
+            Duration::from_secs(60),
+            100,
+        );
+        cache.insert(1, 1);
+        cache.insert(1, 10);
+        cache.insert(2, 2);
+        cache.remove(&2);
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!(cache.pending(), 2);
+
+        *store.down.lock().unwrap() = true;
+        assert!(cache.flush().is_err());
+        assert_eq!(cache.pending(), 2);
+        *store.down.lock().unwrap() = false;
+
+        cache.insert(3, 3);
+        drop(cache);
+        let rows = store.rows.lock().unwrap();
+        assert_eq!(*rows, HashMap::from([(1, 10), (3, 3)]));
+    }
+
+    #[test]
+    fn test_full_batch_is_written_early() {
+        let store = Store::default();
+        let cache = WriteBehind::new(
+            Cache::new_unbounded(),
+            store.clone(),
+            Duration::from_secs(60),
+            10,
+        );
+        for i in 0..10 {
+            cache.insert(i, i);
+        }
+        let start = std::time::Instant::now();
+        while store.rows.lock().unwrap().len() < 10 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(cache.pending(), 0);
+    }
+}
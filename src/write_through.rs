@@ -0,0 +1,100 @@
+//! Write-through caching: changes reach the backing store before the cache.
+use crate::sync::Recover;
+use crate::Cache;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::sync::Mutex;
+
+/// The source of truth behind a [`WriteThrough`] cache.
+pub trait Store<K, V>: Send {
+    fn insert(&mut self, key: &K, value: &V) -> Result<()>;
+
+    fn remove(&mut self, key: &K) -> Result<()>;
+}
+
+/// A cache whose inserts and removals are applied to a [`Store`] first.
+///
+/// A change that the store rejects is not applied to the cache, and the
+/// error is returned. Writes hold a lock across both steps, so concurrent
+/// writes reach the store and the cache in the same order.
+pub struct WriteThrough<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+{
+    cache: Cache<K, V>,
+    store: Mutex<S>,
+}
+
+impl<K, V, S> WriteThrough<K, V, S>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    V: Clone + Send + Sync + 'static + Serialize + for<'a> Deserialize<'a>,
+    S: Store<K, V>,
+{
+    pub fn new(cache: Cache<K, V>, store: S) -> Self {
+        WriteThrough {
+            cache,
+            store: Mutex::new(store),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.cache.get(key)
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Result<()> {
+        let mut store = self.store.lock().recover();
+        store.insert(&key, &value)?;
+        self.cache.insert(key, value);
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &K) -> Result<Option<V>> {
+        let mut store = self.store.lock().recover();
+        store.remove(key)?;
+        Ok(self.cache.remove(key))
+    }
+
+    pub fn cache(&self) -> &Cache<K, V> {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Store, WriteThrough};
+    use crate::Cache;
+    use anyhow::{ensure, Result};
+    use std::collections::HashMap;
+
+    /// Stands in for a database that rejects negative values.
+    #[derive(Default)]
+    struct Table(HashMap<i32, i32>);
+
+    impl Store<i32, i32> for Table {
+        fn insert(&mut self, key: &i32, value: &i32) -> Result<()> {
+            ensure!(*value >= 0, "Negative value");
+            self.0.insert(*key, *value);
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &i32) -> Result<()> {
+            self.0.remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_through() {
+        let cache = WriteThrough::new(Cache::new_lru(10), Table::default());
+        cache.insert(1, 1).unwrap();
+        assert!(cache.insert(2, -2).is_err());
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.remove(&1).unwrap(), Some(1));
+
+        let table = cache.store.into_inner().unwrap();
+        assert!(table.0.is_empty());
+    }
+}